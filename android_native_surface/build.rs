@@ -2,13 +2,46 @@ use std::{env, fs::File, path::PathBuf};
 
 use gl_generator::{Api, Fallbacks, Profile, Registry};
 
+/// Extensions whose entry points and enums are generated on top of GLES 3.2
+const EXTENSIONS: [&str; 10] = [
+    "GL_EXT_color_buffer_float",
+    "GL_EXT_color_buffer_half_float",
+    "GL_EXT_disjoint_timer_query",
+    "GL_EXT_multisampled_render_to_texture",
+    "GL_EXT_sRGB_write_control",
+    "GL_EXT_texture_filter_anisotropic",
+    "GL_KHR_debug",
+    "GL_KHR_texture_compression_astc_ldr",
+    "GL_OES_EGL_image",
+    "GL_OES_EGL_image_external",
+];
+
 fn main() {
     let dest = PathBuf::from(&env::var("OUT_DIR").unwrap());
 
     println!("cargo:rerun-if-changed=build.rs");
+    // Binding class overrides, read with option_env!() in src/bindings.rs
+    for class in [
+        "INIT",
+        "NATIVE_GL",
+        "WINDOW_WRAPPER",
+        "SURFACE_WRAPPER",
+        "SURFACE_TEXTURE_WRAPPER",
+        "SCENE",
+    ] {
+        println!("cargo:rerun-if-env-changed=ANDROID_NATIVE_SURFACE_{class}_CLASS");
+    }
 
+    // Entry points beyond the version of the context, or of an extension that it does not
+    // advertise, must not be called.  See `support::capabilities::Feature` for the runtime checks.
     let mut file = File::create(dest.join("gl_bindings.rs")).unwrap();
-    Registry::new(Api::Gles2, (3, 3), Profile::Core, Fallbacks::All, [])
-        .write_bindings(gl_generator::StructGenerator, &mut file)
-        .unwrap();
+    Registry::new(
+        Api::Gles2,
+        (3, 2),
+        Profile::Core,
+        Fallbacks::All,
+        EXTENSIONS,
+    )
+    .write_bindings(gl_generator::StructGenerator, &mut file)
+    .unwrap();
 }
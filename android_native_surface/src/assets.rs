@@ -0,0 +1,281 @@
+//! Loading of shaders, textures and models by path, from the APK or from a directory.
+//!
+//! Everything that is not compiled in goes through the [`AssetLoader`] that Kotlin installs with
+//! `initAssets()`, which wraps its `android.content.res.AssetManager` in an [`ApkAssets`].  When
+//! the `android_native_surface.asset_dir` Java system property is set, a [`DirAssets`] reads from
+//! that directory instead, e.g. to iterate on shaders pushed with `adb` without rebuilding the APK.
+//!
+//! Every built-in shader can be overridden by putting its source at the path that [`shader()`] is
+//! called with, such as `shaders/scene.frag`.  The HUD draws with the `shaders/text.*` shaders.
+//!
+//! Paths are relative to the asset root and separated by `/`.  There is no model format yet, so
+//! models are read as raw bytes with [`AssetLoader::load()`].
+
+use std::{
+    borrow::Cow,
+    fmt, fs, io,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+#[cfg(target_os = "android")]
+use std::{ffi::CString, ptr::NonNull};
+
+use glutin::error::ErrorKind;
+#[cfg(target_os = "android")]
+use jni::{
+    objects::{GlobalRef, JObject},
+    JNIEnv,
+};
+use log::{debug, warn};
+#[cfg(target_os = "android")]
+use ndk::asset::AssetManager;
+
+use crate::support::texture::{self, Image, TextureError};
+
+#[derive(Debug)]
+pub enum AssetError {
+    /// `initAssets()` was not called yet
+    NoLoader,
+    Io {
+        path: String,
+        error: io::Error,
+    },
+    Texture {
+        path: String,
+        error: TextureError,
+    },
+    /// The context could not be made current to upload to
+    Egl(ErrorKind),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoLoader => f.write_str("No asset loader was installed with initAssets()"),
+            Self::Io { path, error } => write!(f, "Cannot read asset {path:?}: {error}"),
+            Self::Texture { path, error } => write!(f, "Cannot decode asset {path:?}: {error}"),
+            Self::Egl(kind) => write!(f, "Cannot make the GL context current: {kind:?}"),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+impl From<ErrorKind> for AssetError {
+    fn from(kind: ErrorKind) -> Self {
+        Self::Egl(kind)
+    }
+}
+
+pub type Result<T, E = AssetError> = std::result::Result<T, E>;
+
+/// Source of assets by path
+pub trait AssetLoader: fmt::Debug + Send + Sync {
+    /// Reads the whole asset at `path`, failing with [`io::ErrorKind::NotFound`] if it does not
+    /// exist.
+    fn load(&self, path: &str) -> io::Result<Vec<u8>>;
+}
+
+/// Assets packaged in the APK
+#[cfg(target_os = "android")]
+#[derive(Debug)]
+pub struct ApkAssets {
+    manager: AssetManager,
+    /// Keeps the Java `AssetManager`, and thereby `manager`, alive
+    _java: GlobalRef,
+}
+
+#[cfg(target_os = "android")]
+impl ApkAssets {
+    /// Wraps the `android.content.res.AssetManager` `asset_manager`.
+    pub fn from_java(
+        env: &mut JNIEnv<'_>,
+        asset_manager: &JObject<'_>,
+    ) -> jni::errors::Result<Self> {
+        if asset_manager.is_null() {
+            return Err(jni::errors::Error::NullPtr("asset_manager"));
+        }
+        let java = env.new_global_ref(asset_manager)?;
+        // SAFETY: `asset_manager` is a non-null AssetManager, whose native counterpart lives for
+        // as long as the global reference above.
+        let ptr = unsafe {
+            ndk_sys::AAssetManager_fromJava(env.get_raw().cast(), java.as_obj().as_raw().cast())
+        };
+        let ptr =
+            NonNull::new(ptr).ok_or(jni::errors::Error::NullPtr("AAssetManager_fromJava()"))?;
+        let manager = unsafe { AssetManager::from_ptr(ptr) };
+        Ok(Self {
+            manager,
+            _java: java,
+        })
+    }
+}
+
+#[cfg(target_os = "android")]
+impl AssetLoader for ApkAssets {
+    fn load(&self, path: &str) -> io::Result<Vec<u8>> {
+        let c_path =
+            CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut asset = self
+            .manager
+            .open(&c_path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Not in the APK"))?;
+        Ok(asset.buffer()?.to_vec())
+    }
+}
+
+/// Assets in a directory on the filesystem
+#[derive(Debug)]
+pub struct DirAssets {
+    root: PathBuf,
+}
+
+impl DirAssets {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl AssetLoader for DirAssets {
+    fn load(&self, path: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.root.join(path))
+    }
+}
+
+static LOADER: RwLock<Option<Arc<dyn AssetLoader>>> = RwLock::new(None);
+
+/// Replaces the loader that all assets are read through.
+pub fn install(loader: Arc<dyn AssetLoader>) {
+    debug!("Loading assets through {loader:?}");
+    *LOADER.write().unwrap() = Some(loader);
+}
+
+/// The installed loader, if any
+pub fn loader() -> Option<Arc<dyn AssetLoader>> {
+    LOADER.read().unwrap().clone()
+}
+
+/// Reads the asset at `path` through the installed loader.
+pub fn load(path: &str) -> Result<Vec<u8>> {
+    let loader = loader().ok_or(AssetError::NoLoader)?;
+    loader.load(path).map_err(|error| AssetError::Io {
+        path: path.to_owned(),
+        error,
+    })
+}
+
+/// Reads and decodes the PNG, JPEG or KTX2 image at `path`.
+pub fn load_image(path: &str) -> Result<Image> {
+    texture::decode(&load(path)?).map_err(|error| AssetError::Texture {
+        path: path.to_owned(),
+        error,
+    })
+}
+
+/// Reads the GLSL source at `path`, NUL-terminated for [`crate::support::create_program()`].  Falls
+/// back to the compiled-in `builtin` source, which must be NUL-terminated too, when there is no
+/// loader or no such asset.
+pub fn shader(path: &str, builtin: &'static [u8]) -> Cow<'static, [u8]> {
+    match load(path) {
+        Ok(mut source) => {
+            debug!("Loaded shader {path:?}");
+            source.push(0);
+            Cow::Owned(source)
+        }
+        Err(AssetError::NoLoader) => Cow::Borrowed(builtin),
+        Err(AssetError::Io { error, .. }) if error.kind() == io::ErrorKind::NotFound => {
+            Cow::Borrowed(builtin)
+        }
+        Err(e) => {
+            warn!("{e}, using the built-in shader");
+            Cow::Borrowed(builtin)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        sync::{Mutex, MutexGuard},
+    };
+
+    use super::*;
+
+    /// Directory under [`std::env::temp_dir()`] that is removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "android_native_surface-{name}-{}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("shaders")).unwrap();
+            Self(dir)
+        }
+
+        fn write(&self, path: &str, contents: &[u8]) {
+            fs::write(self.0.join(path), contents).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Installs a [`DirAssets`] of `dir` as the loader.  The tests hold the returned guard, as
+    /// there is only one loader per process.
+    fn install_dir(dir: &Path) -> MutexGuard<'static, ()> {
+        static LOCK: Mutex<()> = Mutex::new(());
+        let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        install(Arc::new(DirAssets::new(dir)));
+        guard
+    }
+
+    #[test]
+    fn dir_assets_load_by_relative_path() {
+        let dir = TempDir::new("load");
+        dir.write("shaders/a.vert", b"void main() {}");
+        let _loader = install_dir(&dir.0);
+
+        assert_eq!(load("shaders/a.vert").unwrap(), b"void main() {}");
+        let Err(AssetError::Io { path, error }) = load("shaders/missing.vert") else {
+            panic!("Loaded a missing asset");
+        };
+        assert_eq!(path, "shaders/missing.vert");
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn shaders_fall_back_to_the_builtin_source() {
+        let dir = TempDir::new("shader");
+        dir.write("shaders/override.frag", b"void main() {}");
+        let _loader = install_dir(&dir.0);
+
+        // Loaded sources are NUL-terminated like the built-in ones
+        let source = shader("shaders/override.frag", b"builtin\0");
+        assert_eq!(&*source, b"void main() {}\0");
+        assert!(matches!(source, Cow::Owned(_)));
+        let source = shader("shaders/missing.frag", b"builtin\0");
+        assert!(matches!(source, Cow::Borrowed(b"builtin\0")));
+    }
+
+    #[test]
+    fn images_are_decoded() {
+        let dir = TempDir::new("image");
+        dir.write("rgba.png", include_bytes!("../tests/fixtures/rgba.png"));
+        dir.write("garbage.png", b"not an image");
+        let _loader = install_dir(&dir.0);
+
+        let image = load_image("rgba.png").unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert!(matches!(
+            load_image("garbage.png"),
+            Err(AssetError::Texture { path, .. }) if path == "garbage.png"
+        ));
+    }
+}
@@ -0,0 +1,304 @@
+//! Registration of the native methods with `RegisterNatives()` from [`JNI_OnLoad()`].
+//!
+//! This lets the library be embedded under any Java package, instead of being tied to the mangled
+//! `Java_rust_androidnativesurface_MainActivity_*` export names.  The binding class of every
+//! [`Binding`] is looked up from, in order of precedence:
+//!
+//! 1. The Java system property named by [`Binding::property`], which can be set with
+//!    `System.setProperty()` before calling `System.loadLibrary()`;
+//! 2. The environment variable of the same name (upper-cased, `.` replaced by `_`) at build time;
+//! 3. The `MainActivity` classes of the bundled app.
+//!
+//! Every native method, and every method that Rust calls back into, must exist on these classes
+//! with the exact signature listed here, otherwise loading the library fails.  Natives that apply
+//! to any window are registered once on the common base class of both surface wrappers.
+
+use std::ffi::c_void;
+
+use jni::{
+    objects::JString,
+    sys::{jint, JNI_ERR, JNI_VERSION_1_6},
+    JNIEnv, JavaVM, NativeMethod,
+};
+use log::{debug, error};
+
+struct Binding {
+    /// Name of the system property that overrides [`Binding::class`]
+    property: &'static str,
+    /// Build-time class name, in JNI `FindClass()` notation
+    class: &'static str,
+    /// Implemented in Rust: `(name, signature, function)`
+    natives: Vec<(&'static str, &'static str, *mut c_void)>,
+    /// Implemented in Java and called from Rust: `(name, signature)`
+    callbacks: &'static [(&'static str, &'static str)],
+}
+
+macro_rules! class_name {
+    ($env:literal, $default:literal) => {
+        match option_env!($env) {
+            Some(class) => class,
+            None => $default,
+        }
+    };
+}
+
+fn bindings() -> [Binding; 6] {
+    [
+        Binding {
+            property: "android_native_surface.init_class",
+            class: class_name!(
+                "ANDROID_NATIVE_SURFACE_INIT_CLASS",
+                "rust/androidnativesurface/MainActivity$Companion"
+            ),
+            natives: vec![
+                ("init", "()V", crate::init as _),
+                (
+                    "initAssets",
+                    "(Landroid/content/res/AssetManager;)V",
+                    crate::init_assets as _,
+                ),
+                ("stopStdioCapture", "()V", crate::stop_stdio_capture as _),
+                (
+                    "setLogFilter",
+                    "(Ljava/lang/String;)V",
+                    crate::set_log_filter as _,
+                ),
+            ],
+            callbacks: &[],
+        },
+        Binding {
+            property: "android_native_surface.native_gl_class",
+            class: class_name!(
+                "ANDROID_NATIVE_SURFACE_NATIVE_GL_CLASS",
+                "rust/androidnativesurface/MainActivity$NativeGL"
+            ),
+            natives: vec![
+                ("init", "(Z)J", crate::native_gl_init as _),
+                ("destroy", "(J)Z", crate::native_gl_destroy as _),
+                ("injectFault", "(JI)V", crate::native_gl_inject_fault as _),
+                (
+                    "uploadBitmap",
+                    "(JLandroid/graphics/Bitmap;)J",
+                    crate::native_gl_upload_bitmap as _,
+                ),
+                (
+                    "loadTexture",
+                    "(JLjava/lang/String;)J",
+                    crate::native_gl_load_texture as _,
+                ),
+                (
+                    "deleteTexture",
+                    "(J)V",
+                    crate::native_gl_delete_texture as _,
+                ),
+                (
+                    "getCapabilities",
+                    "(J)Ljava/util/Map;",
+                    crate::native_gl_get_capabilities as _,
+                ),
+                (
+                    "renderToBitmap",
+                    "(JLandroid/graphics/Bitmap;)V",
+                    crate::native_gl_render_to_bitmap as _,
+                ),
+                ("verifySrgb", "(J)V", crate::native_gl_verify_srgb as _),
+            ],
+            callbacks: &[],
+        },
+        Binding {
+            property: "android_native_surface.window_wrapper_class",
+            class: class_name!(
+                "ANDROID_NATIVE_SURFACE_WINDOW_WRAPPER_CLASS",
+                "rust/androidnativesurface/MainActivity$NativeWindowWrapper"
+            ),
+            natives: vec![
+                ("remove", "(J)V", crate::window_wrapper_remove as _),
+                ("render", "(JJ)V", crate::window_wrapper_render as _),
+                (
+                    "setFrameRate",
+                    "(JFII)V",
+                    crate::window_wrapper_set_frame_rate as _,
+                ),
+                (
+                    "setHudEnabled",
+                    "(JZ)V",
+                    crate::window_wrapper_set_hud_enabled as _,
+                ),
+                (
+                    "setMsaaSamples",
+                    "(JI)V",
+                    crate::window_wrapper_set_msaa_samples as _,
+                ),
+                ("setSrgb", "(JJZ)V", crate::window_wrapper_set_srgb as _),
+                (
+                    "setPostPasses",
+                    "(J[I[F[J)V",
+                    crate::window_wrapper_set_post_passes as _,
+                ),
+                ("setScene", "(JJ)V", crate::window_wrapper_set_scene as _),
+                (
+                    "onMotionEvent",
+                    "(JLandroid/view/MotionEvent;II)Z",
+                    crate::window_wrapper_on_motion_event as _,
+                ),
+            ],
+            callbacks: &[("onRecovery", "(I)V")],
+        },
+        Binding {
+            property: "android_native_surface.surface_wrapper_class",
+            class: class_name!(
+                "ANDROID_NATIVE_SURFACE_SURFACE_WRAPPER_CLASS",
+                "rust/androidnativesurface/MainActivity$NativeSurfaceWrapper"
+            ),
+            natives: vec![(
+                "setSurface",
+                "(JLandroid/view/Surface;I)J",
+                crate::surface_wrapper_set_surface as _,
+            )],
+            callbacks: &[],
+        },
+        Binding {
+            property: "android_native_surface.surface_texture_wrapper_class",
+            class: class_name!(
+                "ANDROID_NATIVE_SURFACE_SURFACE_TEXTURE_WRAPPER_CLASS",
+                "rust/androidnativesurface/MainActivity$NativeSurfaceTextureWrapper"
+            ),
+            natives: vec![(
+                "setSurfaceTexture",
+                "(JLandroid/graphics/SurfaceTexture;I)J",
+                crate::surface_texture_wrapper_set_surface_texture as _,
+            )],
+            callbacks: &[],
+        },
+        Binding {
+            property: "android_native_surface.scene_class",
+            class: class_name!(
+                "ANDROID_NATIVE_SURFACE_SCENE_CLASS",
+                "rust/androidnativesurface/MainActivity$Scene"
+            ),
+            natives: vec![
+                ("create", "()J", crate::scene_create as _),
+                ("destroy", "(J)V", crate::scene_destroy as _),
+                ("addRect", "(JFFFFII)J", crate::scene_add_rect as _),
+                ("addSprite", "(JJFFFFII)J", crate::scene_add_sprite as _),
+                (
+                    "setTransform",
+                    "(JJFFFFFF)V",
+                    crate::scene_set_transform as _,
+                ),
+                ("setClip", "(JJFFFF)V", crate::scene_set_clip as _),
+                ("clearClip", "(JJ)V", crate::scene_clear_clip as _),
+                ("remove", "(JJ)V", crate::scene_remove as _),
+                (
+                    "setShowPointers",
+                    "(JZ)V",
+                    crate::scene_set_show_pointers as _,
+                ),
+            ],
+            callbacks: &[],
+        },
+    ]
+}
+
+/// Reads `System.getProperty(key)`
+pub fn system_property(env: &mut JNIEnv, key: &str) -> jni::errors::Result<Option<String>> {
+    let key = env.new_string(key)?;
+    let value = env
+        .call_static_method(
+            "java/lang/System",
+            "getProperty",
+            "(Ljava/lang/String;)Ljava/lang/String;",
+            &[(&key).into()],
+        )?
+        .l()?;
+    if value.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(env.get_string(&JString::from(value))?.into()))
+}
+
+/// Turns a failed JNI call into a readable error, clearing the Java exception that it raised.
+fn describe(env: &mut JNIEnv, what: String, e: jni::errors::Error) -> String {
+    let exception = env.exception_occurred().ok().filter(|e| !e.is_null());
+    env.exception_clear().unwrap();
+    let message = exception.and_then(|exception| {
+        let message = env
+            .call_method(&exception, "toString", "()Ljava/lang/String;", &[])
+            .and_then(|m| m.l())
+            .ok()?;
+        Some(String::from(env.get_string(&JString::from(message)).ok()?))
+    });
+    match message {
+        Some(message) => format!("{what}: {message}"),
+        None => format!("{what}: {e}"),
+    }
+}
+
+fn register_natives(env: &mut JNIEnv) -> Result<(), String> {
+    for binding in bindings() {
+        let class_name = match system_property(env, binding.property) {
+            Ok(Some(class_name)) => class_name,
+            Ok(None) => binding.class.to_owned(),
+            Err(e) => return Err(describe(env, format!("Reading {}", binding.property), e)),
+        };
+
+        let class = env.find_class(&class_name).map_err(|e| {
+            describe(
+                env,
+                format!("Binding class `{class_name}` (from `{}`)", binding.property),
+                e,
+            )
+        })?;
+
+        // One at a time, so that a mismatch points at the exact method
+        for &(name, sig, fn_ptr) in &binding.natives {
+            let method = NativeMethod {
+                name: name.into(),
+                sig: sig.into(),
+                fn_ptr,
+            };
+            env.register_native_methods(&class, &[method])
+                .map_err(|e| {
+                    describe(
+                        env,
+                        format!("Native method `{class_name}.{name}{sig}` does not match"),
+                        e,
+                    )
+                })?;
+        }
+
+        for &(name, sig) in binding.callbacks {
+            env.get_method_id(&class, name, sig).map_err(|e| {
+                describe(
+                    env,
+                    format!("Callback method `{class_name}.{name}{sig}` does not exist"),
+                    e,
+                )
+            })?;
+        }
+
+        debug!(
+            "Registered {} native methods on {class_name}",
+            binding.natives.len()
+        );
+    }
+
+    Ok(())
+}
+
+#[no_mangle]
+pub extern "system" fn JNI_OnLoad(vm: *mut jni::sys::JavaVM, _reserved: *mut c_void) -> jint {
+    let vm = unsafe { JavaVM::from_raw(vm) }.unwrap();
+    let mut env = vm.get_env().unwrap();
+    // Log registration failures, which would otherwise only surface as a generic
+    // UnsatisfiedLinkError from System.loadLibrary()
+    crate::logger::init(&mut env);
+
+    match register_natives(&mut env) {
+        Ok(()) => JNI_VERSION_1_6,
+        Err(e) => {
+            error!("Failed to register native methods: {e}");
+            JNI_ERR
+        }
+    }
+}
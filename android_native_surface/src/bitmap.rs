@@ -0,0 +1,273 @@
+//! Interop with `android.graphics.Bitmap` through the `AndroidBitmap_*()` API.
+//!
+//! Pixels are converted between the `RGBA_8888`, `RGB_565` and `RGBA_F16` configs of a bitmap,
+//! and the [`Image`]s that textures are uploaded from or the RGBA8 pixels that are read back from
+//! a framebuffer.  GL works with straight alpha whereas bitmaps are usually premultiplied, which
+//! is undone when reading and redone when writing, by the conversions in [`crate::pixel`].
+
+use std::{fmt, slice};
+
+use glutin::error::ErrorKind;
+use jni::{objects::JObject, JNIEnv};
+use log::warn;
+use ndk::bitmap::{Bitmap, BitmapFormat, BitmapInfo};
+
+use crate::{
+    pixel,
+    support::texture::{Image, TextureError, TextureFormat},
+};
+
+#[derive(Debug)]
+pub enum BitmapError {
+    Bitmap(ndk::bitmap::BitmapError),
+    Jni(jni::errors::Error),
+    UnsupportedFormat(BitmapFormat),
+    /// Only mutable bitmaps can be rendered to
+    Immutable,
+    /// The bitmap is larger than `GL_MAX_RENDERBUFFER_SIZE` in either dimension
+    TooLarge {
+        max_size: u32,
+    },
+    /// The offscreen framebuffer to render the bitmap with is not `GL_FRAMEBUFFER_COMPLETE`
+    IncompleteFramebuffer {
+        width: i32,
+        height: i32,
+        status: u32,
+    },
+    /// The pixels to write do not have the size of the bitmap
+    SizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    /// The context could not be made current to upload to or render from
+    Egl(ErrorKind),
+    Texture(TextureError),
+}
+
+impl fmt::Display for BitmapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bitmap(e) => write!(f, "AndroidBitmap call failed: {e}"),
+            Self::Jni(e) => write!(f, "JNI call failed: {e}"),
+            Self::UnsupportedFormat(format) => write!(
+                f,
+                "Unsupported bitmap format {format:?}, expected RGBA_8888, RGB_565 or RGBA_F16"
+            ),
+            Self::Immutable => f.write_str("Bitmap is immutable"),
+            Self::TooLarge { max_size } => {
+                write!(f, "Bitmap exceeds GL_MAX_RENDERBUFFER_SIZE of {max_size}")
+            }
+            Self::IncompleteFramebuffer {
+                width,
+                height,
+                status,
+            } => write!(
+                f,
+                "Cannot render to a {width}x{height} framebuffer, status {status:#06x}"
+            ),
+            Self::SizeMismatch { expected, actual } => write!(
+                f,
+                "Expected {expected} bytes of pixels for the bitmap, got {actual}"
+            ),
+            Self::Egl(kind) => write!(f, "Cannot make the GL context current: {kind:?}"),
+            Self::Texture(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for BitmapError {}
+
+impl From<ndk::bitmap::BitmapError> for BitmapError {
+    fn from(e: ndk::bitmap::BitmapError) -> Self {
+        Self::Bitmap(e)
+    }
+}
+
+impl From<jni::errors::Error> for BitmapError {
+    fn from(e: jni::errors::Error) -> Self {
+        Self::Jni(e)
+    }
+}
+
+impl From<ErrorKind> for BitmapError {
+    fn from(kind: ErrorKind) -> Self {
+        Self::Egl(kind)
+    }
+}
+
+impl From<TextureError> for BitmapError {
+    fn from(e: TextureError) -> Self {
+        Self::Texture(e)
+    }
+}
+
+pub type Result<T, E = BitmapError> = std::result::Result<T, E>;
+
+/// Wraps `bitmap`, after checking that it is not `null`.
+fn wrap(env: &JNIEnv<'_>, bitmap: &JObject<'_>) -> Result<Bitmap> {
+    if bitmap.is_null() {
+        return Err(jni::errors::Error::NullPtr("bitmap").into());
+    }
+    // SAFETY: The JNI signature guarantees that this is an android.graphics.Bitmap, which Java
+    // keeps alive for the duration of the native call.
+    Ok(unsafe { Bitmap::from_jni(env.get_raw(), bitmap.as_raw()) })
+}
+
+/// Size of `bitmap` in pixels
+pub fn size(env: &JNIEnv<'_>, bitmap: &JObject<'_>) -> Result<(u32, u32)> {
+    let info = wrap(env, bitmap)?.info()?;
+    Ok((info.width(), info.height()))
+}
+
+/// Pixels of a bitmap, that are unlocked again on [`Drop`]
+struct LockedBitmap {
+    bitmap: Bitmap,
+    info: BitmapInfo,
+    premultiplied: bool,
+    pixels: *mut u8,
+}
+
+impl LockedBitmap {
+    fn lock(env: &mut JNIEnv<'_>, bitmap: &JObject<'_>) -> Result<Self> {
+        let wrapped = wrap(env, bitmap)?;
+        // AndroidBitmapInfo::flags only reports this from API level 30
+        let premultiplied = env
+            .call_method(bitmap, "isPremultiplied", "()Z", &[])?
+            .z()?;
+        let info = wrapped.info()?;
+        let pixels = wrapped.lock_pixels()?.cast();
+        Ok(Self {
+            bitmap: wrapped,
+            info,
+            premultiplied,
+            pixels,
+        })
+    }
+
+    /// The first `len` bytes of row `y`, which must not exceed the stride.
+    fn row(&self, y: u32, len: usize) -> &[u8] {
+        assert!(y < self.info.height() && len <= self.info.stride() as usize);
+        unsafe {
+            slice::from_raw_parts(
+                self.pixels.add(y as usize * self.info.stride() as usize),
+                len,
+            )
+        }
+    }
+
+    fn row_mut(&mut self, y: u32, len: usize) -> &mut [u8] {
+        assert!(y < self.info.height() && len <= self.info.stride() as usize);
+        unsafe {
+            slice::from_raw_parts_mut(
+                self.pixels.add(y as usize * self.info.stride() as usize),
+                len,
+            )
+        }
+    }
+}
+
+impl Drop for LockedBitmap {
+    fn drop(&mut self) {
+        if let Err(e) = self.bitmap.unlock_pixels() {
+            warn!("Cannot unlock bitmap pixels: {e}");
+        }
+    }
+}
+
+/// Copies the pixels of `bitmap` into an [`Image`] with straight alpha.
+///
+/// `RGBA_8888` is treated as sRGB and `RGBA_F16` as linear, which are the defaults for bitmaps
+/// that were not created with an explicit `ColorSpace`.
+pub fn read_image(env: &mut JNIEnv<'_>, bitmap: &JObject<'_>) -> Result<Image> {
+    let locked = LockedBitmap::lock(env, bitmap)?;
+    let (width, height) = (locked.info.width(), locked.info.height());
+    let (format, srgb, bytes_per_pixel) = match locked.info.format() {
+        BitmapFormat::RGBA_8888 => (TextureFormat::Rgba8, true, 4),
+        BitmapFormat::RGB_565 => (TextureFormat::Rgb565, false, 2),
+        BitmapFormat::RGBA_F16 => (TextureFormat::Rgba16F, false, 8),
+        format => return Err(BitmapError::UnsupportedFormat(format)),
+    };
+
+    let row_len = width as usize * bytes_per_pixel;
+    let mut data = Vec::with_capacity(row_len * height as usize);
+    for y in 0..height {
+        data.extend_from_slice(locked.row(y, row_len));
+    }
+
+    // RGB_565 is always opaque
+    if locked.premultiplied {
+        match format {
+            TextureFormat::Rgba8 => data
+                .chunks_exact_mut(4)
+                .for_each(pixel::unpremultiply_rgba8),
+            TextureFormat::Rgba16F => data
+                .chunks_exact_mut(8)
+                .for_each(pixel::unpremultiply_rgba16f),
+            _ => {}
+        }
+    }
+
+    Ok(Image {
+        width,
+        height,
+        format,
+        srgb,
+        levels: vec![data],
+    })
+}
+
+/// Copies `pixels` into `bitmap`, converting them to its config.
+///
+/// `pixels` must be tightly packed RGBA8 with straight alpha and the size of the bitmap, with the
+/// bottom row first as returned by `glReadPixels()`.
+pub fn write_rgba8(env: &mut JNIEnv<'_>, bitmap: &JObject<'_>, pixels: &[u8]) -> Result<()> {
+    if !env.call_method(bitmap, "isMutable", "()Z", &[])?.z()? {
+        return Err(BitmapError::Immutable);
+    }
+
+    let mut locked = LockedBitmap::lock(env, bitmap)?;
+    let format = locked.info.format();
+    if !matches!(
+        format,
+        BitmapFormat::RGBA_8888 | BitmapFormat::RGB_565 | BitmapFormat::RGBA_F16
+    ) {
+        return Err(BitmapError::UnsupportedFormat(format));
+    }
+
+    let width = locked.info.width() as usize;
+    let height = locked.info.height() as usize;
+    if pixels.len() != width * height * 4 {
+        return Err(BitmapError::SizeMismatch {
+            expected: width * height * 4,
+            actual: pixels.len(),
+        });
+    }
+    let premultiplied = locked.premultiplied;
+
+    for (src, y) in pixels.chunks_exact(width * 4).rev().zip(0..) {
+        match format {
+            BitmapFormat::RGBA_8888 => {
+                let dst = locked.row_mut(y, width * 4);
+                dst.copy_from_slice(src);
+                if premultiplied {
+                    dst.chunks_exact_mut(4).for_each(pixel::premultiply_rgba8);
+                }
+            }
+            BitmapFormat::RGB_565 => {
+                let dst = locked.row_mut(y, width * 2);
+                for (dst, src) in dst.chunks_exact_mut(2).zip(src.chunks_exact(4)) {
+                    dst.copy_from_slice(&pixel::rgba8_to_rgb565(src).to_ne_bytes());
+                }
+            }
+            BitmapFormat::RGBA_F16 => {
+                let dst = locked.row_mut(y, width * 8);
+                for (dst, src) in dst.chunks_exact_mut(8).zip(src.chunks_exact(4)) {
+                    pixel::rgba8_to_rgba16f(src, dst, premultiplied);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(())
+}
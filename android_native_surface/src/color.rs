@@ -0,0 +1,42 @@
+//! sRGB transfer function, for windows whose surface encodes to sRGB on write.
+//!
+//! Colors from Kotlin, like `android.graphics.Color` ints, are sRGB-encoded.  Shaders blend and
+//! interpolate linearly, so on an sRGB surface such colors must be decoded before they reach a
+//! shader, just like sRGB textures are decoded by the sampler.  On a linear surface they are used
+//! as-is, which matches how Android composes everything else.
+
+/// Decodes an sRGB-encoded channel in `0..=1` to linear
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Decodes the color channels of an sRGB-encoded RGBA color, alpha is always linear
+pub fn linearize([r, g, b, a]: [f32; 4]) -> [f32; 4] {
+    [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_to_linear_at_known_points() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert_eq!(srgb_to_linear(1.0), 1.0);
+        // Where the linear segment ends, which both segments agree on
+        assert!((srgb_to_linear(0.04045) - 0.04045 / 12.92).abs() < 1e-7);
+        assert!((((0.04045 + 0.055) / 1.055f32).powf(2.4) - 0.04045 / 12.92).abs() < 1e-6);
+        assert!((srgb_to_linear(0.5) - 0.214_041).abs() < 1e-6);
+    }
+
+    #[test]
+    fn linearize_keeps_alpha() {
+        let [r, g, b, a] = linearize([0.0, 0.5, 1.0, 0.5]);
+        assert_eq!((r, b, a), (0.0, 1.0, 0.5));
+        assert!((g - 0.214_041).abs() < 1e-6);
+    }
+}
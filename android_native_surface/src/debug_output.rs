@@ -0,0 +1,170 @@
+//! Forwards driver diagnostics from `KHR_debug` and `EGL_KHR_debug` into [`log`].
+//!
+//! GL messages are logged under a `gl::<source>::<type>` target with their severity mapped to a
+//! level, so that they can be filtered per source and type.  EGL messages are logged under the
+//! `egl` target and name the EGL command that failed.  Most drivers only report more than errors
+//! on a debug context, see [`crate::NativeGL::new()`].
+
+use std::ffi::{c_char, c_void, CStr};
+
+use glutin::display::{Display, GlDisplay as _};
+use log::{debug, log, warn, Level};
+
+use crate::support::gl::{
+    self,
+    types::{GLchar, GLenum, GLsizei, GLuint},
+};
+
+/// Installs the GL debug callback on the current context.  On a debug context, messages are
+/// delivered synchronously so that they are logged from within the call that caused them.
+///
+/// # Safety
+/// A context must be current that supports
+/// [`Feature::DebugOutput`][crate::support::capabilities::Feature::DebugOutput].
+pub unsafe fn enable_gl_debug_output(gl: &gl::Gl) {
+    let mut flags = 0;
+    gl.GetIntegerv(gl::CONTEXT_FLAGS, &mut flags);
+    let debug_context = flags as GLenum & gl::CONTEXT_FLAG_DEBUG_BIT != 0;
+
+    gl.DebugMessageCallback(Some(gl_callback), std::ptr::null());
+    // Enabled by default on debug contexts only
+    gl.Enable(gl::DEBUG_OUTPUT);
+    if debug_context {
+        gl.Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+    }
+    debug!("Enabled GL debug output, debug context: {debug_context}");
+}
+
+extern "system" fn gl_callback(
+    source: GLenum,
+    ty: GLenum,
+    id: GLuint,
+    severity: GLenum,
+    length: GLsizei,
+    message: *const GLchar,
+    _user_param: *mut c_void,
+) {
+    let level = match severity {
+        gl::DEBUG_SEVERITY_HIGH => Level::Error,
+        gl::DEBUG_SEVERITY_MEDIUM => Level::Warn,
+        gl::DEBUG_SEVERITY_LOW => Level::Info,
+        _ => Level::Debug,
+    };
+    let source = match source {
+        gl::DEBUG_SOURCE_API => "api",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "window_system",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "shader_compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "third_party",
+        gl::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other",
+    };
+    let ty = match ty {
+        gl::DEBUG_TYPE_ERROR => "error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined",
+        gl::DEBUG_TYPE_PORTABILITY => "portability",
+        gl::DEBUG_TYPE_PERFORMANCE => "performance",
+        gl::DEBUG_TYPE_MARKER => "marker",
+        gl::DEBUG_TYPE_PUSH_GROUP => "push_group",
+        gl::DEBUG_TYPE_POP_GROUP => "pop_group",
+        _ => "other",
+    };
+    // The length excludes the NUL terminator, but is not reliable on every driver
+    let message = if length >= 0 {
+        let bytes = unsafe { std::slice::from_raw_parts(message.cast::<u8>(), length as usize) };
+        String::from_utf8_lossy(bytes)
+    } else {
+        unsafe { CStr::from_ptr(message) }.to_string_lossy()
+    };
+    let target = format!("gl::{source}::{ty}");
+    log!(target: &target, level, "[{id:#x}] {}", message.trim_end_matches('\0'));
+}
+
+const EGL_EXTENSIONS: i32 = 0x3055;
+const EGL_NONE: isize = 0x3038;
+const EGL_TRUE: isize = 1;
+const EGL_DEBUG_MSG_CRITICAL_KHR: i32 = 0x33B9;
+const EGL_DEBUG_MSG_ERROR_KHR: i32 = 0x33BA;
+const EGL_DEBUG_MSG_WARN_KHR: i32 = 0x33BB;
+const EGL_DEBUG_MSG_INFO_KHR: i32 = 0x33BC;
+
+type EglDebugProc = extern "system" fn(
+    error: u32,
+    command: *const c_char,
+    message_type: i32,
+    thread_label: *mut c_void,
+    object_label: *mut c_void,
+    message: *const c_char,
+);
+type EglDebugMessageControlFn =
+    unsafe extern "system" fn(callback: Option<EglDebugProc>, attrib_list: *const isize) -> i32;
+
+/// Installs the process-wide `EGL_KHR_debug` callback, if the client extension is available.
+/// Critical messages and errors are always reported, warnings and info only when `verbose`.
+pub fn enable_egl_debug_output(gl_display: &Display, verbose: bool) {
+    let Display::Egl(egl_display) = gl_display;
+    let egl = egl_display.egl();
+    // EGL_KHR_debug is a client extension, queried without a display
+    let client_extensions = unsafe { egl.QueryString(std::ptr::null(), EGL_EXTENSIONS) };
+    let supported = !client_extensions.is_null()
+        && unsafe { CStr::from_ptr(client_extensions) }
+            .to_string_lossy()
+            .split_whitespace()
+            .any(|extension| extension == "EGL_KHR_debug");
+    if !supported {
+        debug!("EGL_KHR_debug is not available");
+        return;
+    }
+
+    let control = gl_display.get_proc_address(c"eglDebugMessageControlKHR");
+    if control.is_null() {
+        warn!("EGL_KHR_debug is advertised but eglDebugMessageControlKHR() is missing");
+        return;
+    }
+    let control: EglDebugMessageControlFn = unsafe { std::mem::transmute(control) };
+
+    let verbose = isize::from(verbose);
+    let attribs = [
+        EGL_DEBUG_MSG_WARN_KHR as isize,
+        verbose,
+        EGL_DEBUG_MSG_INFO_KHR as isize,
+        verbose,
+        EGL_NONE,
+    ];
+    let result = unsafe { control(Some(egl_callback), attribs.as_ptr()) };
+    if result as isize == EGL_TRUE {
+        debug!("Enabled EGL debug output, verbose: {}", verbose != 0);
+    } else {
+        warn!("eglDebugMessageControlKHR() failed with {result:#x}");
+    }
+}
+
+extern "system" fn egl_callback(
+    error: u32,
+    command: *const c_char,
+    message_type: i32,
+    _thread_label: *mut c_void,
+    _object_label: *mut c_void,
+    message: *const c_char,
+) {
+    let level = match message_type {
+        EGL_DEBUG_MSG_CRITICAL_KHR | EGL_DEBUG_MSG_ERROR_KHR => Level::Error,
+        EGL_DEBUG_MSG_WARN_KHR => Level::Warn,
+        EGL_DEBUG_MSG_INFO_KHR => Level::Info,
+        _ => Level::Debug,
+    };
+    let string = |s: *const c_char| {
+        if s.is_null() {
+            "".into()
+        } else {
+            unsafe { CStr::from_ptr(s) }.to_string_lossy()
+        }
+    };
+    log!(
+        target: "egl",
+        level,
+        "{}: {} (error {error:#x})",
+        string(command),
+        string(message)
+    );
+}
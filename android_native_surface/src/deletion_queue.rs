@@ -0,0 +1,193 @@
+//! Deferred deletion of GL objects.
+//!
+//! GL objects can only be deleted while the context that owns them is current, which is not the
+//! case when a scene or window is dropped from an arbitrary JNI call.  Instead, [`GlObject`]
+//! pushes its name onto a [`DeletionQueue`] when dropped, which [`crate::NativeGL`] drains the
+//! next time it has a context current.
+//!
+//! Objects may outlive the context that created them, e.g. a texture that Java still holds on to
+//! when the context is recreated after a loss.  Their names are meaningless to the new context,
+//! so [`DeletionQueue::discard()`] starts a new epoch and objects from an older epoch are not
+//! queued at all.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use crate::support::gl::{self, types::GLuint};
+
+/// Kind of a GL object name, selecting the matching `glDelete*()` function
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlObjectKind {
+    Buffer,
+    Framebuffer,
+    Program,
+    Query,
+    Renderbuffer,
+    Texture,
+    VertexArray,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    names: Vec<(GlObjectKind, GLuint)>,
+    /// Bumped every time the owning context is lost
+    epoch: u64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    pending: Mutex<Pending>,
+    /// Number of [`GlObject`]s that have not been dropped yet
+    alive: AtomicUsize,
+}
+
+/// Cheaply clonable handle to a queue of GL object names that are pending deletion
+#[derive(Clone, Debug, Default)]
+pub struct DeletionQueue(Arc<Inner>);
+
+impl DeletionQueue {
+    /// Takes ownership of `name`, to be deleted through this queue once the returned [`GlObject`]
+    /// is dropped.
+    pub fn wrap(&self, kind: GlObjectKind, name: GLuint) -> GlObject {
+        let epoch = self.0.pending.lock().unwrap().epoch;
+        self.0.alive.fetch_add(1, Ordering::Relaxed);
+        GlObject {
+            kind,
+            name,
+            epoch,
+            queue: self.clone(),
+        }
+    }
+
+    /// Incremented by every [`DeletionQueue::discard()`], i.e. every time the owning context is
+    /// replaced.  Objects wrapped in an older epoch are no longer valid.
+    pub fn epoch(&self) -> u64 {
+        self.0.pending.lock().unwrap().epoch
+    }
+
+    /// Number of wrapped objects that are not yet dropped, and hence not yet queued.
+    pub fn alive(&self) -> usize {
+        self.0.alive.load(Ordering::Relaxed)
+    }
+
+    /// Deletes all pending objects in the order they were dropped, returning how many were
+    /// deleted.
+    ///
+    /// # Safety
+    /// The context that created the pending objects must be current on this thread.
+    pub unsafe fn drain(&self, gl: &gl::Gl) -> usize {
+        let pending = self.take();
+        for &(kind, name) in &pending {
+            match kind {
+                GlObjectKind::Buffer => gl.DeleteBuffers(1, &name),
+                GlObjectKind::Framebuffer => gl.DeleteFramebuffers(1, &name),
+                GlObjectKind::Program => gl.DeleteProgram(name),
+                GlObjectKind::Query => gl.DeleteQueries(1, &name),
+                GlObjectKind::Renderbuffer => gl.DeleteRenderbuffers(1, &name),
+                GlObjectKind::Texture => gl.DeleteTextures(1, &name),
+                GlObjectKind::VertexArray => gl.DeleteVertexArrays(1, &name),
+            }
+        }
+        pending.len()
+    }
+
+    /// Removes all pending names from the queue, in the order they were dropped
+    fn take(&self) -> Vec<(GlObjectKind, GLuint)> {
+        std::mem::take(&mut self.0.pending.lock().unwrap().names)
+    }
+
+    /// Forgets all pending objects without deleting them, for when the context that owned them
+    /// is gone.  Objects that are still alive are forgotten when they are dropped.  Returns how
+    /// many pending objects were discarded.
+    pub fn discard(&self) -> usize {
+        let mut pending = self.0.pending.lock().unwrap();
+        pending.epoch += 1;
+        std::mem::take(&mut pending.names).len()
+    }
+}
+
+/// Owned GL object name that is queued for deletion on [`Drop`]
+#[derive(Debug)]
+pub struct GlObject {
+    kind: GlObjectKind,
+    name: GLuint,
+    /// Epoch of the queue when this object was created
+    epoch: u64,
+    queue: DeletionQueue,
+}
+
+impl GlObject {
+    pub fn name(&self) -> GLuint {
+        self.name
+    }
+}
+
+impl Drop for GlObject {
+    fn drop(&mut self) {
+        let mut pending = self.queue.0.pending.lock().unwrap();
+        if pending.epoch == self.epoch {
+            pending.names.push((self.kind, self.name));
+        }
+        self.queue.0.alive.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_queues_object() {
+        let queue = DeletionQueue::default();
+        let texture = queue.wrap(GlObjectKind::Texture, 1);
+        let buffer = queue.wrap(GlObjectKind::Buffer, 2);
+        assert_eq!(texture.name(), 1);
+        assert!(queue.take().is_empty());
+
+        drop(buffer);
+        drop(texture);
+        assert_eq!(
+            queue.take(),
+            [(GlObjectKind::Buffer, 2), (GlObjectKind::Texture, 1)]
+        );
+        assert!(queue.take().is_empty());
+    }
+
+    #[test]
+    fn discard_forgets_old_epoch() {
+        let queue = DeletionQueue::default();
+        let old = queue.wrap(GlObjectKind::Program, 1);
+        drop(queue.wrap(GlObjectKind::Query, 2));
+        assert_eq!(queue.epoch(), 0);
+
+        assert_eq!(queue.discard(), 1);
+        assert_eq!(queue.epoch(), 1);
+        let new = queue.wrap(GlObjectKind::Program, 1);
+        // The name was only valid in the lost context, and may be reused by the new one
+        drop(old);
+        assert!(queue.take().is_empty());
+
+        drop(new);
+        assert_eq!(queue.take(), [(GlObjectKind::Program, 1)]);
+        assert_eq!(queue.discard(), 0);
+    }
+
+    #[test]
+    fn alive_counts_leaks() {
+        let queue = DeletionQueue::default();
+        let leaked = queue.wrap(GlObjectKind::Framebuffer, 1);
+        let dropped = queue.wrap(GlObjectKind::Renderbuffer, 2);
+        assert_eq!(queue.alive(), 2);
+
+        drop(dropped);
+        assert_eq!(queue.alive(), 1);
+        // Objects of an older epoch are still counted until dropped
+        queue.discard();
+        assert_eq!(queue.alive(), 1);
+        drop(leaked);
+        assert_eq!(queue.alive(), 0);
+        assert!(queue.take().is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! Runtime-loaded `ANativeWindow_setFrameRate()` and
+//! `ANativeWindow_setFrameRateWithChangeStrategy()`.
+//!
+//! These were introduced in API 30 and 31 respectively, while this crate only requires API 28
+//! (see the `api-level-28` feature on `ndk`).  Instead of hard-linking them we look them up in
+//! `libandroid.so` on first use, so that the library still loads on older devices.
+//!
+//! The frame rate is checked up front like the platform checks it, see [`check_frame_rate()`].
+
+use std::io::{self, ErrorKind};
+#[cfg(target_os = "android")]
+use std::{ffi::c_void, sync::OnceLock};
+
+#[cfg(target_os = "android")]
+use libloading::Library;
+#[cfg(target_os = "android")]
+use log::warn;
+#[cfg(target_os = "android")]
+use ndk::native_window::NativeWindow;
+
+/// Mirrors `ANativeWindow_FrameRateCompatibility`, which `ndk` only exposes with `api-level-30`.
+#[repr(i8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameRateCompatibility {
+    /// There are no inherent restrictions on the frame rate of this window (games, UI).
+    #[default]
+    Default = 0,
+    /// This window displays content with an inherently fixed frame rate, e.g. video.
+    FixedSource = 1,
+}
+
+impl TryFrom<i32> for FrameRateCompatibility {
+    type Error = i32;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Default),
+            1 => Ok(Self::FixedSource),
+            x => Err(x),
+        }
+    }
+}
+
+/// Mirrors `ANativeWindow_ChangeFrameRateStrategy`, which `ndk` only exposes with `api-level-31`.
+#[repr(i8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChangeFrameRateStrategy {
+    /// Change the frame rate only if the transition is going to be seamless.
+    #[default]
+    OnlyIfSeamless = 0,
+    /// Change the frame rate even if the transition is going to be non-seamless.
+    Always = 1,
+}
+
+impl TryFrom<i32> for ChangeFrameRateStrategy {
+    type Error = i32;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::OnlyIfSeamless),
+            1 => Ok(Self::Always),
+            x => Err(x),
+        }
+    }
+}
+
+#[cfg(target_os = "android")]
+type SetFrameRateFn =
+    unsafe extern "C" fn(window: *mut c_void, frame_rate: f32, compatibility: i8) -> i32;
+#[cfg(target_os = "android")]
+type SetFrameRateWithChangeStrategyFn = unsafe extern "C" fn(
+    window: *mut c_void,
+    frame_rate: f32,
+    compatibility: i8,
+    change_frame_rate_strategy: i8,
+) -> i32;
+
+#[cfg(target_os = "android")]
+struct Functions {
+    set_frame_rate: Option<SetFrameRateFn>,
+    set_frame_rate_with_change_strategy: Option<SetFrameRateWithChangeStrategyFn>,
+    /// Keeps the function pointers above alive
+    _lib: Library,
+}
+
+#[cfg(target_os = "android")]
+fn functions() -> Option<&'static Functions> {
+    static FUNCTIONS: OnceLock<Option<Functions>> = OnceLock::new();
+    FUNCTIONS
+        .get_or_init(|| {
+            // SAFETY: libandroid.so is already loaded by virtue of linking against the NDK, this
+            // only increments its refcount and doesn't run any (new) initializers.
+            let lib = unsafe { Library::new("libandroid.so") }
+                .inspect_err(|e| warn!("Failed to open libandroid.so: {e}"))
+                .ok()?;
+            // SAFETY: The signatures match the NDK headers.
+            unsafe {
+                Some(Functions {
+                    set_frame_rate: lib
+                        .get::<SetFrameRateFn>(b"ANativeWindow_setFrameRate\0")
+                        .ok()
+                        .map(|f| *f),
+                    set_frame_rate_with_change_strategy: lib
+                        .get::<SetFrameRateWithChangeStrategyFn>(
+                            b"ANativeWindow_setFrameRateWithChangeStrategy\0",
+                        )
+                        .ok()
+                        .map(|f| *f),
+                    _lib: lib,
+                })
+            }
+        })
+        .as_ref()
+}
+
+/// Rejects what the platform would reject with `EINVAL`: negative and non-finite frame rates.
+/// `0` lets the system pick, as if no frame rate was set.
+pub fn check_frame_rate(frame_rate: f32) -> io::Result<()> {
+    if frame_rate.is_finite() && frame_rate >= 0.0 {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Frame rate {frame_rate} is not a finite, non-negative number"),
+        ))
+    }
+}
+
+/// The functions return `0` or a negative `errno`, other values are not documented
+fn status_to_io_result(status: i32) -> io::Result<()> {
+    match status {
+        0 => Ok(()),
+        r if r < 0 => Err(io::Error::from_raw_os_error(-r)),
+        r => Err(io::Error::other(format!("Unexpected status {r}"))),
+    }
+}
+
+/// Sets the intended frame rate for `window`, picking the most specific function available on
+/// this device.
+///
+/// Falls back to `ANativeWindow_setFrameRate()` on API 30, where `change_frame_rate_strategy` is
+/// implicitly [`ChangeFrameRateStrategy::OnlyIfSeamless`].  Returns [`ErrorKind::Unsupported`]
+/// below API 30, and [`ErrorKind::InvalidInput`] if [`check_frame_rate()`] fails.
+#[cfg(target_os = "android")]
+pub fn set_frame_rate(
+    window: &NativeWindow,
+    frame_rate: f32,
+    compatibility: FrameRateCompatibility,
+    change_frame_rate_strategy: ChangeFrameRateStrategy,
+) -> io::Result<()> {
+    check_frame_rate(frame_rate)?;
+    let functions = functions().ok_or(ErrorKind::Unsupported)?;
+    let window = window.ptr().as_ptr().cast();
+
+    let status = if let Some(f) = functions.set_frame_rate_with_change_strategy {
+        unsafe {
+            f(
+                window,
+                frame_rate,
+                compatibility as i8,
+                change_frame_rate_strategy as i8,
+            )
+        }
+    } else if let Some(f) = functions.set_frame_rate {
+        if change_frame_rate_strategy != ChangeFrameRateStrategy::OnlyIfSeamless {
+            warn!("{change_frame_rate_strategy:?} requires API 31, falling back to OnlyIfSeamless");
+        }
+        unsafe { f(window, frame_rate, compatibility as i8) }
+    } else {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "ANativeWindow_setFrameRate() requires API 30",
+        ));
+    };
+
+    status_to_io_result(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_rate() {
+        for frame_rate in [0.0, 24.0, 59.94, 120.0] {
+            assert!(check_frame_rate(frame_rate).is_ok(), "{frame_rate}");
+        }
+        for frame_rate in [-1.0, -0.5, f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let e = check_frame_rate(frame_rate).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput, "{frame_rate}");
+        }
+    }
+
+    #[test]
+    fn compatibility() {
+        assert_eq!(
+            FrameRateCompatibility::try_from(0),
+            Ok(FrameRateCompatibility::Default)
+        );
+        assert_eq!(
+            FrameRateCompatibility::try_from(1),
+            Ok(FrameRateCompatibility::FixedSource)
+        );
+        assert_eq!(FrameRateCompatibility::try_from(2), Err(2));
+        assert_eq!(FrameRateCompatibility::try_from(-1), Err(-1));
+        assert_eq!(FrameRateCompatibility::default() as i8, 0);
+    }
+
+    #[test]
+    fn change_frame_rate_strategy() {
+        assert_eq!(
+            ChangeFrameRateStrategy::try_from(0),
+            Ok(ChangeFrameRateStrategy::OnlyIfSeamless)
+        );
+        assert_eq!(
+            ChangeFrameRateStrategy::try_from(1),
+            Ok(ChangeFrameRateStrategy::Always)
+        );
+        assert_eq!(ChangeFrameRateStrategy::try_from(2), Err(2));
+        assert_eq!(ChangeFrameRateStrategy::default() as i8, 0);
+    }
+
+    #[test]
+    fn status() {
+        assert!(status_to_io_result(0).is_ok());
+        let e = status_to_io_result(-22).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(22));
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        let e = status_to_io_result(1).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+    }
+}
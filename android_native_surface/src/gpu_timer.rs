@@ -0,0 +1,395 @@
+//! GPU time of render phases, measured with `EXT_disjoint_timer_query`.
+//!
+//! [`ndk::trace::Section`]s only measure how long the CPU took to submit commands.  A [`GpuTimer`]
+//! brackets render phases with `GL_TIME_ELAPSED_EXT` queries instead, whose results are read back
+//! a few frames later so that the pipeline never stalls on them.  Frames during which the GPU
+//! reported a disjoint event, such as a frequency change, are discarded as their results are
+//! meaningless.  Every submitted frame is also shown as an async "GPU frame" trace section until
+//! its results are read back, to visualize how many frames the GPU is behind.
+//!
+//! The disjoint flag belongs to the context that all windows share, and reading it clears it, so
+//! [`Renderer::poll_gpu_disjoint()`] reads it once per frame and every [`GpuTimer`] compares the
+//! number of events against the one at the start of its frames.  Which frames are read back or
+//! discarded is decided by [`PendingFrames`] and averaged by [`FrameHistory`], which do not touch
+//! GL so that they can be tested on the host.
+
+use std::{collections::VecDeque, mem, time::Duration};
+
+use log::debug;
+
+use crate::{
+    deletion_queue::{DeletionQueue, GlObject, GlObjectKind},
+    support::{capabilities::Feature, gl, Renderer},
+    trace,
+};
+
+/// Number of completed frames that the statistics are averaged over
+const FRAME_HISTORY: usize = 60;
+/// Number of submitted frames to wait for before the oldest one is given up on
+const MAX_PENDING_FRAMES: usize = 5;
+
+/// Phase name and query object of every phase in a frame, in submission order
+type FrameQueries<Q> = Vec<(&'static str, Q)>;
+
+/// GPU time of every phase in a frame, in submission order
+type FrameTimes = Vec<(&'static str, Duration)>;
+
+/// A submitted frame whose results are not read yet
+#[derive(Debug)]
+struct PendingFrame<Q, S> {
+    queries: FrameQueries<Q>,
+    /// [`Renderer::gpu_disjoint()`] when the first phase of the frame began
+    disjoint: u64,
+    /// Kept until the frame is read back or given up on
+    _in_flight: S,
+}
+
+/// Submitted frames of query objects `Q` whose results are not read yet, oldest first.  `S` is
+/// dropped when a frame is read back or given up on, such as the "GPU frame" trace section.
+#[derive(Debug)]
+struct PendingFrames<Q, S> {
+    frames: VecDeque<PendingFrame<Q, S>>,
+}
+
+impl<Q, S> Default for PendingFrames<Q, S> {
+    fn default() -> Self {
+        Self {
+            frames: VecDeque::new(),
+        }
+    }
+}
+
+impl<Q, S> PendingFrames<Q, S> {
+    fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn submit(&mut self, queries: FrameQueries<Q>, disjoint: u64, in_flight: S) {
+        self.frames.push_back(PendingFrame {
+            queries,
+            disjoint,
+            _in_flight: in_flight,
+        });
+    }
+
+    /// Reads back the oldest frames whose last query is `available()` with `result()`, and
+    /// discards those that overlapped a disjoint event, i.e. that began before `disjoint` events
+    /// were seen.  The oldest frame is given up on when more than [`MAX_PENDING_FRAMES`] are
+    /// waiting.  All query objects that are done with are moved to `free`.
+    fn poll(
+        &mut self,
+        disjoint: u64,
+        mut available: impl FnMut(&Q) -> bool,
+        mut result: impl FnMut(&Q) -> Duration,
+        free: &mut Vec<Q>,
+    ) -> Vec<FrameTimes> {
+        let release = |frame: PendingFrame<Q, S>, free: &mut Vec<Q>| {
+            free.extend(frame.queries.into_iter().map(|(_, query)| query));
+        };
+        let mut times = Vec::new();
+        while let Some(frame) = self.frames.pop_front() {
+            if frame.disjoint != disjoint {
+                debug!("GPU timer disjoint, discarding a frame");
+                release(frame, free);
+                continue;
+            }
+
+            // Queries complete in order, hence the last one completes the frame
+            let (_, last) = frame.queries.last().unwrap();
+            if !available(last) {
+                if self.frames.len() < MAX_PENDING_FRAMES {
+                    self.frames.push_front(frame);
+                    break;
+                }
+                debug!("Giving up on GPU timer results of a frame");
+                release(frame, free);
+                continue;
+            }
+
+            times.push(
+                frame
+                    .queries
+                    .into_iter()
+                    .map(|(phase, query)| {
+                        let time = result(&query);
+                        free.push(query);
+                        (phase, time)
+                    })
+                    .collect(),
+            );
+        }
+        times
+    }
+}
+
+/// GPU time of every phase of the most recently completed frames, oldest first
+#[derive(Debug, Default)]
+struct FrameHistory {
+    frames: VecDeque<FrameTimes>,
+}
+
+impl FrameHistory {
+    fn record(&mut self, times: FrameTimes) {
+        if self.frames.len() == FRAME_HISTORY {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(times);
+    }
+
+    fn gpu_time(&self) -> Option<Duration> {
+        let count = u32::try_from(self.frames.len()).unwrap();
+        (count > 0).then(|| {
+            self.frames
+                .iter()
+                .flatten()
+                .map(|&(_, time)| time)
+                .sum::<Duration>()
+                / count
+        })
+    }
+
+    fn phase_times(&self) -> FrameTimes {
+        let mut phases = FrameTimes::new();
+        for &(phase, time) in self.frames.iter().flatten() {
+            match phases.iter_mut().find(|(p, _)| *p == phase) {
+                Some((_, total)) => *total += time,
+                None => phases.push((phase, time)),
+            }
+        }
+        let count = u32::try_from(self.frames.len()).unwrap();
+        for (_, time) in &mut phases {
+            *time /= count;
+        }
+        phases
+    }
+}
+
+/// Per-window GPU time statistics.  All methods are no-ops on contexts without
+/// [`Feature::TimerQuery`].
+#[derive(Debug, Default)]
+pub struct GpuTimer {
+    /// [`DeletionQueue::epoch()`] that all query objects below were created in
+    epoch: u64,
+    /// Phase of the query that is currently active
+    active: Option<(&'static str, GlObject)>,
+    /// Queries of the frame that is being rendered
+    current: FrameQueries<GlObject>,
+    /// [`Renderer::gpu_disjoint()`] when the first phase of `current` began
+    current_disjoint: u64,
+    /// Queries of submitted frames whose results are not read yet
+    pending: PendingFrames<GlObject, trace::AsyncSection>,
+    /// Query objects whose results were read, for reuse
+    free: Vec<GlObject>,
+    history: FrameHistory,
+}
+
+impl GpuTimer {
+    /// Forgets all query objects when the context that they were created on was replaced.
+    fn check_epoch(&mut self, deletion_queue: &DeletionQueue) {
+        let epoch = deletion_queue.epoch();
+        if epoch != self.epoch {
+            debug!("Context was replaced, dropping GPU timer queries");
+            *self = Self {
+                epoch,
+                history: mem::take(&mut self.history),
+                ..Default::default()
+            };
+        }
+    }
+
+    /// Starts timing `phase` on the current context of `renderer`.  Phases cannot nest, every
+    /// call must be followed by [`GpuTimer::end()`].
+    pub fn begin(
+        &mut self,
+        renderer: &Renderer,
+        deletion_queue: &DeletionQueue,
+        phase: &'static str,
+    ) {
+        if !renderer.capabilities().supports(Feature::TimerQuery) {
+            return;
+        }
+        self.check_epoch(deletion_queue);
+        assert!(self.active.is_none(), "GPU timer phases cannot nest");
+        if self.current.is_empty() {
+            self.current_disjoint = renderer.gpu_disjoint();
+        }
+
+        let gl = renderer.gl();
+        let query = self.free.pop().unwrap_or_else(|| {
+            let mut query = 0;
+            unsafe { gl.GenQueries(1, &mut query) };
+            deletion_queue.wrap(GlObjectKind::Query, query)
+        });
+        unsafe { gl.BeginQuery(gl::TIME_ELAPSED_EXT, query.name()) };
+        self.active = Some((phase, query));
+    }
+
+    /// Stops timing the phase that was started by [`GpuTimer::begin()`].
+    pub fn end(&mut self, renderer: &Renderer) {
+        if let Some(query) = self.active.take() {
+            unsafe { renderer.gl().EndQuery(gl::TIME_ELAPSED_EXT) };
+            self.current.push(query);
+        }
+    }
+
+    /// Submits the phases of the current frame with id `frame_id` from [`trace::next_frame_id()`],
+    /// and reads back the results of earlier frames that are available by now.  Must be preceded
+    /// by [`Renderer::poll_gpu_disjoint()`] in the same frame.
+    pub fn end_frame(
+        &mut self,
+        renderer: &Renderer,
+        deletion_queue: &DeletionQueue,
+        frame_id: i32,
+    ) {
+        if !renderer.capabilities().supports(Feature::TimerQuery) {
+            return;
+        }
+        self.check_epoch(deletion_queue);
+        assert!(self.active.is_none(), "GPU timer phase was not ended");
+        if !self.current.is_empty() {
+            self.pending.submit(
+                mem::take(&mut self.current),
+                self.current_disjoint,
+                trace::AsyncSection::begin("GPU frame", frame_id),
+            );
+        }
+
+        let gl = renderer.gl();
+        let frames = self.pending.poll(
+            renderer.gpu_disjoint(),
+            |query| {
+                let mut available = 0;
+                unsafe {
+                    gl.GetQueryObjectuiv(query.name(), gl::QUERY_RESULT_AVAILABLE, &mut available)
+                };
+                available != 0
+            },
+            |query| {
+                let mut nanos = 0;
+                unsafe { gl.GetQueryObjectui64vEXT(query.name(), gl::QUERY_RESULT, &mut nanos) };
+                Duration::from_nanos(nanos)
+            },
+            &mut self.free,
+        );
+        for times in frames {
+            self.record_frame(times);
+        }
+        trace::set_counter("GPU frames in flight", self.pending.len() as i64);
+    }
+
+    fn record_frame(&mut self, times: FrameTimes) {
+        for &(phase, time) in &times {
+            trace::set_counter(&format!("GPU {phase} us"), time.as_micros() as i64);
+        }
+        let total = times.iter().map(|&(_, time)| time).sum::<Duration>();
+        trace::set_counter("GPU frame us", total.as_micros() as i64);
+        self.history.record(times);
+    }
+
+    /// Average GPU time per frame, summed over all phases
+    pub fn gpu_time(&self) -> Option<Duration> {
+        self.history.gpu_time()
+    }
+
+    /// Average GPU time per frame of every phase, in the order they were first recorded
+    pub fn phase_times(&self) -> Vec<(&'static str, Duration)> {
+        self.history.phase_times()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    /// Frame of one query per phase, numbered from `first`
+    fn queries(first: u32, phases: &[&'static str]) -> FrameQueries<u32> {
+        phases.iter().zip(first..).map(|(&p, q)| (p, q)).collect()
+    }
+
+    /// Polls with query `q` taking `q` ms, and the queries from `available` on not done yet
+    fn poll(
+        pending: &mut PendingFrames<u32, ()>,
+        disjoint: u64,
+        available: u32,
+        free: &mut Vec<u32>,
+    ) -> Vec<FrameTimes> {
+        pending.poll(disjoint, |&q| q < available, |&q| q * MS, free)
+    }
+
+    #[test]
+    fn frames_are_read_back_in_order_once_available() {
+        let mut pending = PendingFrames::default();
+        let mut free = Vec::new();
+        pending.submit(queries(1, &["draw", "hud"]), 0, ());
+        pending.submit(queries(3, &["draw"]), 0, ());
+
+        // The first frame is only done when its last query is
+        assert!(poll(&mut pending, 0, 2, &mut free).is_empty());
+        assert_eq!(pending.len(), 2);
+
+        let times = poll(&mut pending, 0, 3, &mut free);
+        assert_eq!(times, [vec![("draw", MS), ("hud", 2 * MS)]]);
+        assert_eq!(free, [1, 2]);
+
+        let times = poll(&mut pending, 0, 4, &mut free);
+        assert_eq!(times, [vec![("draw", 3 * MS)]]);
+        assert_eq!(pending.len(), 0);
+    }
+
+    #[test]
+    fn frames_before_a_disjoint_event_are_discarded() {
+        let mut pending = PendingFrames::default();
+        let mut free = Vec::new();
+        pending.submit(queries(1, &["draw"]), 0, ());
+        pending.submit(queries(2, &["draw"]), 1, ());
+
+        // Another window polled the event, but both frames see it through the count
+        let times = poll(&mut pending, 1, u32::MAX, &mut free);
+        assert_eq!(times, [vec![("draw", 2 * MS)]]);
+        assert_eq!(free, [1, 2]);
+
+        // Frames that began after the event are kept
+        pending.submit(queries(3, &["draw"]), 1, ());
+        assert_eq!(poll(&mut pending, 1, u32::MAX, &mut free).len(), 1);
+    }
+
+    #[test]
+    fn oldest_frame_is_given_up_on() {
+        let mut pending = PendingFrames::default();
+        let mut free = Vec::new();
+        for q in 0..=MAX_PENDING_FRAMES as u32 {
+            pending.submit(queries(q, &["draw"]), 0, ());
+        }
+        assert!(poll(&mut pending, 0, 0, &mut free).is_empty());
+        assert_eq!(free, [0]);
+        assert_eq!(pending.len(), MAX_PENDING_FRAMES);
+    }
+
+    #[test]
+    fn history_averages_per_phase() {
+        let mut history = FrameHistory::default();
+        assert_eq!(history.gpu_time(), None);
+        assert!(history.phase_times().is_empty());
+
+        history.record(vec![("draw", 2 * MS), ("hud", MS)]);
+        history.record(vec![("draw", 4 * MS), ("post", 4 * MS)]);
+        assert_eq!(history.gpu_time(), Some(11 * MS / 2));
+        // A phase that is missing from a frame counts as zero there
+        assert_eq!(
+            history.phase_times(),
+            [("draw", 3 * MS), ("hud", MS / 2), ("post", 2 * MS)]
+        );
+    }
+
+    #[test]
+    fn history_keeps_the_most_recent_frames() {
+        let mut history = FrameHistory::default();
+        history.record(vec![("draw", 100 * MS)]);
+        for _ in 0..FRAME_HISTORY {
+            history.record(vec![("draw", MS)]);
+        }
+        assert_eq!(history.gpu_time(), Some(MS));
+    }
+}
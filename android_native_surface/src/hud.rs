@@ -0,0 +1,254 @@
+//! Debug overlay that is drawn over a [`crate::support::GlWindow`] when enabled from Kotlin.
+//!
+//! It shows how fast the window is rendered to, and which surface, EGL config and GPU it ended up
+//! with, which makes it easy to compare the `SurfaceView` and `TextureView` paths side by side.
+
+#[cfg(target_os = "android")]
+use std::fmt::Write as _;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "android")]
+use glutin::{
+    config::{ColorBufferType, Config},
+    prelude::*,
+};
+#[cfg(target_os = "android")]
+use ndk::native_window::NativeWindow;
+
+use crate::trace;
+#[cfg(target_os = "android")]
+use crate::{gpu_timer::GpuTimer, msaa::Msaa, support::capabilities::Capabilities};
+
+/// Number of frames that the statistics are averaged over
+const FRAME_HISTORY: usize = 60;
+/// A frame interval this much longer than the average counts as dropping frames
+const DROPPED_FRAME_THRESHOLD: f32 = 1.5;
+/// A frame interval this much longer than the average is not a hitch but the window sitting idle,
+/// as it is only redrawn on demand, and restarts the statistics
+const IDLE_THRESHOLD: f32 = 4.0;
+
+pub const TEXT_SIZE: f32 = 28.0;
+pub const TEXT_POSITION: (f32, f32) = (16.0, 16.0);
+pub const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+/// Drawn offset behind the text, to keep it readable on any background
+pub const SHADOW_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.8];
+pub const SHADOW_OFFSET: f32 = 2.0;
+
+/// Frame pacing statistics over the most recent frames
+#[derive(Debug, Default)]
+struct FrameStats {
+    /// Start and CPU time of the most recent frames, oldest first
+    frames: VecDeque<(Instant, Duration)>,
+    /// Frames that were estimated to be dropped since the window was created
+    dropped_frames: u64,
+}
+
+impl FrameStats {
+    fn record(&mut self, start: Instant, cpu_time: Duration) {
+        // Frames are paced by the display, so a longer interval than usual means that as many
+        // vsyncs were missed.  Much longer intervals mean that nothing was drawn for a while.
+        if let (Some(fps), Some((last, _))) = (self.fps(), self.frames.back()) {
+            let intervals = start.duration_since(*last).as_secs_f32() * fps;
+            if intervals > IDLE_THRESHOLD {
+                self.frames.clear();
+            } else if intervals > DROPPED_FRAME_THRESHOLD {
+                self.dropped_frames += intervals.round() as u64 - 1;
+            }
+        }
+
+        if self.frames.len() == FRAME_HISTORY {
+            self.frames.pop_front();
+        }
+        self.frames.push_back((start, cpu_time));
+    }
+
+    fn fps(&self) -> Option<f32> {
+        let (first, _) = self.frames.front()?;
+        let (last, _) = self.frames.back()?;
+        let elapsed = last.duration_since(*first).as_secs_f32();
+        (elapsed > 0.0).then(|| (self.frames.len() - 1) as f32 / elapsed)
+    }
+
+    fn cpu_time(&self) -> Option<Duration> {
+        let count = u32::try_from(self.frames.len()).unwrap();
+        (count > 0).then(|| self.frames.iter().map(|(_, t)| *t).sum::<Duration>() / count)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Hud {
+    pub enabled: bool,
+    stats: FrameStats,
+}
+
+impl Hud {
+    /// Records a frame that started rendering at `start` and took `cpu_time` until it was
+    /// submitted, and updates the fps and dropped frames trace counters.
+    pub fn record_frame(&mut self, start: Instant, cpu_time: Duration) {
+        self.stats.record(start, cpu_time);
+        if let Some(fps) = self.fps() {
+            trace::set_counter("fps", fps.round() as i64);
+        }
+        trace::set_counter("dropped frames", self.stats.dropped_frames as i64);
+    }
+
+    /// Average number of frames per second, [`None`] until two frames were recorded since the
+    /// window was last idle.
+    pub fn fps(&self) -> Option<f32> {
+        self.stats.fps()
+    }
+
+    /// Average CPU time per frame
+    pub fn cpu_time(&self) -> Option<Duration> {
+        self.stats.cpu_time()
+    }
+
+    /// The lines to display over `window`.
+    #[cfg(target_os = "android")]
+    pub fn text(
+        &self,
+        window: &NativeWindow,
+        config: &Config,
+        capabilities: &Capabilities,
+        gpu_timer: &GpuTimer,
+        msaa: &Msaa,
+        srgb: bool,
+    ) -> String {
+        let mut text = String::new();
+        match (self.fps(), self.cpu_time()) {
+            (Some(fps), Some(cpu_time)) => writeln!(
+                text,
+                "{fps:.1} FPS, {:.2} ms CPU, {} dropped",
+                cpu_time.as_secs_f64() * 1000.0,
+                self.stats.dropped_frames
+            ),
+            _ => writeln!(text, "-- FPS"),
+        }
+        .unwrap();
+
+        if let Some(gpu_time) = gpu_timer.gpu_time() {
+            write!(text, "{:.2} ms GPU", gpu_time.as_secs_f64() * 1000.0).unwrap();
+            let phases = gpu_timer
+                .phase_times()
+                .into_iter()
+                .map(|(phase, time)| format!("{phase} {:.2}", time.as_secs_f64() * 1000.0))
+                .collect::<Vec<_>>();
+            writeln!(text, " ({})", phases.join(", ")).unwrap();
+        }
+
+        writeln!(
+            text,
+            "{}x{} {:?}",
+            window.width(),
+            window.height(),
+            window.format()
+        )
+        .unwrap();
+
+        let color = match config.color_buffer_type() {
+            Some(ColorBufferType::Rgb {
+                r_size,
+                g_size,
+                b_size,
+            }) => format!("R{r_size}G{g_size}B{b_size}"),
+            Some(ColorBufferType::Luminance(size)) => format!("L{size}"),
+            None => "unknown color".to_owned(),
+        };
+        write!(
+            text,
+            "EGL {color}A{} {}, {} samples, ",
+            config.alpha_size(),
+            if srgb { "sRGB" } else { "linear" },
+            config.num_samples()
+        )
+        .unwrap();
+        match msaa.active() {
+            Some((samples, strategy)) => writeln!(text, "MSAA {samples}x {strategy:?}"),
+            None => writeln!(text, "no MSAA"),
+        }
+        .unwrap();
+
+        write!(text, "{} ({})", capabilities.renderer, capabilities.version).unwrap();
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_micros(16_667);
+
+    /// Records `count` frames that start `interval` apart after `start`, returning the start of
+    /// the last one
+    fn record(stats: &mut FrameStats, start: Instant, interval: Duration, count: u32) -> Instant {
+        for i in 0..count {
+            stats.record(
+                start + interval * i,
+                Duration::from_millis(u64::from(i % 3)),
+            );
+        }
+        start + interval * (count - 1)
+    }
+
+    #[test]
+    fn fps_and_cpu_time() {
+        let mut stats = FrameStats::default();
+        assert_eq!((stats.fps(), stats.cpu_time()), (None, None));
+
+        let start = Instant::now();
+        stats.record(start, Duration::from_millis(4));
+        assert_eq!(stats.fps(), None);
+        assert_eq!(stats.cpu_time(), Some(Duration::from_millis(4)));
+
+        stats.record(start + INTERVAL, Duration::from_millis(2));
+        assert!((stats.fps().unwrap() - 60.0).abs() < 0.01);
+        assert_eq!(stats.cpu_time(), Some(Duration::from_millis(3)));
+
+        // Only the most recent frames are averaged
+        let mut stats = FrameStats::default();
+        let last = record(&mut stats, start, INTERVAL * 2, FRAME_HISTORY as u32);
+        record(&mut stats, last + INTERVAL, INTERVAL, FRAME_HISTORY as u32);
+        assert_eq!(stats.frames.len(), FRAME_HISTORY);
+        assert!((stats.fps().unwrap() - 60.0).abs() < 0.01);
+        assert_eq!(stats.cpu_time(), Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn estimates_dropped_frames() {
+        let mut stats = FrameStats::default();
+        let start = Instant::now();
+        let last = record(&mut stats, start, INTERVAL, 10);
+        assert_eq!(stats.dropped_frames, 0);
+
+        // Slightly late frames are not dropped
+        stats.record(last + INTERVAL * 14 / 10, Duration::ZERO);
+        assert_eq!(stats.dropped_frames, 0);
+
+        // Three vsyncs between two frames missed two
+        let last = stats.frames.back().unwrap().0;
+        stats.record(last + INTERVAL * 3, Duration::ZERO);
+        assert_eq!(stats.dropped_frames, 2);
+    }
+
+    #[test]
+    fn idle_gap_restarts_statistics() {
+        let mut stats = FrameStats::default();
+        let start = Instant::now();
+        let last = record(&mut stats, start, INTERVAL, 10);
+
+        // Redrawn on demand a second later
+        let resumed = last + Duration::from_secs(1);
+        stats.record(resumed, Duration::ZERO);
+        assert_eq!(stats.dropped_frames, 0);
+        assert_eq!(stats.frames.len(), 1);
+        assert_eq!(stats.fps(), None);
+
+        record(&mut stats, resumed + INTERVAL, INTERVAL, 5);
+        assert!((stats.fps().unwrap() - 60.0).abs() < 0.01);
+        assert_eq!(stats.dropped_frames, 0);
+    }
+}
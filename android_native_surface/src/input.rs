@@ -0,0 +1,315 @@
+//! Conversion of Java `MotionEvent`s, that Kotlin forwards from a surface wrapper, into a plain
+//! [`MotionEvent`] that scenes consume.
+//!
+//! `AMotionEvent_fromJava()` was introduced in API 31, while this crate only requires API 28.  Like
+//! [`crate::frame_rate`] it is looked up in `libandroid.so` at runtime, and the event is read
+//! through [`ndk::event::MotionEvent`].  On older devices the same values are read through JNI
+//! getters instead.
+//!
+//! Coordinates arrive in view pixels, which differ from the buffer pixels that are rendered to
+//! when the buffer size was fixed (e.g. with `SurfaceHolder.setFixedSize()`) and scaled by the
+//! compositor.  [`MotionEvent::scale()`] maps them back, per [`Sample`] so that it can be tested
+//! on the host, where the event itself does not exist.
+
+#[cfg(target_os = "android")]
+use std::{
+    ffi::c_void,
+    ptr::NonNull,
+    sync::{Once, OnceLock},
+};
+
+#[cfg(target_os = "android")]
+use jni::{
+    objects::{JObject, JValue},
+    JNIEnv,
+};
+#[cfg(target_os = "android")]
+use libloading::Library;
+#[cfg(target_os = "android")]
+use log::{debug, warn};
+#[cfg(target_os = "android")]
+use ndk::event::{Axis, MotionAction};
+
+/// `MotionEvent.AXIS_TILT`
+#[cfg(target_os = "android")]
+const JAVA_AXIS_TILT: i32 = 25;
+
+/// State of one pointer at one point in time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pointer {
+    /// Stable for as long as the pointer is down
+    pub id: i32,
+    pub x: f32,
+    pub y: f32,
+    /// Normally `0..=1`, but may exceed `1` depending on the calibration of the device
+    pub pressure: f32,
+    /// Radians from perpendicular to the screen, `0` for fingers and untilted styluses
+    pub tilt: f32,
+    /// Radians clockwise from vertical, of the touch area or the stylus
+    pub orientation: f32,
+}
+
+impl Pointer {
+    /// Reads the pointer with `id` through the `axis_value()` of an [`ndk::event::Pointer`] or
+    /// [`ndk::event::HistoricalPointer`], which share no trait.
+    #[cfg(target_os = "android")]
+    fn from_axes(id: i32, axis_value: impl Fn(Axis) -> f32) -> Self {
+        Self {
+            id,
+            x: axis_value(Axis::X),
+            y: axis_value(Axis::Y),
+            pressure: axis_value(Axis::Pressure),
+            tilt: axis_value(Axis::Tilt),
+            orientation: axis_value(Axis::Orientation),
+        }
+    }
+}
+
+/// Every pointer at one point in time
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    /// In the `CLOCK_MONOTONIC` time base
+    pub event_time_ns: i64,
+    pub pointers: Vec<Pointer>,
+}
+
+impl Sample {
+    /// Scales the coordinates of all pointers, see [`MotionEvent::scale()`].
+    pub fn scale(&mut self, scale_x: f32, scale_y: f32) {
+        for pointer in &mut self.pointers {
+            pointer.x *= scale_x;
+            pointer.y *= scale_y;
+        }
+    }
+}
+
+#[cfg(target_os = "android")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MotionEvent {
+    pub action: MotionAction,
+    /// Pointer that went down or up, for [`MotionAction::PointerDown`] and
+    /// [`MotionAction::PointerUp`]
+    pub action_pointer_id: i32,
+    /// Batched historical samples since the previous event, oldest first, followed by the
+    /// current sample
+    pub samples: Vec<Sample>,
+}
+
+#[cfg(target_os = "android")]
+impl MotionEvent {
+    /// Maps all coordinates from view pixels to buffer pixels.
+    pub fn scale(&mut self, scale_x: f32, scale_y: f32) {
+        for sample in &mut self.samples {
+            sample.scale(scale_x, scale_y);
+        }
+    }
+
+    /// Reads the Java `android.view.MotionEvent` `event`.
+    pub fn from_java(env: &mut JNIEnv<'_>, event: &JObject<'_>) -> jni::errors::Result<Self> {
+        if event.is_null() {
+            return Err(jni::errors::Error::NullPtr("event"));
+        }
+        match functions() {
+            Some(f) => {
+                // SAFETY: `event` is a non-null MotionEvent, which Java keeps alive for the
+                // duration of the native call.
+                let ptr = unsafe { (f.from_java)(env.get_raw().cast(), event.as_raw().cast()) };
+                let Some(ptr) = NonNull::new(ptr.cast_mut()) else {
+                    warn!("AMotionEvent_fromJava() failed, using JNI");
+                    return Self::from_java_getters(env, event);
+                };
+                let native = unsafe { ndk::event::MotionEvent::from_ptr(ptr.cast()) };
+                let result = Self::from_native(&native);
+                unsafe { (f.release)(ptr.as_ptr()) };
+                Ok(result)
+            }
+            None => {
+                static WARN: Once = Once::new();
+                WARN.call_once(|| debug!("AMotionEvent_fromJava() is unavailable, using JNI"));
+                Self::from_java_getters(env, event)
+            }
+        }
+    }
+
+    fn from_native(event: &ndk::event::MotionEvent) -> Self {
+        let mut samples = event
+            .history()
+            .map(|historical| Sample {
+                event_time_ns: historical.event_time(),
+                pointers: historical
+                    .pointers()
+                    .map(|p| Pointer::from_axes(p.pointer_id(), |axis| p.axis_value(axis)))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        samples.push(Sample {
+            event_time_ns: event.event_time(),
+            pointers: event
+                .pointers()
+                .map(|p| Pointer::from_axes(p.pointer_id(), |axis| p.axis_value(axis)))
+                .collect(),
+        });
+
+        Self {
+            action: event.action(),
+            action_pointer_id: event.pointer_at_index(event.pointer_index()).pointer_id(),
+            samples,
+        }
+    }
+
+    /// Fallback for [`MotionEvent::from_java()`] below API 31, with millisecond timestamps.
+    fn from_java_getters(env: &mut JNIEnv<'_>, event: &JObject<'_>) -> jni::errors::Result<Self> {
+        let int = |env: &mut JNIEnv<'_>, name, sig, args: &[JValue<'_, '_>]| {
+            env.call_method(event, name, sig, args)?.i()
+        };
+        let action = int(env, "getActionMasked", "()I", &[])?;
+        let action_index = int(env, "getActionIndex", "()I", &[])?;
+        let pointer_count = int(env, "getPointerCount", "()I", &[])?;
+        let history_size = int(env, "getHistorySize", "()I", &[])?;
+        let action_pointer_id = int(env, "getPointerId", "(I)I", &[action_index.into()])?;
+        let ids = (0..pointer_count)
+            .map(|i| int(env, "getPointerId", "(I)I", &[i.into()]))
+            .collect::<jni::errors::Result<Vec<_>>>()?;
+
+        // `None` reads the current sample, `Some(pos)` a historical one
+        let sample = |env: &mut JNIEnv<'_>, pos: Option<i32>| {
+            let mut float = |name: &str, i: i32| {
+                let value = match pos {
+                    Some(pos) => env.call_method(
+                        event,
+                        format!("getHistorical{name}"),
+                        "(II)F",
+                        &[i.into(), pos.into()],
+                    ),
+                    None => env.call_method(event, format!("get{name}"), "(I)F", &[i.into()]),
+                };
+                value?.f()
+            };
+            let mut pointers = Vec::with_capacity(ids.len());
+            for (i, &id) in (0..).zip(&ids) {
+                pointers.push(Pointer {
+                    id,
+                    x: float("X", i)?,
+                    y: float("Y", i)?,
+                    pressure: float("Pressure", i)?,
+                    tilt: 0.0,
+                    orientation: float("Orientation", i)?,
+                });
+            }
+            for (pointer, i) in pointers.iter_mut().zip(0..) {
+                pointer.tilt = match pos {
+                    Some(pos) => env.call_method(
+                        event,
+                        "getHistoricalAxisValue",
+                        "(III)F",
+                        &[JAVA_AXIS_TILT.into(), i.into(), pos.into()],
+                    ),
+                    None => env.call_method(
+                        event,
+                        "getAxisValue",
+                        "(II)F",
+                        &[JAVA_AXIS_TILT.into(), i.into()],
+                    ),
+                }?
+                .f()?;
+            }
+            let event_time_ms = match pos {
+                Some(pos) => {
+                    env.call_method(event, "getHistoricalEventTime", "(I)J", &[pos.into()])
+                }
+                None => env.call_method(event, "getEventTime", "()J", &[]),
+            }?
+            .j()?;
+            Ok(Sample {
+                event_time_ns: event_time_ms * 1_000_000,
+                pointers,
+            })
+        };
+        let samples = (0..history_size)
+            .map(Some)
+            .chain([None])
+            .map(|pos| sample(env, pos))
+            .collect::<jni::errors::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            action: MotionAction::from(action),
+            action_pointer_id,
+            samples,
+        })
+    }
+}
+
+#[cfg(target_os = "android")]
+type FromJavaFn =
+    unsafe extern "C" fn(env: *mut c_void, motion_event: *mut c_void) -> *const c_void;
+#[cfg(target_os = "android")]
+type ReleaseFn = unsafe extern "C" fn(event: *const c_void);
+
+/// `AMotionEvent_fromJava()` and `AInputEvent_release()`, which were introduced in API 31
+#[cfg(target_os = "android")]
+struct Functions {
+    from_java: FromJavaFn,
+    release: ReleaseFn,
+    /// Keeps the function pointers above alive
+    _lib: Library,
+}
+
+#[cfg(target_os = "android")]
+fn functions() -> Option<&'static Functions> {
+    static FUNCTIONS: OnceLock<Option<Functions>> = OnceLock::new();
+    FUNCTIONS
+        .get_or_init(|| {
+            // SAFETY: libandroid.so is already loaded by virtue of linking against the NDK, this
+            // only increments its refcount and doesn't run any (new) initializers.
+            let lib = unsafe { Library::new("libandroid.so") }
+                .inspect_err(|e| warn!("Failed to open libandroid.so: {e}"))
+                .ok()?;
+            // SAFETY: The signatures match the NDK headers.  Both were added in the same API
+            // level, so either both or none are present.
+            unsafe {
+                Some(Functions {
+                    from_java: *lib.get(b"AMotionEvent_fromJava\0").ok()?,
+                    release: *lib.get(b"AInputEvent_release\0").ok()?,
+                    _lib: lib,
+                })
+            }
+        })
+        .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pointer(id: i32, x: f32, y: f32) -> Pointer {
+        Pointer {
+            id,
+            x,
+            y,
+            pressure: 0.5,
+            tilt: 0.25,
+            orientation: 1.0,
+        }
+    }
+
+    #[test]
+    fn scale_maps_coordinates() {
+        let mut sample = Sample {
+            event_time_ns: 1_000,
+            pointers: vec![pointer(0, 100.0, 50.0), pointer(3, 0.0, 1080.0)],
+        };
+        // A 1080x2400 view of a buffer that was fixed to 540x1200
+        sample.scale(0.5, 0.5);
+        assert_eq!(
+            sample.pointers,
+            [pointer(0, 50.0, 25.0), pointer(3, 0.0, 540.0)]
+        );
+
+        sample.scale(2.0, 4.0);
+        assert_eq!(
+            sample.pointers,
+            [pointer(0, 100.0, 100.0), pointer(3, 0.0, 2160.0)]
+        );
+        assert_eq!(sample.event_time_ns, 1_000);
+    }
+}
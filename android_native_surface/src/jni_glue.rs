@@ -0,0 +1,441 @@
+//! Generates the `extern "system"` JNI exports from plain Rust functions with [`jni_export!`].
+//!
+//! Every export follows the same steps: open a trace [`Section`][ndk::trace::Section] named after
+//! the function, resolve the `long` handles that Java passes in to locked objects from their
+//! [`Registry`][crate::registry::Registry], run the function body, and map a null or stale handle
+//! to an `IllegalStateException`.  The return value is converted with [`IntoJava`] only after
+//! all objects are unlocked again, so that it may call back into Java.  A panic in any of these
+//! steps is thrown as a `RuntimeException`, rather than aborting the process at the FFI boundary.
+
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+use jni::{
+    objects::{JObject, JValue},
+    sys::{jboolean, jlong, jobject},
+    JNIEnv,
+};
+use log::{debug, error};
+
+use crate::{
+    recovery::Recovery,
+    registry::{self, Handle, Registry},
+    scene::{NodeId, Scene},
+    support::texture::Texture,
+    NativeGL, Window, NATIVE_GLS, SCENES, TEXTURES, WINDOWS,
+};
+
+/// Objects that Java refers to by a `long` handle into their [`Registry`]
+pub trait Registered: Sized + 'static {
+    fn registry() -> &'static Registry<Self>;
+}
+
+impl Registered for NativeGL {
+    fn registry() -> &'static Registry<Self> {
+        &NATIVE_GLS
+    }
+}
+
+impl Registered for Window {
+    fn registry() -> &'static Registry<Self> {
+        &WINDOWS
+    }
+}
+
+impl Registered for Texture {
+    fn registry() -> &'static Registry<Self> {
+        &TEXTURES
+    }
+}
+
+impl Registered for Scene {
+    fn registry() -> &'static Registry<Self> {
+        &SCENES
+    }
+}
+
+/// Context passed to [`IntoJava::into_java()`]
+pub struct JniCall<'a, 'local> {
+    pub env: &'a mut JNIEnv<'local>,
+    /// The Java object that the native method was called on
+    pub this: &'a JObject<'local>,
+    /// The window that the call operated on, if any
+    pub window: Option<Handle<Window>>,
+}
+
+/// Conversion of the value returned by a [`jni_export!`] function to what the native method
+/// returns to Java.
+pub trait IntoJava {
+    /// Also returned when an exception was thrown
+    type Java: Default;
+
+    fn into_java(self, call: &mut JniCall<'_, '_>) -> Self::Java;
+}
+
+impl IntoJava for () {
+    type Java = ();
+
+    fn into_java(self, _call: &mut JniCall<'_, '_>) {}
+}
+
+impl IntoJava for bool {
+    type Java = jboolean;
+
+    fn into_java(self, _call: &mut JniCall<'_, '_>) -> jboolean {
+        self.into()
+    }
+}
+
+/// Local reference to a Java object, returned from a native method
+#[repr(transparent)]
+pub struct JavaObject(jobject);
+
+/// `null`
+impl Default for JavaObject {
+    fn default() -> Self {
+        Self(ptr::null_mut())
+    }
+}
+
+/// A `java.util.TreeMap`, so that the keys stay sorted
+impl IntoJava for BTreeMap<String, String> {
+    type Java = JavaObject;
+
+    fn into_java(self, call: &mut JniCall<'_, '_>) -> JavaObject {
+        let map = call
+            .env
+            .new_object("java/util/TreeMap", "()V", &[])
+            .unwrap();
+        for (key, value) in self {
+            let key = call.env.new_string(key).unwrap();
+            let value = call.env.new_string(value).unwrap();
+            call.env
+                .call_method(
+                    &map,
+                    "put",
+                    "(Ljava/lang/Object;Ljava/lang/Object;)Ljava/lang/Object;",
+                    &[JValue::Object(&key), JValue::Object(&value)],
+                )
+                .unwrap();
+            call.env.delete_local_ref(key).unwrap();
+            call.env.delete_local_ref(value).unwrap();
+        }
+        JavaObject(map.into_raw())
+    }
+}
+
+impl IntoJava for NodeId {
+    type Java = jlong;
+
+    fn into_java(self, _call: &mut JniCall<'_, '_>) -> jlong {
+        self.into_raw()
+    }
+}
+
+/// Java takes ownership through the returned handle
+impl<T: Registered> IntoJava for T {
+    type Java = jlong;
+
+    fn into_java(self, _call: &mut JniCall<'_, '_>) -> jlong {
+        T::registry().insert(self).into_raw()
+    }
+}
+
+/// Throws a Java `RuntimeException` for errors that are caused by the arguments or the device,
+/// rather than by a bug.  An exception that is already pending, such as the one that
+/// [`check_argument()`] threw, is kept instead.
+impl<T: IntoJava, E: fmt::Display> IntoJava for Result<T, E> {
+    type Java = T::Java;
+
+    fn into_java(self, call: &mut JniCall<'_, '_>) -> T::Java {
+        match self {
+            Ok(value) => value.into_java(call),
+            Err(e) => {
+                if !call.env.exception_check().unwrap() {
+                    call.env
+                        .throw_new("java/lang/RuntimeException", e.to_string())
+                        .unwrap();
+                }
+                T::Java::default()
+            }
+        }
+    }
+}
+
+/// Notifies the wrapper through its `onRecovery()` callback.  For [`Recovery::DropWindow`] the
+/// window is removed first, and its handle is no longer valid.
+impl IntoJava for Option<Recovery> {
+    type Java = ();
+
+    fn into_java(self, call: &mut JniCall<'_, '_>) {
+        let Some(recovery) = self else {
+            return;
+        };
+
+        if recovery == Recovery::DropWindow {
+            let window = WINDOWS.remove(call.window.expect("Recovery without a window"));
+            if let Some(window) = throw_on_err(call.env, window) {
+                debug!("Dropped dead window {window:?}");
+            }
+        }
+
+        call.env
+            .call_method(
+                call.this,
+                "onRecovery",
+                "(I)V",
+                &[JValue::Int(recovery as i32)],
+            )
+            .unwrap();
+    }
+}
+
+/// Throws a Java `IllegalStateException` for a null or stale handle, returning [`None`] so that
+/// the caller can bail out.
+pub fn throw_on_err<T>(env: &mut JNIEnv, result: registry::Result<T>) -> Option<T> {
+    result
+        .inspect_err(|e| {
+            env.throw_new("java/lang/IllegalStateException", e.to_string())
+                .unwrap()
+        })
+        .ok()
+}
+
+/// Throws a Java `IllegalArgumentException` for a value that Java passed in but that is unknown
+/// here, such as an enum value that only exists on the Kotlin side.  `what` names the argument.
+pub fn check_argument<T, E: fmt::Display>(
+    env: &mut JNIEnv,
+    what: &str,
+    result: Result<T, E>,
+) -> jni::errors::Result<T> {
+    result.map_err(|e| {
+        env.throw_new(
+            "java/lang/IllegalArgumentException",
+            format!("Unknown {what} {e}"),
+        )
+        .unwrap();
+        jni::errors::Error::JavaException
+    })
+}
+
+/// Runs the steps of the [`jni_export!`] function `name`, throwing a Java `RuntimeException` if
+/// they panic.  An exception that is already pending, e.g. from the JNI call that failed, is kept.
+pub fn catch_panic<'local, J: Default>(
+    env: &mut JNIEnv<'local>,
+    name: &str,
+    f: impl FnOnce(&mut JNIEnv<'local>) -> J,
+) -> J {
+    match panic::catch_unwind(AssertUnwindSafe(|| f(&mut *env))) {
+        Ok(value) => value,
+        Err(payload) => {
+            let message = panic_message(&*payload);
+            error!("{name} panicked: {message}");
+            if !env.exception_check().unwrap() {
+                env.throw_new(
+                    "java/lang/RuntimeException",
+                    format!("{name} panicked: {message}"),
+                )
+                .unwrap();
+            }
+            J::default()
+        }
+    }
+}
+
+/// The message that was passed to [`panic!()`], if it was formatted to a string
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload
+            .downcast_ref::<String>()
+            .map_or("Box<dyn Any>", String::as_str),
+    }
+}
+
+/// Final step of every [`jni_export!`] function, after all objects are unlocked
+pub fn finish<'local, R: IntoJava>(
+    env: &mut JNIEnv<'local>,
+    this: &JObject<'local>,
+    window: Option<Handle<Window>>,
+    result: registry::Result<R>,
+) -> R::Java {
+    match throw_on_err(env, result) {
+        Some(value) => value.into_java(&mut JniCall { env, this, window }),
+        None => R::Java::default(),
+    }
+}
+
+/// Defines a JNI export from a plain Rust function, see the [module docs][self].
+///
+/// The first two parameters name the `JNIEnv` and `this` object, which are in scope of the body as
+/// `&mut JNIEnv` and `&JObject`.  They are followed by the objects to resolve from a `long` handle,
+/// which must be one of:
+///
+/// - `gl: &mut NativeGL, window: &mut Window`, locked in that order;
+/// - `gl: &mut NativeGL`;
+/// - `window: &mut Window`;
+/// - `object: &mut Type`, which locks an object of any other [`Registered`] type;
+/// - `object: Type`, which removes an object of a [`Registered`] type from its registry.
+///
+/// Any remaining parameters are passed through from Java as-is.  The return type must implement
+/// [`IntoJava`].  The export still needs to be listed in [`crate::bindings`].
+macro_rules! jni_export {
+    (@ret) => { () };
+    (@ret $ret:ty) => { $ret };
+
+    (
+        $(#[$meta:meta])*
+        fn $name:ident($env:ident, $this:ident, $gl:ident: &mut NativeGL, $window:ident: &mut Window $(, $arg:ident: $ty:ty)*) $(-> $ret:ty)? $body:block
+    ) => {
+        $(#[$meta])*
+        extern "system" fn $name<'local>(
+            mut env: ::jni::JNIEnv<'local>,
+            this: ::jni::objects::JObject<'local>,
+            $gl: ::jni::sys::jlong,
+            $window: ::jni::sys::jlong,
+            $($arg: $ty),*
+        ) -> <jni_export!(@ret $($ret)?) as $crate::jni_glue::IntoJava>::Java {
+            let _t = ::ndk::trace::Section::new(stringify!($name)).unwrap();
+            ::log::trace!("{} on {this:?}", stringify!($name));
+
+            $crate::jni_glue::catch_panic(&mut env, stringify!($name), |env| {
+                let window = $crate::registry::Handle::from_raw($window);
+                let result = $crate::NATIVE_GLS
+                    .with($crate::registry::Handle::from_raw($gl), |$gl| {
+                        $crate::WINDOWS.with(window, |$window| {
+                            #[allow(unused_variables)]
+                            let $env = &mut *env;
+                            #[allow(unused_variables)]
+                            let $this = &this;
+                            let result: jni_export!(@ret $($ret)?) = $body;
+                            result
+                        })
+                    })
+                    .and_then(|r| r);
+                $crate::jni_glue::finish(env, &this, Some(window), result)
+            })
+        }
+    };
+
+    (
+        $(#[$meta:meta])*
+        fn $name:ident($env:ident, $this:ident, $gl:ident: &mut NativeGL $(, $arg:ident: $ty:ty)*) $(-> $ret:ty)? $body:block
+    ) => {
+        $(#[$meta])*
+        extern "system" fn $name<'local>(
+            mut env: ::jni::JNIEnv<'local>,
+            this: ::jni::objects::JObject<'local>,
+            $gl: ::jni::sys::jlong,
+            $($arg: $ty),*
+        ) -> <jni_export!(@ret $($ret)?) as $crate::jni_glue::IntoJava>::Java {
+            let _t = ::ndk::trace::Section::new(stringify!($name)).unwrap();
+            ::log::trace!("{} on {this:?}", stringify!($name));
+
+            $crate::jni_glue::catch_panic(&mut env, stringify!($name), |env| {
+                let result = $crate::NATIVE_GLS.with($crate::registry::Handle::from_raw($gl), |$gl| {
+                    #[allow(unused_variables)]
+                    let $env = &mut *env;
+                    #[allow(unused_variables)]
+                    let $this = &this;
+                    let result: jni_export!(@ret $($ret)?) = $body;
+                    result
+                });
+                $crate::jni_glue::finish(env, &this, None, result)
+            })
+        }
+    };
+
+    (
+        $(#[$meta:meta])*
+        fn $name:ident($env:ident, $this:ident, $window:ident: &mut Window $(, $arg:ident: $ty:ty)*) $(-> $ret:ty)? $body:block
+    ) => {
+        $(#[$meta])*
+        extern "system" fn $name<'local>(
+            mut env: ::jni::JNIEnv<'local>,
+            this: ::jni::objects::JObject<'local>,
+            $window: ::jni::sys::jlong,
+            $($arg: $ty),*
+        ) -> <jni_export!(@ret $($ret)?) as $crate::jni_glue::IntoJava>::Java {
+            let _t = ::ndk::trace::Section::new(stringify!($name)).unwrap();
+            ::log::trace!("{} on {this:?}", stringify!($name));
+
+            $crate::jni_glue::catch_panic(&mut env, stringify!($name), |env| {
+                let window = $crate::registry::Handle::from_raw($window);
+                let result = $crate::WINDOWS.with(window, |$window| {
+                    #[allow(unused_variables)]
+                    let $env = &mut *env;
+                    #[allow(unused_variables)]
+                    let $this = &this;
+                    let result: jni_export!(@ret $($ret)?) = $body;
+                    result
+                });
+                $crate::jni_glue::finish(env, &this, Some(window), result)
+            })
+        }
+    };
+
+    (
+        $(#[$meta:meta])*
+        fn $name:ident($env:ident, $this:ident, $object:ident: &mut $object_ty:ident $(, $arg:ident: $ty:ty)*) $(-> $ret:ty)? $body:block
+    ) => {
+        $(#[$meta])*
+        extern "system" fn $name<'local>(
+            mut env: ::jni::JNIEnv<'local>,
+            this: ::jni::objects::JObject<'local>,
+            $object: ::jni::sys::jlong,
+            $($arg: $ty),*
+        ) -> <jni_export!(@ret $($ret)?) as $crate::jni_glue::IntoJava>::Java {
+            let _t = ::ndk::trace::Section::new(stringify!($name)).unwrap();
+            ::log::trace!("{} on {this:?}", stringify!($name));
+
+            $crate::jni_glue::catch_panic(&mut env, stringify!($name), |env| {
+                let registry = <$object_ty as $crate::jni_glue::Registered>::registry();
+                let result = registry.with($crate::registry::Handle::from_raw($object), |$object| {
+                    #[allow(unused_variables)]
+                    let $env = &mut *env;
+                    #[allow(unused_variables)]
+                    let $this = &this;
+                    let result: jni_export!(@ret $($ret)?) = $body;
+                    result
+                });
+                $crate::jni_glue::finish(env, &this, None, result)
+            })
+        }
+    };
+
+    (
+        $(#[$meta:meta])*
+        fn $name:ident($env:ident, $this:ident, $object:ident: $object_ty:ident $(, $arg:ident: $ty:ty)*) $(-> $ret:ty)? $body:block
+    ) => {
+        $(#[$meta])*
+        extern "system" fn $name<'local>(
+            mut env: ::jni::JNIEnv<'local>,
+            this: ::jni::objects::JObject<'local>,
+            $object: ::jni::sys::jlong,
+            $($arg: $ty),*
+        ) -> <jni_export!(@ret $($ret)?) as $crate::jni_glue::IntoJava>::Java {
+            let _t = ::ndk::trace::Section::new(stringify!($name)).unwrap();
+            ::log::trace!("{} on {this:?}", stringify!($name));
+
+            $crate::jni_glue::catch_panic(&mut env, stringify!($name), |env| {
+                let registry = <$object_ty as $crate::jni_glue::Registered>::registry();
+                let result = registry
+                    .remove($crate::registry::Handle::from_raw($object))
+                    .map(|$object| {
+                        #[allow(unused_variables)]
+                        let $env = &mut *env;
+                        #[allow(unused_variables)]
+                        let $this = &this;
+                        let result: jni_export!(@ret $($ret)?) = $body;
+                        result
+                    });
+                $crate::jni_glue::finish(env, &this, None, result)
+            })
+        }
+    };
+}
@@ -1,40 +1,223 @@
+// Most of the crate is only reachable through the JNI exports, which only exist on Android.  The
+// rest is still built on the host, to be unit tested there.
+#![cfg_attr(not(target_os = "android"), allow(dead_code))]
+
+#[cfg(target_os = "android")]
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::{self, BufRead, BufReader},
-    thread,
+    collections::{BTreeMap, HashMap},
+    io,
+    sync::{Arc, Mutex},
+    time::Instant,
 };
 
-use android_logger::FilterBuilder;
+#[cfg(target_os = "android")]
 use glutin::{
     config::Config,
-    context::{ContextApi, ContextAttributesBuilder, NotCurrentContext},
+    context::{NotCurrentContext, PossiblyCurrentContext},
     display::Display,
+    error::ErrorKind,
     prelude::*,
 };
+#[cfg(target_os = "android")]
 use jni::{
-    objects::{JClass, JObject},
+    objects::{JClass, JFloatArray, JIntArray, JLongArray, JObject, JString},
+    sys::{jboolean, jfloat, jint, jlong, JNI_FALSE, JNI_TRUE},
     JNIEnv,
 };
-use log::{debug, info, LevelFilter};
+#[cfg(target_os = "android")]
+use log::{debug, error, info, warn};
+#[cfg(target_os = "android")]
 use ndk::{
     hardware_buffer_format::HardwareBufferFormat, native_window::NativeWindow,
-    surface_texture::SurfaceTexture, trace::Section,
+    surface_texture::SurfaceTexture,
 };
+#[cfg(target_os = "android")]
 use raw_window_handle::DisplayHandle;
 
+mod assets;
+#[cfg(target_os = "android")]
+mod bindings;
+#[cfg(target_os = "android")]
+mod bitmap;
+mod color;
+mod debug_output;
+mod deletion_queue;
+mod frame_rate;
+mod gpu_timer;
+mod hud;
+mod input;
+#[cfg(target_os = "android")]
+#[macro_use]
+mod jni_glue;
+#[cfg(target_os = "android")]
+mod logger;
+mod msaa;
+mod pixel;
+mod post;
+mod recovery;
+mod registry;
+mod scene;
+mod software;
+mod stdio_capture;
 mod support;
+mod text;
+mod trace;
+
+#[cfg(target_os = "android")]
+use deletion_queue::DeletionQueue;
+#[cfg(target_os = "android")]
+use frame_rate::{ChangeFrameRateStrategy, FrameRateCompatibility};
+#[cfg(target_os = "android")]
+use jni_glue::{check_argument, throw_on_err, IntoJava as _, JniCall};
+#[cfg(target_os = "android")]
+use recovery::{FaultInjector, Recover, Recovery};
+#[cfg(target_os = "android")]
+use registry::{Handle, Registry};
+#[cfg(target_os = "android")]
+use scene::{Fill, Node, NodeId, Rect, Scene, SceneError, Transform};
+#[cfg(target_os = "android")]
+use stdio_capture::StdioCapture;
+#[cfg(target_os = "android")]
+use support::{
+    capabilities::{self, Capabilities},
+    texture::Texture,
+};
+#[cfg(target_os = "android")]
+use trace::Section;
+
+/// How a [`Window`] is rendered to, selected per window from Kotlin
+#[cfg(target_os = "android")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    Gl = 0,
+    Software = 1,
+}
+
+#[cfg(target_os = "android")]
+impl TryFrom<i32> for Backend {
+    type Error = i32;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Gl),
+            1 => Ok(Self::Software),
+            x => Err(x),
+        }
+    }
+}
+
+/// The object stored in `mNative` of every surface wrapper on the Java side
+#[cfg(target_os = "android")]
+#[derive(Debug)]
+enum Window {
+    /// Boxed as it is much larger than a software window
+    Gl(Box<support::GlWindow>),
+    Software(software::SoftwareWindow),
+}
+
+#[cfg(target_os = "android")]
+impl Window {
+    fn native_window(&self) -> &NativeWindow {
+        match self {
+            Self::Gl(gl_window) => &gl_window.window,
+            Self::Software(software_window) => &software_window.window,
+        }
+    }
+
+    fn set_hud_enabled(&mut self, enabled: bool) {
+        match self {
+            Self::Gl(gl_window) => gl_window.hud.enabled = enabled,
+            Self::Software(_) => warn!("The HUD is only drawn over GL windows"),
+        }
+    }
+
+    /// Delivers `event` to the attached scene, with coordinates mapped from a view of
+    /// `view_width` x `view_height` to the buffer of the window.  Returns whether a scene
+    /// received it.
+    fn handle_motion(
+        &mut self,
+        mut event: input::MotionEvent,
+        view_width: i32,
+        view_height: i32,
+    ) -> bool {
+        let Self::Gl(gl_window) = self else {
+            return false;
+        };
+        let Some(scene) = gl_window.scene else {
+            return false;
+        };
+
+        let (width, height) = (gl_window.window.width(), gl_window.window.height());
+        if view_width > 0 && view_height > 0 && (width, height) != (view_width, view_height) {
+            event.scale(
+                width as f32 / view_width as f32,
+                height as f32 / view_height as f32,
+            );
+        }
+        SCENES
+            .with(scene, |scene| scene.handle_motion(&event))
+            .is_ok()
+    }
+
+    fn set_msaa_samples(&mut self, samples: u32) {
+        match self {
+            Self::Gl(gl_window) => gl_window.msaa.set_samples(samples),
+            Self::Software(_) => warn!("MSAA is only supported on GL windows"),
+        }
+    }
+
+    fn set_post_passes(&mut self, passes: Vec<post::Pass>) {
+        match self {
+            Self::Gl(gl_window) => gl_window.post.passes = passes,
+            Self::Software(_) => warn!("Post-processing is only supported on GL windows"),
+        }
+    }
+
+    fn set_scene(&mut self, scene: Option<Handle<Scene>>) {
+        match self {
+            Self::Gl(gl_window) => gl_window.scene = scene,
+            Self::Software(_) => warn!("Scenes are only drawn on GL windows"),
+        }
+    }
+
+    /// Hint the compositor about the rate at which this window will be rendered to.
+    fn set_frame_rate(
+        &self,
+        frame_rate: f32,
+        compatibility: FrameRateCompatibility,
+        change_frame_rate_strategy: ChangeFrameRateStrategy,
+    ) -> io::Result<()> {
+        frame_rate::set_frame_rate(
+            self.native_window(),
+            frame_rate,
+            compatibility,
+            change_frame_rate_strategy,
+        )
+    }
+}
 
+#[cfg(target_os = "android")]
 struct NativeGL {
     gl_display: Display,
     // TODO: HardwareBufferFormat does not derive Hash?
     gl_contexts: HashMap</*HardwareBufferFormat*/ i32, (Option<NotCurrentContext>, Config)>,
     /// Lazy-initialized when the first context+surface is made current
     renderer: Option<support::Renderer>,
+    /// Queried when the first context was created, and checked to be sufficient
+    capabilities: Capabilities,
+    /// Fails the next `swap_buffers()`, see [`NativeGL::inject_fault()`]
+    faults: FaultInjector,
+    /// Cloned into every [`support::GlWindow`], whose surfaces must not outlive `gl_display`
+    gl_windows: Arc<()>,
+    /// GL objects that were dropped while no context was current
+    deletion_queue: DeletionQueue,
+    /// Whether contexts are created with `EGL_CONTEXT_OPENGL_DEBUG`
+    debug: bool,
 }
 
+#[cfg(target_os = "android")]
 impl NativeGL {
-    fn new() -> Self {
+    fn new(debug: bool) -> capabilities::Result<Self> {
         let _t = Section::new("Gl::new()").unwrap();
 
         // TODO: EGL can update the format of the window by choosing a different format,
@@ -45,61 +228,127 @@ impl NativeGL {
         let display_handle = DisplayHandle::android();
 
         let gl_display = support::create_display(display_handle);
+        debug_output::enable_egl_debug_output(&gl_display, debug);
 
         let template = support::config_template(format);
         let gl_config = unsafe {
             gl_display
                 .find_configs(template)
                 .unwrap()
-                .reduce(|accum, config| {
-                    // Find the config with the maximum number of samples.
-                    //
-                    // In general if you're not sure what you want in template you can request or
-                    // don't want to require multisampling for example, you can search for a
-                    // specific option you want afterwards.
-                    //
-                    // XXX however on macOS you can request only one config, so you should do
-                    // a search with the help of `find_configs` and adjusting your template.
-                    if config.num_samples() > accum.num_samples() {
-                        config
-                    } else {
-                        accum
-                    }
-                })
+                // Prefer a single-sampled surface, MSAA is opted into per window through an
+                // offscreen framebuffer instead (see msaa.rs)
+                .min_by_key(|config| config.num_samples())
                 .unwrap()
         };
 
-        println!(
+        info!(
             "Picked a config with {} samples, {:?}, alpha: {}",
             gl_config.num_samples(),
             gl_config.color_buffer_type(),
             gl_config.alpha_size()
         );
 
-        // The context creation part. It can be created before surface and that's how
-        // it's expected in multithreaded + multiwindow operation mode, since you
-        // can send NotCurrentContext, but not Surface.
-        let context_attributes = ContextAttributesBuilder::new().build(None);
-
-        // Since glutin by default tries to create OpenGL core context, which may not be
-        // present we should try gles.
-        let fallback_context_attributes = ContextAttributesBuilder::new()
-            .with_context_api(ContextApi::Gles(None))
-            .build(None);
-        let gl_context = unsafe {
-            gl_display
-                .create_context(&gl_config, &context_attributes)
-                .unwrap_or_else(|_| {
-                    gl_display
-                        .create_context(&gl_config, &fallback_context_attributes)
-                        .expect("failed to create context")
-                })
-        };
+        let gl_context = support::create_context(&gl_display, &gl_config, debug);
+
+        // Reject contexts that the renderer cannot run on before Java holds on to them
+        let NotCurrentContext::Egl(gl_context) = gl_context;
+        let gl_context = gl_context
+            .make_current_surfaceless()
+            .map_err(|e| e.error_kind())?;
+        let capabilities =
+            unsafe { Capabilities::query(&support::load_gl(&gl_display), &gl_display) };
+        let gl_context =
+            NotCurrentContext::Egl(gl_context.make_not_current().map_err(|e| e.error_kind())?);
+        let capabilities = capabilities?;
+        info!("Running on {capabilities}");
 
-        Self {
+        Ok(Self {
             gl_display,
             gl_contexts: std::iter::once((format.into(), (Some(gl_context), gl_config))).collect(),
             renderer: None,
+            capabilities,
+            faults: FaultInjector::default(),
+            gl_windows: Arc::new(()),
+            deletion_queue: DeletionQueue::default(),
+            debug,
+        })
+    }
+
+    /// Number of [`support::GlWindow`]s created by [`NativeGL::create_gl_window()`] that are
+    /// still alive.
+    fn live_gl_windows(&self) -> usize {
+        Arc::strong_count(&self.gl_windows) - 1
+    }
+
+    /// Deletes all GL objects, releases all contexts and terminates the display.
+    ///
+    /// Fails without terminating the display if [`NativeGL::live_gl_windows()`] is not `0`, as
+    /// their surfaces would otherwise be left dangling.
+    fn destroy(mut self) -> Result<(), String> {
+        let _t = Section::new("Gl::destroy()").unwrap();
+        let live_gl_windows = self.live_gl_windows();
+        if live_gl_windows > 0 {
+            return Err(format!("{live_gl_windows} GlWindows are still alive"));
+        }
+        debug!("Destroying {} contexts", self.gl_contexts.len());
+
+        if let Some(renderer) = self.renderer.take() {
+            let _t = Section::new("Renderer teardown").unwrap();
+            // All objects live in the context that the renderer was first created on
+            let format = HardwareBufferFormat::R8G8B8X8_UNORM;
+            let (gl_context_rc, _gl_config) = self
+                .gl_contexts
+                .get_mut(&format.into())
+                .expect("No context/config for format");
+            let NotCurrentContext::Egl(gl_context) = gl_context_rc.take().expect("Didn't put back");
+
+            let gl = renderer.gl().clone();
+            // Queues the renderer objects for deletion
+            drop(renderer);
+
+            // There are no windows left, so bind the context without a surface
+            match gl_context.make_current_surfaceless() {
+                Ok(gl_context) => {
+                    let deleted = unsafe { self.deletion_queue.drain(&gl) };
+                    debug!("Deleted {deleted} GL objects");
+                    *gl_context_rc = Some(NotCurrentContext::Egl(
+                        gl_context
+                            .make_not_current()
+                            .expect("Cannot uncurrent GL context"),
+                    ));
+                }
+                Err(e) => {
+                    // The objects are freed with the context regardless
+                    warn!("Cannot make context current to delete GL objects: {e}");
+                }
+            }
+        }
+
+        let undeleted = self.deletion_queue.discard();
+        let alive = self.deletion_queue.alive();
+        if undeleted > 0 || alive > 0 {
+            warn!("Leaked {undeleted} dropped but undeleted and {alive} still-alive GL objects");
+        }
+
+        for (format, (gl_context, _gl_config)) in self.gl_contexts.drain() {
+            debug!("Releasing context for format {format}");
+            drop(gl_context);
+        }
+
+        let Display::Egl(gl_display) = self.gl_display;
+        // SAFETY: We're the only user of EGL in this process, and no objects are left that
+        // reference this display.
+        unsafe { gl_display.terminate() };
+        Ok(())
+    }
+
+    fn create_window(&mut self, window: NativeWindow, backend: Backend) -> Window {
+        match backend {
+            Backend::Gl => Window::Gl(Box::new(self.create_gl_window(window))),
+            Backend::Software => {
+                debug!("Add software window {window:?}");
+                Window::Software(software::SoftwareWindow::new(window))
+            }
         }
     }
 
@@ -116,240 +365,938 @@ impl NativeGL {
             .expect("No context/config for format");
 
         // Create a wrapper for GL window and surface.
-        support::GlWindow::from_existing(&self.gl_display, window, gl_config)
+        support::GlWindow::from_existing(
+            &self.gl_display,
+            window,
+            gl_config,
+            self.gl_windows.clone(),
+        )
+    }
+
+    /// Recreates the surface of `window` with the sRGB or the linear colorspace.
+    fn set_srgb(&mut self, window: &mut Window, srgb: bool) -> glutin::error::Result<()> {
+        let Window::Gl(gl_window) = window else {
+            warn!("sRGB is only supported on GL windows");
+            return Ok(());
+        };
+        debug!("Set sRGB on {gl_window:?} to {srgb}");
+        let format = HardwareBufferFormat::R8G8B8X8_UNORM;
+        let (_gl_context_rc, gl_config) = &self.gl_contexts[&format.into()];
+        gl_window.set_srgb(&self.gl_display, gl_config, srgb)
+    }
+
+    /// Make the next `swap_buffers()` fail with the error that triggers `recovery`.
+    fn inject_fault(&mut self, recovery: Recovery) {
+        warn!("Injecting fault for {recovery:?}");
+        self.faults.inject(recovery);
     }
 
-    fn render_to_gl_window(&mut self, gl_window: &support::GlWindow) {
+    /// Returns the [`Recovery`] that was performed if rendering failed.  For
+    /// [`Recovery::DropWindow`] the caller is responsible for dropping `window`.  Errors that no
+    /// [`Recovery`] applies to are returned as is.
+    fn render_to_window(&mut self, window: &mut Window) -> Result<Option<Recovery>, ErrorKind> {
+        match window {
+            Window::Gl(gl_window) => self.render_to_gl_window(gl_window),
+            Window::Software(software_window) => {
+                let _t = Section::new("Software::render()").unwrap();
+                software_window.render();
+                Ok(None)
+            }
+        }
+    }
+
+    fn render_to_gl_window(
+        &mut self,
+        gl_window: &mut support::GlWindow,
+    ) -> Result<Option<Recovery>, ErrorKind> {
         debug!("Render to window {gl_window:?}");
         let _t = Section::new("Gl::render_to_window()").unwrap();
 
-        let (gl_context_rc, gl_context, gl_window, renderer) = {
-            let _t = Section::new("Preparation").unwrap();
+        // TODO: Lazy-init more configs!
+        // let format = window.format();
+        let format = HardwareBufferFormat::R8G8B8X8_UNORM;
 
-            // TODO: Lazy-init more configs!
-            // let format = window.format();
-            let format = HardwareBufferFormat::R8G8B8X8_UNORM;
-            let (gl_context_rc, _gl_config) = self
-                .gl_contexts
-                .get_mut(&format.into())
-                .expect("No context/config for format");
-            let gl_context = gl_context_rc.take().expect("Didn't put back");
+        // Treat it as current without calling into EGL, so that we retain ownership of the
+        // context when make_current() fails.
+        let gl_context = self
+            .gl_contexts
+            .get_mut(&format.into())
+            .expect("No context/config for format")
+            .0
+            .take()
+            .expect("Didn't put back")
+            .treat_as_possibly_current();
+
+        let result = self.try_render_to_gl_window(&gl_context, gl_window);
+
+        let result = {
+            let _t = Section::new("make_not_current").unwrap();
+            // Always release the context, even when rendering failed
+            match gl_context.make_not_current() {
+                Ok(gl_context) => {
+                    self.gl_contexts.get_mut(&format.into()).unwrap().0 = Some(gl_context);
+                    result
+                }
+                // The context is dropped here, and recreated in recover() below
+                Err(e) => result.and(Err(e.error_kind())),
+            }
+        };
+
+        let Err(kind) = result else {
+            return Ok(None);
+        };
+        let Some(recovery) = Recovery::from_error_kind(kind) else {
+            error!("Rendering to {gl_window:?} failed with unrecoverable {kind:?}");
+            // Keep the next frame from finding no context at all
+            if self.gl_contexts[&format.into()].0.is_none() {
+                self.recreate_context(format);
+            }
+            return Err(kind);
+        };
+        warn!("Rendering to {gl_window:?} failed with {kind:?}, recovering with {recovery:?}");
+        Ok(Some(self.recover(format, gl_window, recovery)))
+    }
+
+    fn try_render_to_gl_window(
+        &mut self,
+        gl_context: &PossiblyCurrentContext,
+        gl_window: &mut support::GlWindow,
+    ) -> Result<(), ErrorKind> {
+        let frame_start = Instant::now();
+        let frame_id = trace::next_frame_id();
+        // Until the frame is submitted, overlapping with the frames of other windows
+        let _frame = trace::AsyncSection::begin("frame", frame_id);
+        let renderer = {
+            let _t = Section::new("Preparation").unwrap();
 
             // Make it current and load symbols.
-            let gl_context = gl_context.make_current(&gl_window.surface).unwrap();
+            gl_context
+                .make_current(gl_window.surface()?)
+                .map_err(|e| e.error_kind())?;
 
             let renderer = self.renderer.get_or_insert_with(|| {
                 let _t = Section::new("Renderer setup").unwrap();
-                support::Renderer::new(&self.gl_display)
+                support::Renderer::new(
+                    &self.gl_display,
+                    &self.deletion_queue,
+                    self.capabilities.clone(),
+                )
             });
 
-            (gl_context_rc, gl_context, gl_window, renderer)
+            // Now that the context is current, delete whatever was dropped in the meantime
+            let deleted = unsafe { self.deletion_queue.drain(renderer.gl()) };
+            if deleted > 0 {
+                debug!("Deleted {deleted} queued GL objects");
+            }
+            trace::set_counter("GL deletion queue", deleted as i64);
+
+            renderer
         };
 
+        let (width, height) = (gl_window.window.width(), gl_window.window.height());
+        let srgb = gl_window.srgb();
         {
             let _t = Section::new("resize").unwrap();
-            renderer.resize(gl_window.window.width(), gl_window.window.height());
+            renderer.resize(width, height);
         }
 
+        // Where the window is drawn: the surface, or the input of the post-processing passes
+        let destination = {
+            let _t = Section::new("targets").unwrap();
+            let post = gl_window
+                .post
+                .begin(renderer, &self.deletion_queue, width, height, srgb);
+            let (destination, format) = post.map_or_else(
+                || {
+                    let config = gl_window.config();
+                    let format = msaa::surface_format(
+                        config.color_buffer_type(),
+                        config.alpha_size(),
+                        config.float_pixels(),
+                        srgb,
+                    );
+                    (0, format)
+                },
+                |(destination, format)| (destination, Some(format)),
+            );
+            gl_window.msaa.begin(
+                renderer,
+                &self.deletion_queue,
+                width,
+                height,
+                destination,
+                format,
+            );
+            destination
+        };
+
         {
             let _t = Section::new("draw").unwrap();
-            renderer.draw();
+            gl_window
+                .gpu_timer
+                .begin(renderer, &self.deletion_queue, "draw");
+            renderer.draw(srgb);
+            gl_window.gpu_timer.end(renderer);
         }
 
+        if let Some(scene) = gl_window.scene {
+            let _t = Section::new("scene").unwrap();
+            gl_window
+                .gpu_timer
+                .begin(renderer, &self.deletion_queue, "scene");
+            let draw_calls = SCENES.with(scene, |scene| {
+                renderer
+                    .scene()
+                    .draw(scene, width, height, srgb, |texture| {
+                        TEXTURES
+                            .with(texture, |texture| texture.name(&self.deletion_queue))
+                            .ok()
+                            .flatten()
+                    })
+            });
+            gl_window.gpu_timer.end(renderer);
+            match draw_calls {
+                Ok(draw_calls) => trace::set_counter("scene draw calls", draw_calls as i64),
+                Err(e) => {
+                    warn!("Detaching destroyed scene from {gl_window:?}: {e}");
+                    gl_window.scene = None;
+                }
+            }
+        }
+
+        if gl_window.msaa.active().is_some() {
+            let _t = Section::new("msaa resolve").unwrap();
+            gl_window
+                .gpu_timer
+                .begin(renderer, &self.deletion_queue, "resolve");
+            gl_window.msaa.resolve(renderer, destination);
+            gl_window.gpu_timer.end(renderer);
+        }
+
+        if !gl_window.post.passes.is_empty() {
+            let _t = Section::new("post").unwrap();
+            gl_window
+                .gpu_timer
+                .begin(renderer, &self.deletion_queue, "post");
+            let passes = renderer
+                .post()
+                .run(&gl_window.post, width, height, |texture| {
+                    TEXTURES
+                        .with(texture, |texture| texture.name(&self.deletion_queue))
+                        .ok()
+                        .flatten()
+                });
+            gl_window.gpu_timer.end(renderer);
+            trace::set_counter("post passes", passes as i64);
+        }
+
+        // Drawn over the surface after resolving and post-processing, to stay legible
+        if gl_window.hud.enabled {
+            let _t = Section::new("hud").unwrap();
+            let hud_text = gl_window.hud.text(
+                &gl_window.window,
+                gl_window.config(),
+                renderer.capabilities(),
+                &gl_window.gpu_timer,
+                &gl_window.msaa,
+                srgb,
+            );
+            gl_window
+                .gpu_timer
+                .begin(renderer, &self.deletion_queue, "hud");
+            if let Some(text) = renderer.text() {
+                let (x, y) = hud::TEXT_POSITION;
+                let shadow = (x + hud::SHADOW_OFFSET, y + hud::SHADOW_OFFSET);
+                text.queue(&hud_text, shadow, hud::TEXT_SIZE, hud::SHADOW_COLOR);
+                text.queue(&hud_text, (x, y), hud::TEXT_SIZE, hud::TEXT_COLOR);
+                text.flush(width, height);
+            }
+            gl_window.gpu_timer.end(renderer);
+        }
+
+        gl_window
+            .hud
+            .record_frame(frame_start, frame_start.elapsed());
         {
-            let _t = Section::new("swap_buffers").unwrap();
+            let _t = Section::new("gpu_timer").unwrap();
+            renderer.poll_gpu_disjoint();
             gl_window
-                .surface
-                .swap_buffers(&gl_context)
-                .expect("Cannot swap buffers");
+                .gpu_timer
+                .end_frame(renderer, &self.deletion_queue, frame_id);
         }
 
         {
-            let _t = Section::new("make_not_current").unwrap();
-            *gl_context_rc = Some(
-                gl_context
-                    .make_not_current()
-                    .expect("Cannot uncurrent GL context"),
-            );
+            let _t = Section::new("swap_buffers").unwrap();
+            if let Some(kind) = self.faults.take() {
+                return Err(kind);
+            }
+            gl_window
+                .surface()?
+                .swap_buffers(gl_context)
+                .map_err(|e| e.error_kind())
+        }
+    }
+
+    /// Performs `recovery`, see [`recovery::recover()`].
+    fn recover(
+        &mut self,
+        format: HardwareBufferFormat,
+        gl_window: &mut support::GlWindow,
+        recovery: Recovery,
+    ) -> Recovery {
+        let _t = Section::new("Gl::recover()").unwrap();
+        recovery::recover(
+            &mut FailedWindow {
+                gl: self,
+                format,
+                gl_window,
+            },
+            recovery,
+        )
+    }
+
+    fn recreate_context(&mut self, format: HardwareBufferFormat) {
+        let _t = Section::new("Gl::recreate_context()").unwrap();
+        let (gl_context_rc, gl_config) = self.gl_contexts.get_mut(&format.into()).unwrap();
+        // Destroy the old context first
+        *gl_context_rc = None;
+        *gl_context_rc = Some(support::create_context(
+            &self.gl_display,
+            gl_config,
+            self.debug,
+        ));
+        // All GL objects died with the old context, so their names must not be deleted on the
+        // new context.  The renderer is lazily recreated on the next render.
+        drop(self.renderer.take());
+        let discarded = self.deletion_queue.discard();
+        debug!("Discarded {discarded} GL objects of the lost context");
+    }
+
+    /// Runs `f` with the renderer's context current without a surface, for work that is not tied
+    /// to a window.  A context that turns out to be lost is recreated for the next call.
+    fn with_surfaceless_context<T>(
+        &mut self,
+        f: impl FnOnce(&mut support::Renderer, &DeletionQueue) -> T,
+    ) -> Result<T, ErrorKind> {
+        let _t = Section::new("Gl::with_surfaceless_context()").unwrap();
+        // All objects live in the context that the renderer was first created on
+        let format = HardwareBufferFormat::R8G8B8X8_UNORM;
+
+        // Treat it as current without calling into EGL, so that we retain ownership of the
+        // context when make_current_surfaceless() fails.
+        let gl_context = self
+            .gl_contexts
+            .get_mut(&format.into())
+            .expect("No context/config for format")
+            .0
+            .take()
+            .expect("Didn't put back")
+            .treat_as_possibly_current();
+
+        let PossiblyCurrentContext::Egl(egl_context) = &gl_context;
+        let result = match egl_context.make_current_surfaceless() {
+            Ok(()) => {
+                let renderer = self.renderer.get_or_insert_with(|| {
+                    let _t = Section::new("Renderer setup").unwrap();
+                    support::Renderer::new(
+                        &self.gl_display,
+                        &self.deletion_queue,
+                        self.capabilities.clone(),
+                    )
+                });
+                let deleted = unsafe { self.deletion_queue.drain(renderer.gl()) };
+                if deleted > 0 {
+                    debug!("Deleted {deleted} queued GL objects");
+                }
+                Ok(f(renderer, &self.deletion_queue))
+            }
+            Err(e) => Err(e.error_kind()),
+        };
+
+        let result = match gl_context.make_not_current() {
+            Ok(gl_context) => {
+                self.gl_contexts.get_mut(&format.into()).unwrap().0 = Some(gl_context);
+                result
+            }
+            Err(e) => result.and(Err(e.error_kind())),
+        };
+
+        if let Err(kind) = result {
+            warn!("Cannot make the context current without a surface: {kind:?}");
+            if kind == ErrorKind::ContextLost || self.gl_contexts[&format.into()].0.is_none() {
+                self.recreate_context(format);
+            }
         }
+        result
+    }
+
+    /// Uploads the pixels of `bitmap` to a new texture.  The texture does not survive the loss
+    /// of the context, after which it must be uploaded again.
+    fn upload_bitmap(&mut self, env: &mut JNIEnv, bitmap: &JObject) -> bitmap::Result<Texture> {
+        let image = {
+            let _t = Section::new("read_image").unwrap();
+            bitmap::read_image(env, bitmap)?
+        };
+        debug!(
+            "Uploading {}x{} {:?} bitmap",
+            image.width, image.height, image.format
+        );
+        let texture = self.with_surfaceless_context(|renderer, deletion_queue| {
+            Texture::new(
+                renderer.gl(),
+                renderer.capabilities(),
+                deletion_queue,
+                image,
+            )
+        })??;
+        Ok(texture)
+    }
+
+    /// Uploads the image asset at `path`, like [`NativeGL::upload_bitmap()`].
+    fn load_texture(&mut self, path: &str) -> assets::Result<Texture> {
+        let image = {
+            let _t = Section::new("load_image").unwrap();
+            assets::load_image(path)?
+        };
+        debug!(
+            "Uploading {}x{} {:?} asset {path:?}",
+            image.width, image.height, image.format
+        );
+        let texture = self.with_surfaceless_context(|renderer, deletion_queue| {
+            Texture::new(
+                renderer.gl(),
+                renderer.capabilities(),
+                deletion_queue,
+                image,
+            )
+        })?;
+        texture.map_err(|error| assets::AssetError::Texture {
+            path: path.to_owned(),
+            error,
+        })
+    }
+
+    /// Capabilities of the renderer's context, as queried by [`NativeGL::new()`]
+    fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Renders the scene into `bitmap`, through an offscreen framebuffer of the same size.
+    fn render_to_bitmap(&mut self, env: &mut JNIEnv, bitmap: &JObject) -> bitmap::Result<()> {
+        let (width, height) = bitmap::size(env, bitmap)?;
+        let pixels = self.with_surfaceless_context(|renderer, deletion_queue| {
+            let max_size = renderer.capabilities().max_renderbuffer_size;
+            if width > max_size || height > max_size {
+                return Err(bitmap::BitmapError::TooLarge { max_size });
+            }
+            let _t = Section::new("draw_offscreen").unwrap();
+            renderer.draw_offscreen(
+                deletion_queue,
+                width as i32,
+                height as i32,
+                false,
+                |renderer| renderer.draw(false),
+            )
+        })??;
+        let _t = Section::new("write_rgba8").unwrap();
+        bitmap::write_rgba8(env, bitmap, &pixels)
+    }
+
+    /// Draws [`scene::gradient_ramp()`] into an offscreen sRGB framebuffer, and checks that it was
+    /// blended in linear space and encoded on write.
+    fn verify_srgb(&mut self) -> Result<(), String> {
+        let (width, height) = (scene::RAMP_STEPS * 8, 32);
+        let ramp = scene::gradient_ramp(width as f32, height as f32);
+        let pixels = self
+            .with_surfaceless_context(|renderer, deletion_queue| {
+                renderer.draw_offscreen(
+                    deletion_queue,
+                    width as i32,
+                    height as i32,
+                    true,
+                    |renderer| {
+                        renderer
+                            .scene()
+                            .draw(&ramp, width as i32, height as i32, true, |_| None);
+                    },
+                )
+            })
+            .map_err(|kind| format!("Cannot make the GL context current: {kind:?}"))?
+            .map_err(|e| e.to_string())?;
+        scene::check_gradient_ramp(&pixels, width, height).map_err(|e| e.to_string())?;
+        debug!("sRGB gradient ramp matches");
+        Ok(())
     }
 }
 
-#[no_mangle]
-pub extern "system" fn Java_rust_androidnativesurface_MainActivity_00024Companion_init(
-    _env: JNIEnv,
-    _class: JClass,
-) {
+/// A window of a [`NativeGL`] that failed to render, to [`Recover`] from that
+#[cfg(target_os = "android")]
+struct FailedWindow<'a> {
+    gl: &'a mut NativeGL,
+    format: HardwareBufferFormat,
+    gl_window: &'a mut support::GlWindow,
+}
+
+#[cfg(target_os = "android")]
+impl Recover for FailedWindow<'_> {
+    fn recreate_surface(&mut self) -> glutin::error::Result<()> {
+        let (_gl_context_rc, gl_config) = &self.gl.gl_contexts[&self.format.into()];
+        self.gl_window
+            .recreate_surface(&self.gl.gl_display, gl_config)
+            .inspect_err(|e| warn!("Failed to recreate surface for {:?}: {e}", self.gl_window))
+    }
+
+    fn has_context(&self) -> bool {
+        self.gl.gl_contexts[&self.format.into()].0.is_some()
+    }
+
+    fn recreate_context(&mut self) {
+        self.gl.recreate_context(self.format);
+    }
+}
+
+/// Redirection of `stdout` and `stderr` into logcat, until [`stop_stdio_capture()`]
+#[cfg(target_os = "android")]
+static STDIO_CAPTURE: Mutex<Option<StdioCapture>> = Mutex::new(None);
+
+#[cfg(target_os = "android")]
+extern "system" fn init(mut env: JNIEnv, _class: JClass) {
     let _t = Section::new("init").unwrap();
-    android_logger::init_once(
-        android_logger::Config::default()
-            .with_filter(
-                FilterBuilder::new()
-                    .filter_level(LevelFilter::Trace)
-                    // Disable Trace-level messages on JNI crate (specifically around accessing Rust fields)
-                    .filter_module("jni", LevelFilter::Debug)
-                    .build(),
-            )
-            // android_logger erroneously doesn't set log::set_max_level() to the highest
-            // that the filter could match on, hence we have to set it again manually:
-            // https://github.com/rust-mobile/android_logger-rs/issues/80
-            .with_max_level(LevelFilter::Trace),
+    // Idempotent, normally already done from bindings::JNI_OnLoad()
+    logger::init(&mut env);
+
+    let mut capture = STDIO_CAPTURE.lock().unwrap();
+    if capture.is_some() {
+        return;
+    }
+    let mut tag = |property, default: &str| match bindings::system_property(&mut env, property) {
+        Ok(tag) => tag.unwrap_or_else(|| default.to_owned()),
+        Err(e) => {
+            env.exception_clear().unwrap();
+            warn!("Cannot read the {property} property: {e}");
+            default.to_owned()
+        }
+    };
+    let stdout_tag = tag(
+        "android_native_surface.stdout_tag",
+        stdio_capture::DEFAULT_STDOUT_TAG,
+    );
+    let stderr_tag = tag(
+        "android_native_surface.stderr_tag",
+        stdio_capture::DEFAULT_STDERR_TAG,
     );
+    match StdioCapture::start(stdout_tag, stderr_tag) {
+        Ok(started) => *capture = Some(started),
+        Err(e) => warn!("Cannot capture stdout and stderr: {e}"),
+    }
+}
 
-    let file = {
-        let (read, write) = rustix::pipe::pipe().unwrap();
-        rustix::stdio::dup2_stdout(&write).unwrap();
-        rustix::stdio::dup2_stderr(&write).unwrap();
+/// Replaces the log filter with an `env_logger`-style spec, throwing if it is invalid.
+#[cfg(target_os = "android")]
+extern "system" fn set_log_filter(mut env: JNIEnv, _class: JClass, spec: JString) {
+    let _t = Section::new("set_log_filter").unwrap();
+    let spec = String::from(env.get_string(&spec).unwrap());
+    if let Err(e) = logger::set_filter(&mut env, &spec) {
+        env.throw_new(
+            "java/lang/IllegalArgumentException",
+            format!("Invalid log filter {spec:?}: {e}"),
+        )
+        .unwrap();
+    }
+}
 
-        File::from(read)
+/// Installs the loader that shaders, textures and models are read through: the directory from the
+/// `android_native_surface.asset_dir` system property if set, otherwise the APK through the Java
+/// `asset_manager`.
+#[cfg(target_os = "android")]
+extern "system" fn init_assets(mut env: JNIEnv, _class: JClass, asset_manager: JObject) {
+    let _t = Section::new("init_assets").unwrap();
+    let asset_dir = match bindings::system_property(&mut env, "android_native_surface.asset_dir") {
+        Ok(asset_dir) => asset_dir,
+        Err(e) => {
+            env.exception_clear().unwrap();
+            warn!("Cannot read the android_native_surface.asset_dir property: {e}");
+            None
+        }
     };
-
-    thread::spawn(move || -> io::Result<()> {
-        let mut reader = BufReader::new(file);
-        let mut buffer = String::new();
-        loop {
-            buffer.clear();
-            let len = reader.read_line(&mut buffer)?;
-            if len == 0 {
-                break Ok(());
-            } else {
-                info!(target: "RustStdoutStderr", "{buffer}");
+    let loader: Arc<dyn assets::AssetLoader> = match asset_dir {
+        Some(dir) => {
+            info!("Loading assets from {dir:?} instead of the APK");
+            Arc::new(assets::DirAssets::new(dir))
+        }
+        None => match assets::ApkAssets::from_java(&mut env, &asset_manager) {
+            Ok(apk_assets) => Arc::new(apk_assets),
+            Err(e) => {
+                env.throw_new(
+                    "java/lang/IllegalArgumentException",
+                    format!("Invalid AssetManager: {e}"),
+                )
+                .unwrap();
+                return;
             }
+        },
+    };
+    assets::install(loader);
+}
+
+/// Restores the original `stdout` and `stderr`.  A later [`init()`] starts capturing them again.
+#[cfg(target_os = "android")]
+extern "system" fn stop_stdio_capture(_env: JNIEnv, _class: JClass) {
+    let _t = Section::new("stop_stdio_capture").unwrap();
+    if let Some(capture) = STDIO_CAPTURE.lock().unwrap().take() {
+        capture.stop();
+    }
+}
+
+/// Every [`NativeGL`] that Java holds a handle to
+#[cfg(target_os = "android")]
+static NATIVE_GLS: Registry<NativeGL> = Registry::new();
+/// Every [`Window`] that Java holds a handle to.  When both are needed, always lock the
+/// [`NativeGL`] first.
+#[cfg(target_os = "android")]
+static WINDOWS: Registry<Window> = Registry::new();
+/// Every texture that Java holds a handle to, uploaded through a [`NativeGL`].  Locked last, after
+/// the [`Scene`] that refers to it.
+#[cfg(target_os = "android")]
+static TEXTURES: Registry<Texture> = Registry::new();
+/// Every [`Scene`] that Java holds a handle to.  Locked after the [`Window`] it is drawn on.
+#[cfg(target_os = "android")]
+static SCENES: Registry<Scene> = Registry::new();
+
+#[cfg(target_os = "android")]
+extern "system" fn native_gl_init<'local>(
+    mut env: JNIEnv<'local>,
+    this: JObject<'local>,
+    debug: jboolean,
+) -> jlong {
+    let gl =
+        NativeGL::new(debug == JNI_TRUE).inspect_err(|e| error!("Cannot create a NativeGL: {e}"));
+    // Throws a RuntimeException and returns 0 on error
+    gl.into_java(&mut JniCall {
+        env: &mut env,
+        this: &this,
+        window: None,
+    })
+}
+
+#[cfg(target_os = "android")]
+extern "system" fn native_gl_destroy(mut env: JNIEnv, this: JObject, native_gl: jlong) -> jboolean {
+    let _t = Section::new("destroy").unwrap();
+
+    // Check under the same lock as the removal, so that no window can be created in between
+    let gl = NATIVE_GLS.remove_if(Handle::from_raw(native_gl), |gl| {
+        let live_gl_windows = gl.live_gl_windows();
+        if live_gl_windows > 0 {
+            warn!("Refusing to destroy {this:?} while {live_gl_windows} GlWindows are alive");
         }
+        live_gl_windows == 0
     });
+
+    match throw_on_err(&mut env, gl).flatten().map(NativeGL::destroy) {
+        Some(Ok(())) => JNI_TRUE,
+        Some(Err(e)) => {
+            error!("Cannot destroy {this:?}: {e}");
+            JNI_FALSE
+        }
+        None => JNI_FALSE,
+    }
 }
 
-#[no_mangle]
-pub extern "system" fn Java_rust_androidnativesurface_MainActivity_00024NativeGL_init(
-    mut env: JNIEnv,
-    _class: JClass,
-    native_gl: JObject,
-) {
-    let gl = NativeGL::new();
-    unsafe { env.set_rust_field(native_gl, "mNative", gl) }.unwrap();
+#[cfg(target_os = "android")]
+jni_export! {
+    fn native_gl_inject_fault(env, _this, gl: &mut NativeGL, recovery: jint) -> jni::errors::Result<()> {
+        gl.inject_fault(check_argument(env, "recovery", Recovery::try_from(recovery))?);
+        Ok(())
+    }
 }
 
-#[no_mangle]
-pub extern "system" fn Java_rust_androidnativesurface_MainActivity_00024NativeSurfaceWrapper_setSurface(
-    mut env: JNIEnv,
-    _class: JClass,
-    native_gl: JObject,
-    native_surface_wrapper: JObject,
-    surface: JObject,
-) {
-    let _t = Section::new("setSurface").unwrap();
-    debug!("Add Java Surface {surface:?} to {native_surface_wrapper:?}");
-
-    let window =
-        unsafe { NativeWindow::from_surface(env.get_native_interface(), surface.into_raw()) }
-            .unwrap();
-    let mut native_gl =
-        unsafe { env.get_rust_field::<_, _, NativeGL>(native_gl, "mNative") }.unwrap();
-    let gl_window = native_gl.create_gl_window(window);
-    drop(native_gl);
-    unsafe { env.set_rust_field(native_surface_wrapper, "mNative", gl_window) }.unwrap();
-}
-
-#[no_mangle]
-pub extern "system" fn Java_rust_androidnativesurface_MainActivity_00024NativeSurfaceWrapper_removeSurface(
-    mut env: JNIEnv,
-    _class: JClass,
-    native_surface_wrapper: JObject,
-) {
-    let _t = Section::new("removeSurface").unwrap();
-    debug!("Remove Java Surface from {native_surface_wrapper:?}");
+#[cfg(target_os = "android")]
+jni_export! {
+    fn surface_wrapper_set_surface(env, _this, gl: &mut NativeGL, surface: JObject, backend: jint) -> jni::errors::Result<Window> {
+        debug!("Add Java Surface {surface:?}");
+        let backend = check_argument(env, "backend", Backend::try_from(backend))?;
+        let window =
+            unsafe { NativeWindow::from_surface(env.get_native_interface(), surface.into_raw()) }
+                .unwrap();
+        Ok(gl.create_window(window, backend))
+    }
+}
 
-    let gl_window: support::GlWindow =
-        unsafe { env.take_rust_field(native_surface_wrapper, "mNative") }.unwrap();
+#[cfg(target_os = "android")]
+jni_export! {
+    fn window_wrapper_remove(_env, _this, window: Window) {
+        debug!("Removed surface was {window:?}")
+    }
+}
 
-    debug!("Removed surface was {gl_window:?}");
+#[cfg(target_os = "android")]
+jni_export! {
+    fn window_wrapper_render(_env, _this, gl: &mut NativeGL, window: &mut Window) -> Result<Option<Recovery>, ErrorKind> {
+        gl.render_to_window(window)
+    }
 }
 
-#[no_mangle]
-pub extern "system" fn Java_rust_androidnativesurface_MainActivity_00024NativeSurfaceWrapper_renderToSurface(
-    mut env: JNIEnv,
-    _class: JClass,
-    native_gl: JObject,
-    native_surface_wrapper: JObject,
-) {
-    let _t = Section::new("renderToSurface").unwrap();
-    debug!("Render to Java Surface via {native_surface_wrapper:?}");
+#[cfg(target_os = "android")]
+jni_export! {
+    fn window_wrapper_set_frame_rate(_env, _this, window: &mut Window, frame_rate: jfloat, compatibility: jint, change_frame_rate_strategy: jint) {
+        set_frame_rate(window, frame_rate, compatibility, change_frame_rate_strategy)
+    }
+}
 
-    // SAFETY: TODO
-    let mut env2 = unsafe { env.unsafe_clone() };
+#[cfg(target_os = "android")]
+jni_export! {
+    fn surface_texture_wrapper_set_surface_texture(env, _this, gl: &mut NativeGL, surface_texture: JObject, backend: jint) -> jni::errors::Result<Window> {
+        debug!("Add Java SurfaceTexture {surface_texture:?}");
+        // SAFETY: The handle is valid and we're not storing this SurfaceTexture anywhere.  The
+        // lifetime on the Java side is guiding (and a Surface/NativeWindow can exist independently
+        // from it).
+        let surface_texture = unsafe {
+            SurfaceTexture::from_surface_texture(env.get_native_interface(), surface_texture.into_raw())
+                .unwrap()
+        };
+        let backend = check_argument(env, "backend", Backend::try_from(backend))?;
+        let window = surface_texture.acquire_native_window().unwrap();
+        Ok(gl.create_window(window, backend))
+    }
+}
 
-    let gl_window =
-        unsafe { env.get_rust_field::<_, _, support::GlWindow>(native_surface_wrapper, "mNative") }
-            .unwrap();
-    debug!("Java Surface is {gl_window:?}");
+#[cfg(target_os = "android")]
+jni_export! {
+    fn window_wrapper_set_hud_enabled(_env, _this, window: &mut Window, enabled: jboolean) {
+        window.set_hud_enabled(enabled == JNI_TRUE)
+    }
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn window_wrapper_set_srgb(_env, _this, gl: &mut NativeGL, window: &mut Window, srgb: jboolean) -> glutin::error::Result<()> {
+        gl.set_srgb(window, srgb == JNI_TRUE)
+    }
+}
 
-    let mut native_gl =
-        unsafe { env2.get_rust_field::<_, _, NativeGL>(native_gl, "mNative") }.unwrap();
+#[cfg(target_os = "android")]
+jni_export! {
+    fn window_wrapper_set_msaa_samples(_env, _this, window: &mut Window, samples: jint) {
+        window.set_msaa_samples(samples.max(0) as u32)
+    }
+}
 
-    native_gl.render_to_gl_window(&gl_window)
+#[cfg(target_os = "android")]
+jni_export! {
+    fn window_wrapper_set_post_passes(env, _this, window: &mut Window, kinds: JIntArray, values: JFloatArray, textures: JLongArray) -> jni::errors::Result<()> {
+        window.set_post_passes(read_post_passes(env, &kinds, &values, &textures)?);
+        Ok(())
+    }
 }
 
-#[no_mangle]
-pub extern "system" fn Java_rust_androidnativesurface_MainActivity_00024NativeSurfaceTextureWrapper_setSurfaceTexture(
-    mut env: JNIEnv,
-    _class: JClass,
-    native_gl: JObject,
-    native_surface_texture_wrapper: JObject,
-    surface_texture: JObject,
-) {
-    let _t = Section::new("setSurfaceTexture").unwrap();
-    debug!("Add Java SurfaceTexture {surface_texture:?} to {native_surface_texture_wrapper:?}");
-
-    // SAFETY: The handle is valid and we're not storing this SurfaceTexture anywhere.  The lifetime
-    // on the Java side is guiding (and a Surface/NativeWindow can exist independently from it).
-    let surface_texture = unsafe {
-        SurfaceTexture::from_surface_texture(env.get_native_interface(), surface_texture.into_raw())
-            .unwrap()
-    };
-    let window = surface_texture.acquire_native_window().unwrap();
-    let mut native_gl =
-        unsafe { env.get_rust_field::<_, _, NativeGL>(native_gl, "mNative") }.unwrap();
-    let gl_window = native_gl.create_gl_window(window);
-    drop(native_gl);
-    unsafe { env.set_rust_field(native_surface_texture_wrapper, "mNative", gl_window) }.unwrap();
-}
-
-#[no_mangle]
-pub extern "system" fn Java_rust_androidnativesurface_MainActivity_00024NativeSurfaceTextureWrapper_removeSurfaceTexture(
-    mut env: JNIEnv,
-    _class: JClass,
-    native_surface_texture_wrapper: JObject,
-) {
-    let _t = Section::new("removeSurfaceTexture").unwrap();
-    debug!("Remove Java Surface from {native_surface_texture_wrapper:?}");
+/// Zips the parallel arrays of the `setPostPasses()` JNI export above into [`post::Pass`]es
+#[cfg(target_os = "android")]
+fn read_post_passes(
+    env: &mut JNIEnv<'_>,
+    kinds: &JIntArray<'_>,
+    values: &JFloatArray<'_>,
+    textures: &JLongArray<'_>,
+) -> jni::errors::Result<Vec<post::Pass>> {
+    let len = env.get_array_length(kinds)? as usize;
+    let (mut kind_buf, mut value_buf, mut texture_buf) =
+        (vec![0; len], vec![0.0; len], vec![0; len]);
+    env.get_int_array_region(kinds, 0, &mut kind_buf)?;
+    env.get_float_array_region(values, 0, &mut value_buf)?;
+    env.get_long_array_region(textures, 0, &mut texture_buf)?;
 
-    let gl_window: support::GlWindow =
-        unsafe { env.take_rust_field(native_surface_texture_wrapper, "mNative") }.unwrap();
+    let kinds = kind_buf
+        .into_iter()
+        .map(post::PassKind::try_from)
+        .collect::<Result<Vec<_>, _>>();
+    let kinds = check_argument(env, "post-processing pass", kinds)?;
 
-    debug!("Removed surface was {gl_window:?}");
+    let passes = kinds
+        .into_iter()
+        .zip(value_buf)
+        .zip(texture_buf)
+        .map(|((kind, value), texture)| {
+            let pass = post::Pass::new(kind, value, Handle::from_raw(texture));
+            if let post::Pass::ColorLut { lut } = pass {
+                let size = TEXTURES.with(lut, |lut| lut.size());
+                if size.as_ref().is_ok_and(|&size| size != post::LUT_SIZE) {
+                    warn!(
+                        "Color LUT {lut:?} is {size:?}, expected {:?}",
+                        post::LUT_SIZE
+                    );
+                }
+            }
+            pass
+        })
+        .collect::<Vec<_>>();
+    debug!("Post-processing passes: {passes:?}");
+    Ok(passes)
 }
 
-#[no_mangle]
-pub extern "system" fn Java_rust_androidnativesurface_MainActivity_00024NativeSurfaceTextureWrapper_renderToSurfaceTexture(
-    mut env: JNIEnv,
-    _class: JClass,
-    native_gl: JObject,
-    native_surface_texture_wrapper: JObject,
+/// Validates the arguments of the `setFrameRate()` JNI export above, and applies them to `window`
+#[cfg(target_os = "android")]
+fn set_frame_rate(
+    window: &Window,
+    frame_rate: f32,
+    compatibility: i32,
+    change_frame_rate_strategy: i32,
 ) {
-    let _t = Section::new("renderToSurfaceTexture").unwrap();
-    debug!("Render to Java Surface via {native_surface_texture_wrapper:?}");
+    // Values from newer platforms are ignored like the platform ignores them on older devices
+    let compatibility = FrameRateCompatibility::try_from(compatibility).unwrap_or_else(|x| {
+        warn!("Ignoring unknown ANativeWindow_FrameRateCompatibility {x}");
+        FrameRateCompatibility::default()
+    });
+    let change_frame_rate_strategy = ChangeFrameRateStrategy::try_from(change_frame_rate_strategy)
+        .unwrap_or_else(|x| {
+            warn!("Ignoring unknown ANativeWindow_ChangeFrameRateStrategy {x}");
+            ChangeFrameRateStrategy::default()
+        });
 
-    // SAFETY: TODO
-    let mut env2 = unsafe { env.unsafe_clone() };
+    debug!(
+        "Set frame rate on {window:?} to {frame_rate} ({compatibility:?}, {change_frame_rate_strategy:?})"
+    );
+    if let Err(e) = window.set_frame_rate(frame_rate, compatibility, change_frame_rate_strategy) {
+        warn!("Failed to set frame rate on {window:?}: {e}");
+    }
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn native_gl_load_texture(env, _this, gl: &mut NativeGL, path: JString) -> assets::Result<Texture> {
+        let path = String::from(env.get_string(&path).unwrap());
+        gl.load_texture(&path)
+    }
+}
 
-    let gl_window = unsafe {
-        env.get_rust_field::<_, _, support::GlWindow>(native_surface_texture_wrapper, "mNative")
+#[cfg(target_os = "android")]
+jni_export! {
+    fn native_gl_upload_bitmap(env, _this, gl: &mut NativeGL, bitmap: JObject) -> bitmap::Result<Texture> {
+        gl.upload_bitmap(env, &bitmap)
     }
-    .unwrap();
-    debug!("Java Surface is {gl_window:?}");
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn native_gl_delete_texture(_env, _this, texture: Texture) {
+        debug!("Deleting {texture:?}");
+    }
+}
 
-    let mut native_gl =
-        unsafe { env2.get_rust_field::<_, _, NativeGL>(native_gl, "mNative") }.unwrap();
+#[cfg(target_os = "android")]
+jni_export! {
+    fn native_gl_render_to_bitmap(env, _this, gl: &mut NativeGL, bitmap: JObject) -> bitmap::Result<()> {
+        gl.render_to_bitmap(env, &bitmap)
+    }
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn native_gl_verify_srgb(_env, _this, gl: &mut NativeGL) -> Result<(), String> {
+        gl.verify_srgb()
+    }
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn native_gl_get_capabilities(_env, _this, gl: &mut NativeGL) -> BTreeMap<String, String> {
+        gl.capabilities().to_map()
+    }
+}
 
-    native_gl.render_to_gl_window(&gl_window)
+#[cfg(target_os = "android")]
+jni_export! {
+    fn window_wrapper_set_scene(_env, _this, window: &mut Window, scene: jlong) {
+        window.set_scene((scene != 0).then(|| Handle::from_raw(scene)))
+    }
+}
+
+#[cfg(target_os = "android")]
+extern "system" fn scene_create(_env: JNIEnv, _this: JObject) -> jlong {
+    SCENES.insert(Scene::default()).into_raw()
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn scene_destroy(_env, _this, scene: Scene) {
+        debug!("Destroyed {scene:?}")
+    }
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn window_wrapper_on_motion_event(env, _this, window: &mut Window, event: JObject, view_width: jint, view_height: jint) -> jni::errors::Result<bool> {
+        let event = input::MotionEvent::from_java(env, &event)?;
+        Ok(window.handle_motion(event, view_width, view_height))
+    }
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn scene_set_show_pointers(_env, _this, scene: &mut Scene, show: jboolean) {
+        scene.show_pointers = show == JNI_TRUE;
+    }
+}
+
+/// Converts an `android.graphics.Color` int to straight-alpha RGBA
+#[cfg(target_os = "android")]
+fn argb_to_rgba(argb: jint) -> [f32; 4] {
+    let [a, r, g, b] = argb.to_be_bytes().map(|c| f32::from(c) / 255.0);
+    [r, g, b, a]
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn scene_add_rect(_env, _this, scene: &mut Scene, x: jfloat, y: jfloat, width: jfloat, height: jfloat, z: jint, color: jint) -> NodeId {
+        let rect = Rect::new(x, y, width, height);
+        scene.insert(Node::new(rect, z, Fill::Color(argb_to_rgba(color))))
+    }
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn scene_add_sprite(_env, _this, scene: &mut Scene, texture: jlong, x: jfloat, y: jfloat, width: jfloat, height: jfloat, z: jint, tint: jint) -> NodeId {
+        let rect = Rect::new(x, y, width, height);
+        let fill = Fill::Texture {
+            texture: Handle::from_raw(texture),
+            tint: argb_to_rgba(tint),
+        };
+        scene.insert(Node::new(rect, z, fill))
+    }
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn scene_set_transform(_env, _this, scene: &mut Scene, node: jlong, scale_x: jfloat, skew_x: jfloat, translate_x: jfloat, skew_y: jfloat, scale_y: jfloat, translate_y: jfloat) -> Result<(), SceneError> {
+        scene.node_mut(NodeId::from_raw(node))?.transform = Transform {
+            scale_x,
+            skew_x,
+            translate_x,
+            skew_y,
+            scale_y,
+            translate_y,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn scene_set_clip(_env, _this, scene: &mut Scene, node: jlong, x: jfloat, y: jfloat, width: jfloat, height: jfloat) -> Result<(), SceneError> {
+        scene.node_mut(NodeId::from_raw(node))?.clip = Some(Rect::new(x, y, width, height));
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn scene_clear_clip(_env, _this, scene: &mut Scene, node: jlong) -> Result<(), SceneError> {
+        scene.node_mut(NodeId::from_raw(node))?.clip = None;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "android")]
+jni_export! {
+    fn scene_remove(_env, _this, scene: &mut Scene, node: jlong) -> Result<(), SceneError> {
+        scene.remove(NodeId::from_raw(node)).map(drop)
+    }
 }
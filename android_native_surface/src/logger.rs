@@ -0,0 +1,107 @@
+//! [`log`] backend that writes to logcat through [`android_logger`], with a filter that can be
+//! replaced at runtime.
+//!
+//! `android_logger` fixes its filter when it is installed, so this wraps an unfiltered
+//! [`AndroidLogger`] and applies an [`env_filter`] spec such as `android_native_surface=info,jni=warn`
+//! itself.  The initial spec is read from the `android_native_surface.log_filter` Java system
+//! property, and can be changed later through `setLogFilter()`.
+
+use std::sync::{OnceLock, RwLock};
+
+use android_logger::{AndroidLogger, Filter, FilterBuilder};
+use env_filter::ParseError;
+use jni::JNIEnv;
+use log::{debug, warn, LevelFilter, Log, Metadata, Record};
+
+/// Used when the system property is not set.  Trace-level messages of the `jni` crate, specifically
+/// around accessing Rust fields, are too noisy to be useful.
+pub const DEFAULT_FILTER: &str = "trace,jni=debug";
+
+struct Logger {
+    android: AndroidLogger,
+    filter: RwLock<Filter>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.filter.read().unwrap().enabled(metadata) && self.android.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.filter.read().unwrap().matches(record) {
+            self.android.log(record)
+        }
+    }
+
+    fn flush(&self) {
+        self.android.flush()
+    }
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+fn parse(spec: &str) -> Result<Filter, ParseError> {
+    Ok(FilterBuilder::new().try_parse(spec)?.build())
+}
+
+/// Installs the logger on first use, with the filter from the system property or
+/// [`DEFAULT_FILTER`].
+pub fn init(env: &mut JNIEnv<'_>) {
+    logger(env);
+}
+
+/// The installed logger, after installing it if that did not happen yet
+fn logger(env: &mut JNIEnv<'_>) -> &'static Logger {
+    if let Some(logger) = LOGGER.get() {
+        return logger;
+    }
+
+    let spec = crate::bindings::system_property(env, "android_native_surface.log_filter");
+    if spec.is_err() {
+        env.exception_clear().unwrap();
+    }
+    // Errors are only logged once the logger is installed
+    let (filter, error) = match &spec {
+        Ok(Some(spec)) => match parse(spec) {
+            Ok(filter) => (filter, None),
+            Err(e) => (
+                parse(DEFAULT_FILTER).unwrap(),
+                Some(format!("Invalid log filter {spec:?}: {e}")),
+            ),
+        },
+        Ok(None) => (parse(DEFAULT_FILTER).unwrap(), None),
+        Err(e) => (
+            parse(DEFAULT_FILTER).unwrap(),
+            Some(format!("Cannot read the log filter property: {e}")),
+        ),
+    };
+
+    let max_level = filter.filter();
+    let logger = LOGGER.get_or_init(|| Logger {
+        // The level is checked by the filter above, which may be raised later on
+        android: AndroidLogger::new(
+            android_logger::Config::default().with_max_level(LevelFilter::Trace),
+        ),
+        filter: RwLock::new(filter),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(max_level);
+    }
+
+    if let Some(error) = error {
+        warn!("{error}, using {DEFAULT_FILTER:?} instead");
+    }
+    logger
+}
+
+/// Replaces the active filter with `spec`, an `env_logger`-style list of directives, installing
+/// the logger first if needed.  The previous filter stays active if `spec` is invalid.
+pub fn set_filter(env: &mut JNIEnv<'_>, spec: &str) -> Result<(), ParseError> {
+    let filter = parse(spec)?;
+    let logger = logger(env);
+    let max_level = filter.filter();
+    *logger.filter.write().unwrap() = filter;
+    log::set_max_level(max_level);
+    debug!("Log filter set to {spec:?}");
+    Ok(())
+}
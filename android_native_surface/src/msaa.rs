@@ -0,0 +1,408 @@
+//! Per-window multisample anti-aliasing through an offscreen framebuffer.
+//!
+//! Window surfaces are single-sampled, see [`crate::NativeGL`].  When a window asks for MSAA, its
+//! frames are drawn into a framebuffer of the same size instead, which is resolved into the
+//! surface (or the first [`crate::post`] target) at the end of the frame:
+//!
+//! - With [`Feature::MultisampledRenderToTexture`], the samples only live in tile memory and are
+//!   resolved implicitly when a tile is written out to a plain texture, which is then blitted to
+//!   the surface.  This is what tile-based mobile GPUs are optimized for.
+//! - Otherwise into a multisampled renderbuffer, that `glBlitFramebuffer()` resolves into the
+//!   surface directly.
+//!
+//! The requested sample count is clamped to what the device supports for either path and the
+//! color format, which is taken from the EGL config of the surface, and the framebuffer is
+//! reallocated whenever the window is resized or the count changes.  Should the driver still
+//! reject the framebuffer, the window is drawn without MSAA.
+
+use glutin::config::ColorBufferType;
+use log::{debug, warn};
+
+use crate::{
+    deletion_queue::{DeletionQueue, GlObject, GlObjectKind},
+    support::{capabilities::Feature, gl, Renderer},
+};
+
+/// How the samples are resolved into the window surface
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// `EXT_multisampled_render_to_texture`, followed by a plain blit
+    RenderToTexture,
+    /// Multisampled renderbuffer, resolved by a blit
+    Resolve,
+}
+
+/// Allocated multisampled framebuffer
+#[derive(Debug)]
+struct Target {
+    /// [`DeletionQueue::epoch()`] that the objects below were created in
+    epoch: u64,
+    width: i32,
+    height: i32,
+    /// After clamping, always more than `1`
+    samples: u32,
+    strategy: Strategy,
+    /// Sized internal format of the color buffer
+    format: gl::types::GLenum,
+    framebuffer: GlObject,
+    /// Texture for [`Strategy::RenderToTexture`], renderbuffer for [`Strategy::Resolve`]
+    _color: GlObject,
+}
+
+/// MSAA state of one window
+#[derive(Debug, Default)]
+pub struct Msaa {
+    /// Sample count that was asked for, `0` and `1` disable MSAA
+    requested: u32,
+    target: Option<Target>,
+    /// Requested sample count, format and size that MSAA is off for, as the format is unknown or
+    /// supports no samples, or the framebuffer was incomplete.  Not retried until any of them
+    /// changes.
+    unsupported: Option<(u32, Option<gl::types::GLenum>, i32, i32)>,
+}
+
+impl Msaa {
+    /// Takes effect on the next [`Msaa::begin()`].
+    pub fn set_samples(&mut self, samples: u32) {
+        if samples != self.requested {
+            self.target = None;
+        }
+        self.requested = samples;
+    }
+
+    /// Sample count and strategy of the frame that was last drawn, [`None`] when MSAA is off
+    pub fn active(&self) -> Option<(u32, Strategy)> {
+        self.target
+            .as_ref()
+            .map(|target| (target.samples, target.strategy))
+    }
+
+    /// Binds the framebuffer that the frame must be drawn into: the multisampled one, which is
+    /// (re)allocated as needed, or `destination` itself when MSAA is off.  `format` must be the
+    /// sized internal format of `destination`, as a resolving blit requires identical formats
+    /// on both sides, and MSAA is off when it is unknown.
+    pub fn begin(
+        &mut self,
+        renderer: &Renderer,
+        deletion_queue: &DeletionQueue,
+        width: i32,
+        height: i32,
+        destination: gl::types::GLuint,
+        format: Option<gl::types::GLenum>,
+    ) {
+        let epoch = deletion_queue.epoch();
+        let reuse = self.target.as_ref().is_some_and(|target| {
+            (target.epoch, target.width, target.height) == (epoch, width, height)
+                && Some(target.format) == format
+        });
+        let key = (self.requested, format, width, height);
+        let unsupported = self.unsupported == Some(key);
+        if !reuse {
+            // Dropping a target of an older epoch does not queue its stale names
+            self.target = None;
+        }
+        let allocate = !reuse && !unsupported && self.requested > 1;
+        if allocate && format.is_none() {
+            warn!("Unknown surface format, drawing without MSAA");
+            self.unsupported = Some(key);
+        }
+        if let Some(format) = format.filter(|_| allocate) {
+            let capabilities = renderer.capabilities();
+            let (strategy, max_samples) = match capabilities.max_samples_render_to_texture {
+                Some(max_samples)
+                    if capabilities.supports(Feature::MultisampledRenderToTexture) =>
+                {
+                    (Strategy::RenderToTexture, max_samples)
+                }
+                _ => (Strategy::Resolve, capabilities.max_samples),
+            };
+            let samples = clamp_samples(
+                self.requested,
+                max_samples,
+                &supported_sample_counts(renderer, format),
+            );
+            if samples < self.requested {
+                debug!(
+                    "Clamped {} MSAA samples to {samples} for format {format:#06x}",
+                    self.requested
+                );
+            }
+            if samples > 1 {
+                match Target::new(
+                    renderer,
+                    deletion_queue,
+                    width,
+                    height,
+                    samples,
+                    strategy,
+                    format,
+                ) {
+                    Ok(target) => self.target = Some(target),
+                    Err(status) => {
+                        warn!(
+                            "Cannot render to a {width}x{height} framebuffer with {samples} \
+                             samples (status {status:#06x}), drawing without MSAA"
+                        );
+                        self.unsupported = Some(key);
+                    }
+                }
+            } else {
+                self.unsupported = Some(key);
+            }
+        }
+
+        let framebuffer = self
+            .target
+            .as_ref()
+            .map_or(destination, |t| t.framebuffer.name());
+        unsafe { renderer.gl().BindFramebuffer(gl::FRAMEBUFFER, framebuffer) };
+    }
+
+    /// Resolves the frame that was drawn since [`Msaa::begin()`] into `destination`, and leaves
+    /// that bound.  Does nothing when MSAA is off.
+    pub fn resolve(&self, renderer: &Renderer, destination: gl::types::GLuint) {
+        let Some(target) = &self.target else {
+            return;
+        };
+        let gl = renderer.gl();
+        unsafe {
+            gl.BindFramebuffer(gl::READ_FRAMEBUFFER, target.framebuffer.name());
+            gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, destination);
+            let (width, height) = (target.width, target.height);
+            gl.BlitFramebuffer(
+                0,
+                0,
+                width,
+                height,
+                0,
+                0,
+                width,
+                height,
+                gl::COLOR_BUFFER_BIT,
+                gl::NEAREST,
+            );
+            // The samples are redrawn from scratch next frame, which spares tilers from writing
+            // them back to memory
+            gl.InvalidateFramebuffer(gl::READ_FRAMEBUFFER, 1, &gl::COLOR_ATTACHMENT0);
+            gl.BindFramebuffer(gl::FRAMEBUFFER, destination);
+        }
+    }
+}
+
+/// Sample counts that `format` supports, `GL_MAX_SAMPLES` only bounds the counts of all formats
+fn supported_sample_counts(renderer: &Renderer, format: gl::types::GLenum) -> Vec<u32> {
+    let gl = renderer.gl();
+    unsafe {
+        let mut count = 0;
+        gl.GetInternalformativ(
+            gl::RENDERBUFFER,
+            format,
+            gl::NUM_SAMPLE_COUNTS,
+            1,
+            &mut count,
+        );
+        let mut counts = vec![0; count.max(0) as usize];
+        gl.GetInternalformativ(
+            gl::RENDERBUFFER,
+            format,
+            gl::SAMPLES,
+            count,
+            counts.as_mut_ptr(),
+        );
+        counts.into_iter().map(|count| count as u32).collect()
+    }
+}
+
+/// Highest of the `supported` sample counts up to `requested` and `max_samples`, or `1` if there
+/// is none.
+fn clamp_samples(requested: u32, max_samples: u32, supported: &[u32]) -> u32 {
+    let samples = requested.min(max_samples);
+    supported
+        .iter()
+        .copied()
+        .filter(|&count| count <= samples)
+        .max()
+        .unwrap_or(1)
+}
+
+/// Sized internal format of a window surface with the color buffer of its EGL config, for
+/// [`Msaa::begin()`].  [`None`] for configs that have no renderable equivalent, like sRGB without
+/// alpha.
+pub fn surface_format(
+    color_buffer_type: Option<ColorBufferType>,
+    alpha_size: u8,
+    float_pixels: bool,
+    srgb: bool,
+) -> Option<gl::types::GLenum> {
+    let ColorBufferType::Rgb {
+        r_size,
+        g_size,
+        b_size,
+    } = color_buffer_type?
+    else {
+        return None;
+    };
+    Some(
+        match ((r_size, g_size, b_size, alpha_size), float_pixels, srgb) {
+            ((8, 8, 8, 8), false, true) => gl::SRGB8_ALPHA8,
+            ((8, 8, 8, 8), false, false) => gl::RGBA8,
+            ((8, 8, 8, 0), false, false) => gl::RGB8,
+            ((5, 6, 5, 0), false, false) => gl::RGB565,
+            ((10, 10, 10, 2), false, false) => gl::RGB10_A2,
+            ((16, 16, 16, 16), true, false) => gl::RGBA16F,
+            _ => return None,
+        },
+    )
+}
+
+impl Target {
+    /// Fails with the status of the framebuffer if it is incomplete, after unbinding it.
+    fn new(
+        renderer: &Renderer,
+        deletion_queue: &DeletionQueue,
+        width: i32,
+        height: i32,
+        samples: u32,
+        strategy: Strategy,
+        format: gl::types::GLenum,
+    ) -> Result<Self, gl::types::GLenum> {
+        let gl = renderer.gl();
+        unsafe {
+            let mut framebuffer = 0;
+            gl.GenFramebuffers(1, &mut framebuffer);
+            let framebuffer = deletion_queue.wrap(GlObjectKind::Framebuffer, framebuffer);
+            gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer.name());
+
+            let color = match strategy {
+                Strategy::RenderToTexture => {
+                    let mut texture = 0;
+                    gl.GenTextures(1, &mut texture);
+                    let texture = deletion_queue.wrap(GlObjectKind::Texture, texture);
+                    gl.BindTexture(gl::TEXTURE_2D, texture.name());
+                    gl.TexStorage2D(gl::TEXTURE_2D, 1, format, width, height);
+                    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
+                    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
+                    gl.FramebufferTexture2DMultisampleEXT(
+                        gl::FRAMEBUFFER,
+                        gl::COLOR_ATTACHMENT0,
+                        gl::TEXTURE_2D,
+                        texture.name(),
+                        0,
+                        samples as _,
+                    );
+                    texture
+                }
+                Strategy::Resolve => {
+                    let mut renderbuffer = 0;
+                    gl.GenRenderbuffers(1, &mut renderbuffer);
+                    let renderbuffer =
+                        deletion_queue.wrap(GlObjectKind::Renderbuffer, renderbuffer);
+                    gl.BindRenderbuffer(gl::RENDERBUFFER, renderbuffer.name());
+                    gl.RenderbufferStorageMultisample(
+                        gl::RENDERBUFFER,
+                        samples as _,
+                        format,
+                        width,
+                        height,
+                    );
+                    gl.FramebufferRenderbuffer(
+                        gl::FRAMEBUFFER,
+                        gl::COLOR_ATTACHMENT0,
+                        gl::RENDERBUFFER,
+                        renderbuffer.name(),
+                    );
+                    renderbuffer
+                }
+            };
+            let status = gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                // The objects are queued for deletion when dropped
+                gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+                return Err(status);
+            }
+            debug!("Allocated {width}x{height} MSAA target with {samples} samples ({strategy:?})");
+
+            Ok(Self {
+                epoch: deletion_queue.epoch(),
+                width,
+                height,
+                samples,
+                strategy,
+                format,
+                framebuffer,
+                _color: color,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RGB8: ColorBufferType = ColorBufferType::Rgb {
+        r_size: 8,
+        g_size: 8,
+        b_size: 8,
+    };
+
+    #[test]
+    fn clamps_samples() {
+        // In descending order, as GL returns them
+        let supported = [8, 4, 2];
+        assert_eq!(clamp_samples(4, 16, &supported), 4);
+        assert_eq!(clamp_samples(16, 16, &supported), 8);
+        // Render-to-texture may support fewer samples than renderbuffers
+        assert_eq!(clamp_samples(8, 4, &supported), 4);
+        assert_eq!(clamp_samples(3, 16, &supported), 2);
+        assert_eq!(clamp_samples(8, 1, &supported), 1);
+        assert_eq!(clamp_samples(4, 16, &[]), 1);
+    }
+
+    #[test]
+    fn surface_formats() {
+        assert_eq!(surface_format(Some(RGB8), 8, false, false), Some(gl::RGBA8));
+        assert_eq!(
+            surface_format(Some(RGB8), 8, false, true),
+            Some(gl::SRGB8_ALPHA8)
+        );
+        assert_eq!(surface_format(Some(RGB8), 0, false, false), Some(gl::RGB8));
+        // SRGB8 is not renderable, and blitting requires identical formats
+        assert_eq!(surface_format(Some(RGB8), 0, false, true), None);
+
+        let rgb565 = ColorBufferType::Rgb {
+            r_size: 5,
+            g_size: 6,
+            b_size: 5,
+        };
+        assert_eq!(
+            surface_format(Some(rgb565), 0, false, false),
+            Some(gl::RGB565)
+        );
+        let rgb10 = ColorBufferType::Rgb {
+            r_size: 10,
+            g_size: 10,
+            b_size: 10,
+        };
+        assert_eq!(
+            surface_format(Some(rgb10), 2, false, false),
+            Some(gl::RGB10_A2)
+        );
+        let rgb16 = ColorBufferType::Rgb {
+            r_size: 16,
+            g_size: 16,
+            b_size: 16,
+        };
+        assert_eq!(
+            surface_format(Some(rgb16), 16, true, false),
+            Some(gl::RGBA16F)
+        );
+        assert_eq!(surface_format(Some(rgb16), 16, false, false), None);
+
+        assert_eq!(
+            surface_format(Some(ColorBufferType::Luminance(8)), 0, false, false),
+            None
+        );
+        assert_eq!(surface_format(None, 8, false, false), None);
+    }
+}
@@ -0,0 +1,158 @@
+//! Per-pixel conversions between the configs of an `android.graphics.Bitmap` and what GL reads
+//! and writes, see [`crate::bitmap`].  They do not touch Android, so that they can be tested on the
+//! host.
+//!
+//! GL works with straight alpha whereas bitmaps are usually premultiplied.  `RGBA_8888` pixels
+//! are sRGB-encoded and `RGBA_F16` pixels linear.
+
+use half::f16;
+
+use crate::color;
+
+/// Premultiplies the color of an RGBA8 `pixel` by its alpha, rounding to the nearest value.
+pub fn premultiply_rgba8(pixel: &mut [u8]) {
+    let alpha = u32::from(pixel[3]);
+    for c in &mut pixel[..3] {
+        *c = ((u32::from(*c) * alpha + 127) / 255) as u8;
+    }
+}
+
+/// Undoes [`premultiply_rgba8()`] as far as the precision allows.
+pub fn unpremultiply_rgba8(pixel: &mut [u8]) {
+    let alpha = u32::from(pixel[3]);
+    // Fully transparent pixels have lost their color
+    if alpha == 0 || alpha == 255 {
+        return;
+    }
+    for c in &mut pixel[..3] {
+        *c = ((u32::from(*c) * 255 + alpha / 2) / alpha).min(255) as u8;
+    }
+}
+
+/// Divides the color of an RGBA16F `pixel` by its alpha.
+pub fn unpremultiply_rgba16f(pixel: &mut [u8]) {
+    let alpha = read_f16(&pixel[6..]);
+    if alpha <= 0.0 || alpha == 1.0 {
+        return;
+    }
+    for c in pixel[..6].chunks_exact_mut(2) {
+        write_f16(c, read_f16(c) / alpha);
+    }
+}
+
+/// Packs an RGBA8 pixel into `RGB_565`, rounding every channel to the nearest value and dropping
+/// alpha.
+pub fn rgba8_to_rgb565(pixel: &[u8]) -> u16 {
+    let scale = |c: u8, max: u16| (u16::from(c) * max + 127) / 255;
+    (scale(pixel[0], 31) << 11) | (scale(pixel[1], 63) << 5) | scale(pixel[2], 31)
+}
+
+/// Converts a straight-alpha, sRGB-encoded RGBA8 pixel to linear RGBA16F in `dst`, with the color
+/// premultiplied by alpha if `premultiplied`.
+pub fn rgba8_to_rgba16f(pixel: &[u8], dst: &mut [u8], premultiplied: bool) {
+    let alpha = f32::from(pixel[3]) / 255.0;
+    let scale = if premultiplied { alpha } else { 1.0 };
+    for (dst, &c) in dst.chunks_exact_mut(2).zip(&pixel[..3]) {
+        write_f16(dst, color::srgb_to_linear(f32::from(c) / 255.0) * scale);
+    }
+    write_f16(&mut dst[6..], alpha);
+}
+
+/// Reads a half float in native byte order
+pub fn read_f16(bytes: &[u8]) -> f32 {
+    f16::from_bits(u16::from_ne_bytes([bytes[0], bytes[1]])).to_f32()
+}
+
+/// Writes a half float in native byte order
+pub fn write_f16(bytes: &mut [u8], value: f32) {
+    bytes[..2].copy_from_slice(&f16::from_f32(value).to_bits().to_ne_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba16f(pixel: &[u8]) -> [f32; 4] {
+        let mut channels = [0.0; 4];
+        for (channel, bytes) in channels.iter_mut().zip(pixel.chunks_exact(2)) {
+            *channel = read_f16(bytes);
+        }
+        channels
+    }
+
+    #[test]
+    fn premultiplied_pixels_round_trip() {
+        for alpha in 1..=255u8 {
+            // Premultiplying loses up to half a step, which unpremultiplying scales up
+            let tolerance = (255 / i32::from(alpha) + 1) / 2;
+            for c in 0..=255u8 {
+                let mut pixel = [c, c, c, alpha];
+                premultiply_rgba8(&mut pixel);
+                assert!(pixel[0] <= alpha);
+                unpremultiply_rgba8(&mut pixel);
+                let error = (i32::from(pixel[0]) - i32::from(c)).abs();
+                assert!(
+                    error <= tolerance,
+                    "{c} at alpha {alpha} became {}",
+                    pixel[0]
+                );
+                assert_eq!(pixel[3], alpha);
+            }
+        }
+    }
+
+    #[test]
+    fn opaque_and_transparent_pixels() {
+        let mut opaque = [10, 128, 255, 255];
+        premultiply_rgba8(&mut opaque);
+        assert_eq!(opaque, [10, 128, 255, 255]);
+        unpremultiply_rgba8(&mut opaque);
+        assert_eq!(opaque, [10, 128, 255, 255]);
+
+        let mut transparent = [10, 128, 255, 0];
+        premultiply_rgba8(&mut transparent);
+        assert_eq!(transparent, [0, 0, 0, 0]);
+        unpremultiply_rgba8(&mut transparent);
+        assert_eq!(transparent, [0, 0, 0, 0]);
+
+        let mut transparent = [0u8; 8];
+        write_f16(&mut transparent[..2], 0.5);
+        unpremultiply_rgba16f(&mut transparent);
+        assert_eq!(rgba16f(&transparent), [0.5, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn rgb565_packs_and_rounds() {
+        assert_eq!(rgba8_to_rgb565(&[255, 255, 255, 0]), 0xffff);
+        assert_eq!(rgba8_to_rgb565(&[0, 0, 0, 255]), 0);
+        assert_eq!(rgba8_to_rgb565(&[255, 0, 0, 255]), 0xf800);
+        assert_eq!(rgba8_to_rgb565(&[0, 255, 0, 255]), 0x07e0);
+        assert_eq!(rgba8_to_rgb565(&[0, 0, 255, 255]), 0x001f);
+        // Rounded rather than truncated: 5/255 is closer to 1/31 than to 0
+        assert_eq!(rgba8_to_rgb565(&[5, 3, 4, 255]), (1 << 11) | (1 << 5));
+        assert_eq!(
+            rgba8_to_rgb565(&[128, 128, 128, 255]),
+            (16 << 11) | (32 << 5) | 16
+        );
+    }
+
+    #[test]
+    fn rgba16f_is_linear_and_premultiplied() {
+        let mut dst = [0; 8];
+        rgba8_to_rgba16f(&[255, 128, 0, 51], &mut dst, false);
+        let [r, g, b, a] = rgba16f(&dst);
+        assert_eq!((r, b), (1.0, 0.0));
+        assert!((g - color::srgb_to_linear(128.0 / 255.0)).abs() < 1e-3);
+        assert!((a - 0.2).abs() < 1e-3);
+
+        rgba8_to_rgba16f(&[255, 128, 0, 51], &mut dst, true);
+        let [r, g, _, a] = rgba16f(&dst);
+        assert!((r - 0.2).abs() < 1e-3);
+        assert!((g - color::srgb_to_linear(128.0 / 255.0) * 0.2).abs() < 1e-3);
+
+        unpremultiply_rgba16f(&mut dst);
+        let [r, _, _, unpremultiplied_a] = rgba16f(&dst);
+        assert!((r - 1.0).abs() < 2e-3);
+        assert_eq!(unpremultiplied_a, a);
+    }
+}
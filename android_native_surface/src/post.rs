@@ -0,0 +1,562 @@
+//! Full-screen post-processing passes that run between drawing a window and presenting it.
+//!
+//! When a window has any [`Pass`]es, its frame is drawn into an offscreen color target instead of
+//! the surface.  Every pass then samples the output of the previous one and draws a full-screen
+//! triangle, ping-ponging between two pooled targets, and the last pass writes to the surface.
+//! The targets are owned by the window's [`PostChain`] and reallocated when it is resized, while
+//! the compiled shaders are shared by all windows through the [`PostRenderer`].
+//!
+//! Targets are `RGBA16F` where the GPU can render to it, so that tone mapping has values above
+//! `1` to work with, and otherwise match the surface.
+//!
+//! A pass is a fragment shader that samples `image` at `v_uv`, plus the uniforms that are set from
+//! its [`Pass`] variant.  Adding one takes a [`PassKind`] (and its value on the Kotlin side), a
+//! shader in [`PassKind::fragment_source()`] and its uniforms in [`PostRenderer::run()`].
+
+use std::collections::HashMap;
+
+use log::{debug, warn};
+
+use crate::{
+    deletion_queue::{DeletionQueue, GlObject, GlObjectKind},
+    registry::Handle,
+    support::{capabilities::Feature, create_program, gl, texture::Texture, Renderer},
+    trace::Section,
+};
+
+/// Passes as they are numbered on the Kotlin side
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PassKind {
+    Blur = 0,
+    Vignette = 1,
+    ToneMap = 2,
+    ColorLut = 3,
+}
+
+impl TryFrom<i32> for PassKind {
+    type Error = i32;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Blur),
+            1 => Ok(Self::Vignette),
+            2 => Ok(Self::ToneMap),
+            3 => Ok(Self::ColorLut),
+            x => Err(x),
+        }
+    }
+}
+
+impl PassKind {
+    /// Asset path and built-in source of the fragment shader
+    fn fragment_source(self) -> (&'static str, &'static [u8]) {
+        match self {
+            Self::Blur => ("shaders/post/blur.frag", BLUR_SOURCE),
+            Self::Vignette => ("shaders/post/vignette.frag", VIGNETTE_SOURCE),
+            Self::ToneMap => ("shaders/post/tone_map.frag", TONE_MAP_SOURCE),
+            Self::ColorLut => ("shaders/post/color_lut.frag", COLOR_LUT_SOURCE),
+        }
+    }
+}
+
+/// Width and height of the texture of a [`Pass::ColorLut`]
+pub const LUT_SIZE: (u32, u32) = (256, 16);
+
+/// One full-screen pass with its parameters
+#[derive(Clone, Copy, Debug)]
+pub enum Pass {
+    /// 3x3 Gaussian with taps `radius` pixels apart
+    Blur { radius: f32 },
+    /// Darkens towards the corners, by `strength` at the corners themselves
+    Vignette { strength: f32 },
+    /// Reinhard operator, after multiplying by `exposure`
+    ToneMap { exposure: f32 },
+    /// Maps colors through a 16x16x16 LUT, laid out as a [`LUT_SIZE`] texture of 16 blue slices
+    ColorLut { lut: Handle<Texture> },
+}
+
+impl Pass {
+    /// Builds a pass from its Kotlin representation, where `value` is the parameter of the float
+    /// variants and `texture` the handle of the texture variants.
+    pub fn new(kind: PassKind, value: f32, texture: Handle<Texture>) -> Self {
+        match kind {
+            PassKind::Blur => Self::Blur { radius: value },
+            PassKind::Vignette => Self::Vignette { strength: value },
+            PassKind::ToneMap => Self::ToneMap { exposure: value },
+            PassKind::ColorLut => Self::ColorLut { lut: texture },
+        }
+    }
+
+    pub fn kind(&self) -> PassKind {
+        match self {
+            Self::Blur { .. } => PassKind::Blur,
+            Self::Vignette { .. } => PassKind::Vignette,
+            Self::ToneMap { .. } => PassKind::ToneMap,
+            Self::ColorLut { .. } => PassKind::ColorLut,
+        }
+    }
+}
+
+/// Offscreen color target
+#[derive(Debug)]
+struct Target {
+    // Dropped (and thus queued for deletion) in declaration order
+    framebuffer: GlObject,
+    texture: GlObject,
+}
+
+/// Passes of one window, and the targets that they ping-pong between
+#[derive(Debug, Default)]
+pub struct PostChain {
+    pub passes: Vec<Pass>,
+    /// [`DeletionQueue::epoch()`], size and format that all targets were created with
+    allocation: (u64, i32, i32, gl::types::GLenum),
+    /// The first one is drawn into by the window, and at most two are needed
+    targets: Vec<Target>,
+    /// Allocation that the framebuffer was incomplete for, and that post-processing is off for.
+    /// Not retried until it changes.
+    unsupported: Option<(u64, i32, i32, gl::types::GLenum)>,
+}
+
+impl PostChain {
+    /// Binds and returns the framebuffer that the window must be drawn into, and its sized
+    /// internal format, allocating or resizing the targets as needed.  Returns [`None`], and
+    /// releases the targets, when there are no passes or the targets cannot be rendered to, in
+    /// which case the frame is drawn without post-processing.  `srgb` is the colorspace of the
+    /// surface.
+    pub fn begin(
+        &mut self,
+        renderer: &Renderer,
+        deletion_queue: &DeletionQueue,
+        width: i32,
+        height: i32,
+        srgb: bool,
+    ) -> Option<(gl::types::GLuint, gl::types::GLenum)> {
+        if self.passes.is_empty() {
+            self.targets.clear();
+            return None;
+        }
+
+        let format = if renderer
+            .capabilities()
+            .supports(Feature::ColorBufferHalfFloat)
+        {
+            gl::RGBA16F
+        } else if srgb {
+            gl::SRGB8_ALPHA8
+        } else {
+            gl::RGBA8
+        };
+        let allocation = (deletion_queue.epoch(), width, height, format);
+        if allocation != self.allocation {
+            // Dropping targets of an older epoch does not queue their stale names
+            self.targets.clear();
+            self.allocation = allocation;
+        }
+
+        if self.unsupported == Some(allocation) {
+            return None;
+        }
+
+        let count = self.passes.len().min(2);
+        self.targets.truncate(count);
+        while self.targets.len() < count {
+            debug!("Allocating {width}x{height} post-processing target");
+            match Target::new(renderer.gl(), deletion_queue, width, height, format) {
+                Ok(target) => self.targets.push(target),
+                Err(status) => {
+                    warn!(
+                        "Cannot render to a {width}x{height} post-processing target (status \
+                         {status:#06x}), drawing without post-processing"
+                    );
+                    self.targets.clear();
+                    self.unsupported = Some(allocation);
+                    return None;
+                }
+            }
+        }
+
+        let framebuffer = self.targets[0].framebuffer.name();
+        unsafe { renderer.gl().BindFramebuffer(gl::FRAMEBUFFER, framebuffer) };
+        Some((framebuffer, format))
+    }
+}
+
+impl Target {
+    /// Fails with the status of the framebuffer if it is incomplete, after unbinding it.
+    fn new(
+        gl: &gl::Gl,
+        deletion_queue: &DeletionQueue,
+        width: i32,
+        height: i32,
+        format: gl::types::GLenum,
+    ) -> Result<Self, gl::types::GLenum> {
+        unsafe {
+            let mut texture = 0;
+            gl.GenTextures(1, &mut texture);
+            let texture = deletion_queue.wrap(GlObjectKind::Texture, texture);
+            gl.BindTexture(gl::TEXTURE_2D, texture.name());
+            gl.TexStorage2D(gl::TEXTURE_2D, 1, format, width, height);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
+
+            let mut framebuffer = 0;
+            gl.GenFramebuffers(1, &mut framebuffer);
+            let framebuffer = deletion_queue.wrap(GlObjectKind::Framebuffer, framebuffer);
+            gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer.name());
+            gl.FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                texture.name(),
+                0,
+            );
+            let status = gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                // The objects are queued for deletion when dropped
+                gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+                return Err(status);
+            }
+
+            Ok(Self {
+                framebuffer,
+                texture,
+            })
+        }
+    }
+}
+
+/// Shaders and geometry of all passes, compiled on first use.
+///
+/// Must only be used with the context that it was created on.
+pub struct PostRenderer {
+    // Dropped (and thus queued for deletion) in declaration order
+    programs: HashMap<PassKind, GlObject>,
+    vao: GlObject,
+    /// Only referenced through `vao`
+    _vbo: GlObject,
+    deletion_queue: DeletionQueue,
+    gl: gl::Gl,
+}
+
+impl PostRenderer {
+    pub fn new(gl: &gl::Gl, deletion_queue: &DeletionQueue) -> Self {
+        unsafe {
+            let mut vao = std::mem::zeroed();
+            gl.GenVertexArrays(1, &mut vao);
+            gl.BindVertexArray(vao);
+
+            let mut vbo = std::mem::zeroed();
+            gl.GenBuffers(1, &mut vbo);
+            gl.BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl.BufferData(
+                gl::ARRAY_BUFFER,
+                std::mem::size_of_val(&FULL_SCREEN_TRIANGLE) as gl::types::GLsizeiptr,
+                FULL_SCREEN_TRIANGLE.as_ptr().cast(),
+                gl::STATIC_DRAW,
+            );
+            // Bound to location 0 in every program
+            gl.VertexAttribPointer(0, 2, gl::FLOAT, 0, 0, std::ptr::null());
+            gl.EnableVertexAttribArray(0);
+
+            Self {
+                programs: HashMap::new(),
+                vao: deletion_queue.wrap(GlObjectKind::VertexArray, vao),
+                _vbo: deletion_queue.wrap(GlObjectKind::Buffer, vbo),
+                deletion_queue: deletion_queue.clone(),
+                gl: gl.clone(),
+            }
+        }
+    }
+
+    fn program(&mut self, kind: PassKind) -> gl::types::GLuint {
+        let gl = &self.gl;
+        let deletion_queue = &self.deletion_queue;
+        let program = self.programs.entry(kind).or_insert_with(|| unsafe {
+            debug!("Compiling {kind:?} post-processing pass");
+            let program = create_program(
+                gl,
+                ("shaders/post/post.vert", VERTEX_SHADER_SOURCE),
+                kind.fragment_source(),
+                &[(0, c"position")],
+            );
+
+            gl.UseProgram(program);
+            gl.Uniform1i(gl.GetUniformLocation(program, c"image".as_ptr()), 0);
+            gl.Uniform1i(gl.GetUniformLocation(program, c"lut".as_ptr()), 1);
+            deletion_queue.wrap(GlObjectKind::Program, program)
+        });
+        program.name()
+    }
+
+    /// Runs every pass of `chain` over what was drawn into the framebuffer from
+    /// [`PostChain::begin()`], writing the last one to the window surface.  Textures are resolved
+    /// to their GL name with `texture_name`, and passes whose texture is gone are skipped.
+    /// Returns the number of passes that ran, none when [`PostChain::begin()`] had the frame drawn
+    /// without post-processing.
+    pub fn run(
+        &mut self,
+        chain: &PostChain,
+        width: i32,
+        height: i32,
+        texture_name: impl FnMut(Handle<Texture>) -> Option<gl::types::GLuint>,
+    ) -> usize {
+        if chain.targets.is_empty() {
+            return 0;
+        }
+        let steps = plan(&chain.passes, chain.targets.len(), texture_name);
+        for step in &steps {
+            let pass = &chain.passes[step.pass];
+            let _t = Section::new(&format!("{:?}", pass.kind())).unwrap();
+            let program = self.program(pass.kind());
+            let gl = &self.gl;
+            unsafe {
+                let framebuffer = step
+                    .destination
+                    .map_or(0, |target| chain.targets[target].framebuffer.name());
+                gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+                gl.UseProgram(program);
+                gl.BindVertexArray(self.vao.name());
+                gl.ActiveTexture(gl::TEXTURE0);
+                gl.BindTexture(gl::TEXTURE_2D, chain.targets[step.source].texture.name());
+
+                let uniform = |name: &std::ffi::CStr| gl.GetUniformLocation(program, name.as_ptr());
+                gl.Uniform2f(uniform(c"texel"), 1.0 / width as f32, 1.0 / height as f32);
+                match *pass {
+                    Pass::Blur { radius } => gl.Uniform1f(uniform(c"radius"), radius),
+                    Pass::Vignette { strength } => gl.Uniform1f(uniform(c"strength"), strength),
+                    Pass::ToneMap { exposure } => gl.Uniform1f(uniform(c"exposure"), exposure),
+                    Pass::ColorLut { .. } => {
+                        gl.ActiveTexture(gl::TEXTURE1);
+                        // Without a LUT, pass the colors through unchanged
+                        gl.BindTexture(gl::TEXTURE_2D, step.lut.unwrap_or(0));
+                        gl.Uniform1f(uniform(c"enabled"), step.lut.map_or(0.0, |_| 1.0));
+                        gl.ActiveTexture(gl::TEXTURE0);
+                    }
+                }
+                gl.DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+        }
+        steps.len()
+    }
+}
+
+/// One pass that [`PostRenderer::run()`] draws
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Step {
+    /// Index into [`PostChain::passes`]
+    pass: usize,
+    /// Target that holds the latest result
+    source: usize,
+    /// Target to draw into, [`None`] for the window surface
+    destination: Option<usize>,
+    /// Resolved texture of a [`Pass::ColorLut`]
+    lut: Option<gl::types::GLuint>,
+}
+
+/// Orders `passes` to ping-pong between `targets`, starting from the first one that the window
+/// was drawn into.  A [`Pass::ColorLut`] whose texture is gone is skipped, unless it is the last
+/// pass, which always runs to present the result.
+fn plan(
+    passes: &[Pass],
+    targets: usize,
+    mut texture_name: impl FnMut(Handle<Texture>) -> Option<gl::types::GLuint>,
+) -> Vec<Step> {
+    let mut steps = Vec::with_capacity(passes.len());
+    let mut source = 0;
+    for (i, pass) in passes.iter().enumerate() {
+        let last = i + 1 == passes.len();
+        let lut = match *pass {
+            Pass::ColorLut { lut } => match texture_name(lut) {
+                Some(lut) => Some(lut),
+                None if !last => continue,
+                None => None,
+            },
+            _ => None,
+        };
+        let destination = (!last).then_some((source + 1) % targets);
+        steps.push(Step {
+            pass: i,
+            source,
+            destination,
+            lut,
+        });
+        if let Some(destination) = destination {
+            source = destination;
+        }
+    }
+    steps
+}
+
+/// Covers the whole viewport with a single triangle, clipped to the screen
+#[rustfmt::skip]
+static FULL_SCREEN_TRIANGLE: [f32; 6] = [
+    -1.0, -1.0,
+     3.0, -1.0,
+    -1.0,  3.0,
+];
+
+const VERTEX_SHADER_SOURCE: &[u8] = b"
+#version 100
+precision mediump float;
+
+attribute vec2 position;
+
+varying vec2 v_uv;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+    v_uv = position * 0.5 + 0.5;
+}
+\0";
+
+const BLUR_SOURCE: &[u8] = b"
+#version 100
+precision mediump float;
+
+uniform sampler2D image;
+uniform vec2 texel;
+uniform float radius;
+
+varying vec2 v_uv;
+
+void main() {
+    vec2 d = texel * radius;
+    vec4 sum = texture2D(image, v_uv) * 4.0;
+    sum += (texture2D(image, v_uv + vec2(d.x, 0.0)) + texture2D(image, v_uv - vec2(d.x, 0.0))
+        + texture2D(image, v_uv + vec2(0.0, d.y)) + texture2D(image, v_uv - vec2(0.0, d.y))) * 2.0;
+    sum += texture2D(image, v_uv + d) + texture2D(image, v_uv - d)
+        + texture2D(image, v_uv + vec2(d.x, -d.y)) + texture2D(image, v_uv + vec2(-d.x, d.y));
+    gl_FragColor = sum / 16.0;
+}
+\0";
+
+const VIGNETTE_SOURCE: &[u8] = b"
+#version 100
+precision mediump float;
+
+uniform sampler2D image;
+uniform float strength;
+
+varying vec2 v_uv;
+
+void main() {
+    vec4 color = texture2D(image, v_uv);
+    // 0 in the center, 1 in the corners
+    float d = distance(v_uv, vec2(0.5)) * 1.41421356;
+    gl_FragColor = vec4(color.rgb * (1.0 - strength * d * d), color.a);
+}
+\0";
+
+const TONE_MAP_SOURCE: &[u8] = b"
+#version 100
+precision mediump float;
+
+uniform sampler2D image;
+uniform float exposure;
+
+varying vec2 v_uv;
+
+void main() {
+    vec4 color = texture2D(image, v_uv);
+    vec3 x = color.rgb * exposure;
+    gl_FragColor = vec4(x / (1.0 + x), color.a);
+}
+\0";
+
+const COLOR_LUT_SOURCE: &[u8] = b"
+#version 100
+precision mediump float;
+
+uniform sampler2D image;
+uniform sampler2D lut;
+uniform float enabled;
+
+varying vec2 v_uv;
+
+const float SIZE = 16.0;
+
+void main() {
+    vec4 color = texture2D(image, v_uv);
+    vec3 rgb = clamp(color.rgb, 0.0, 1.0);
+    // Interpolate between the two nearest blue slices, the texture filters red and green
+    float slice = rgb.b * (SIZE - 1.0);
+    float lower = floor(slice);
+    float upper = min(lower + 1.0, SIZE - 1.0);
+    vec2 uv = vec2((rgb.r * (SIZE - 1.0) + 0.5) / (SIZE * SIZE), (rgb.g * (SIZE - 1.0) + 0.5) / SIZE);
+    vec3 a = texture2D(lut, uv + vec2(lower / SIZE, 0.0)).rgb;
+    vec3 b = texture2D(lut, uv + vec2(upper / SIZE, 0.0)).rgb;
+    gl_FragColor = vec4(mix(color.rgb, mix(a, b, slice - lower), enabled), color.a);
+}
+\0";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lut(raw: i64) -> Pass {
+        Pass::ColorLut {
+            lut: Handle::from_raw(raw),
+        }
+    }
+
+    /// Resolves handle `n` to name `n`, and handle `0` to a deleted texture
+    fn texture_name(handle: Handle<Texture>) -> Option<gl::types::GLuint> {
+        let raw = handle.into_raw();
+        (raw != 0).then_some(raw as gl::types::GLuint)
+    }
+
+    fn route(steps: &[Step]) -> Vec<(usize, usize, Option<usize>)> {
+        steps
+            .iter()
+            .map(|step| (step.pass, step.source, step.destination))
+            .collect()
+    }
+
+    #[test]
+    fn pass_kinds_match_kotlin() {
+        for kind in [
+            PassKind::Blur,
+            PassKind::Vignette,
+            PassKind::ToneMap,
+            PassKind::ColorLut,
+        ] {
+            assert_eq!(PassKind::try_from(kind as i32), Ok(kind));
+        }
+        assert_eq!(PassKind::try_from(4), Err(4));
+        assert_eq!(PassKind::try_from(-1), Err(-1));
+    }
+
+    #[test]
+    fn passes_ping_pong_and_end_on_the_surface() {
+        let blur = Pass::Blur { radius: 1.0 };
+        let steps = plan(&[blur; 4], 2, texture_name);
+        assert_eq!(
+            route(&steps),
+            [
+                (0, 0, Some(1)),
+                (1, 1, Some(0)),
+                (2, 0, Some(1)),
+                (3, 1, None)
+            ]
+        );
+
+        // A single pass only needs the target that the window was drawn into
+        let steps = plan(&[blur], 1, texture_name);
+        assert_eq!(route(&steps), [(0, 0, None)]);
+    }
+
+    #[test]
+    fn passes_without_a_lut_are_skipped_unless_last() {
+        let vignette = Pass::Vignette { strength: 0.5 };
+        let steps = plan(&[lut(0), vignette, lut(7), lut(0)], 2, texture_name);
+        assert_eq!(
+            route(&steps),
+            [(1, 0, Some(1)), (2, 1, Some(0)), (3, 0, None)]
+        );
+        let luts = steps.iter().map(|step| step.lut).collect::<Vec<_>>();
+        assert_eq!(luts, [None, Some(7), None]);
+    }
+}
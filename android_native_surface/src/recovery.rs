@@ -0,0 +1,241 @@
+//! Classification of EGL errors into recovery actions.
+//!
+//! Contexts can be lost (e.g. after a GPU reset) and surfaces can become invalid (e.g. when the
+//! consumer side of a [`ndk::native_window::NativeWindow`] is abandoned) at any point.  Rather
+//! than panicking on these, [`crate::NativeGL`] maps them to one of the [`Recovery`] actions below
+//! and performs it through [`recover()`].  Every action can be exercised on demand with a
+//! [`FaultInjector`].
+
+use glutin::error::ErrorKind;
+
+/// Recovery action taken after `make_current()` or `swap_buffers()` failed.
+///
+/// Must remain in sync with `Recovery` in Kotlin, which receives the discriminant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recovery {
+    /// The context is gone along with all objects in it: recreate it and the `Renderer`.
+    RecreateContext = 0,
+    /// The `EGLSurface` is no longer usable, but the `NativeWindow` behind it might be: create a
+    /// new surface on the same window.
+    RecreateSurface = 1,
+    /// The `NativeWindow` itself is dead and can no longer be rendered to: drop the window.
+    DropWindow = 2,
+}
+
+impl Recovery {
+    /// Returns [`None`] for errors that are not caused by lost state, and hence indicate a bug.
+    pub fn from_error_kind(kind: ErrorKind) -> Option<Self> {
+        Some(match kind {
+            ErrorKind::ContextLost | ErrorKind::BadContext | ErrorKind::BadContextState => {
+                Self::RecreateContext
+            }
+            ErrorKind::BadSurface | ErrorKind::BadCurrentSurface => Self::RecreateSurface,
+            ErrorKind::BadNativeWindow => Self::DropWindow,
+            _ => return None,
+        })
+    }
+
+    /// The [`ErrorKind`] to inject with [`FaultInjector::inject()`] to exercise this path.
+    pub fn to_error_kind(self) -> ErrorKind {
+        match self {
+            Self::RecreateContext => ErrorKind::ContextLost,
+            Self::RecreateSurface => ErrorKind::BadSurface,
+            Self::DropWindow => ErrorKind::BadNativeWindow,
+        }
+    }
+}
+
+impl TryFrom<i32> for Recovery {
+    type Error = i32;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::RecreateContext),
+            1 => Ok(Self::RecreateSurface),
+            2 => Ok(Self::DropWindow),
+            x => Err(x),
+        }
+    }
+}
+
+/// Error returned from the next `swap_buffers()` instead of calling into EGL, to exercise the
+/// [`Recovery`] paths without having to provoke a real GPU reset or dead window.
+#[derive(Debug, Default)]
+pub struct FaultInjector(Option<ErrorKind>);
+
+impl FaultInjector {
+    /// Makes the next [`FaultInjector::take()`] return the error that triggers `recovery`.
+    pub fn inject(&mut self, recovery: Recovery) {
+        self.0 = Some(recovery.to_error_kind());
+    }
+
+    pub fn take(&mut self) -> Option<ErrorKind> {
+        self.0.take()
+    }
+}
+
+/// The state of a window and its context that a [`Recovery`] acts on
+pub trait Recover {
+    /// Replaces the surface with a new one on the same `NativeWindow`.
+    fn recreate_surface(&mut self) -> glutin::error::Result<()>;
+    /// Whether the context is still there, it is dropped when it cannot be made not-current.
+    fn has_context(&self) -> bool;
+    /// Replaces the context, and thereby all GL objects.
+    fn recreate_context(&mut self);
+}
+
+/// Performs `recovery` on `target`, returning the action that was eventually taken: a surface
+/// that cannot be recreated escalates to [`Recovery::DropWindow`].
+pub fn recover(target: &mut impl Recover, recovery: Recovery) -> Recovery {
+    let recovery = match recovery {
+        Recovery::RecreateSurface => match target.recreate_surface() {
+            Ok(()) => Recovery::RecreateSurface,
+            Err(_) => Recovery::DropWindow,
+        },
+        recovery => recovery,
+    };
+
+    // The context may also have been dropped when it could not be made not-current
+    if recovery == Recovery::RecreateContext || !target.has_context() {
+        target.recreate_context();
+    }
+
+    recovery
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Recovery; 3] = [
+        Recovery::RecreateContext,
+        Recovery::RecreateSurface,
+        Recovery::DropWindow,
+    ];
+
+    /// Counts the actions that were taken, instead of calling into EGL
+    #[derive(Debug, Default)]
+    struct Target {
+        /// Error that [`Recover::recreate_surface()`] fails with
+        surface_error: Option<ErrorKind>,
+        has_context: bool,
+        surfaces_recreated: usize,
+        contexts_recreated: usize,
+    }
+
+    impl Target {
+        fn new() -> Self {
+            Self {
+                has_context: true,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl Recover for Target {
+        fn recreate_surface(&mut self) -> glutin::error::Result<()> {
+            self.surfaces_recreated += 1;
+            self.surface_error.map_or(Ok(()), |kind| Err(kind.into()))
+        }
+
+        fn has_context(&self) -> bool {
+            self.has_context
+        }
+
+        fn recreate_context(&mut self) {
+            self.has_context = true;
+            self.contexts_recreated += 1;
+        }
+    }
+
+    /// Injects `recovery` and recovers from the error that it produces, like a failed frame does.
+    fn inject_and_recover(target: &mut Target, recovery: Recovery) -> Recovery {
+        let mut faults = FaultInjector::default();
+        faults.inject(recovery);
+        let kind = faults.take().expect("Fault was not injected");
+        assert_eq!(faults.take(), None, "Fault is only injected once");
+        recover(target, Recovery::from_error_kind(kind).unwrap())
+    }
+
+    #[test]
+    fn injected_faults_map_back() {
+        for recovery in ALL {
+            assert_eq!(
+                Recovery::from_error_kind(recovery.to_error_kind()),
+                Some(recovery)
+            );
+        }
+    }
+
+    #[test]
+    fn java_discriminants_round_trip() {
+        for recovery in ALL {
+            assert_eq!(Recovery::try_from(recovery as i32), Ok(recovery));
+        }
+        assert_eq!(Recovery::try_from(3), Err(3));
+        assert_eq!(Recovery::try_from(-1), Err(-1));
+    }
+
+    #[test]
+    fn bugs_are_not_recovered_from() {
+        for kind in [
+            ErrorKind::OutOfMemory,
+            ErrorKind::BadParameter,
+            ErrorKind::BadMatch,
+        ] {
+            assert_eq!(Recovery::from_error_kind(kind), None, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn recreate_context() {
+        let mut target = Target::new();
+        let taken = inject_and_recover(&mut target, Recovery::RecreateContext);
+        assert_eq!(taken, Recovery::RecreateContext);
+        assert_eq!(target.surfaces_recreated, 0);
+        assert_eq!(target.contexts_recreated, 1);
+    }
+
+    #[test]
+    fn recreate_surface() {
+        let mut target = Target::new();
+        let taken = inject_and_recover(&mut target, Recovery::RecreateSurface);
+        assert_eq!(taken, Recovery::RecreateSurface);
+        assert_eq!(target.surfaces_recreated, 1);
+        assert_eq!(target.contexts_recreated, 0);
+    }
+
+    #[test]
+    fn recreate_surface_escalates_to_drop_window() {
+        let mut target = Target {
+            surface_error: Some(ErrorKind::BadNativeWindow),
+            ..Target::new()
+        };
+        let taken = inject_and_recover(&mut target, Recovery::RecreateSurface);
+        assert_eq!(taken, Recovery::DropWindow);
+        assert_eq!(target.surfaces_recreated, 1);
+        assert_eq!(target.contexts_recreated, 0);
+    }
+
+    #[test]
+    fn drop_window() {
+        let mut target = Target::new();
+        let taken = inject_and_recover(&mut target, Recovery::DropWindow);
+        assert_eq!(taken, Recovery::DropWindow);
+        assert_eq!(target.surfaces_recreated, 0);
+        assert_eq!(target.contexts_recreated, 0);
+    }
+
+    #[test]
+    fn lost_context_is_always_recreated() {
+        for recovery in ALL {
+            let mut target = Target {
+                has_context: false,
+                ..Target::new()
+            };
+            inject_and_recover(&mut target, recovery);
+            assert!(target.has_context, "{recovery:?}");
+            assert_eq!(target.contexts_recreated, 1, "{recovery:?}");
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Generational slot map of objects that are referenced from Java by an opaque `long` handle.
+//!
+//! Java never sees a pointer: a [`Handle`] packs a slot index with the generation of that slot,
+//! which is bumped whenever an object is removed.  Using a handle after (or while) its object is
+//! removed, and removing it twice, are detected as [`RegistryError::Stale`] instead of being
+//! undefined behaviour.
+//!
+//! Every object sits behind its own [`Mutex`], so that calls from different Java threads on
+//! different objects run concurrently, while calls on the same object are serialized.  The table
+//! lock is only held while looking up a slot, never while calling into an object.
+
+use std::{
+    fmt,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+
+/// Opaque, typed reference to an object in a [`Registry<T>`]
+pub struct Handle<T> {
+    index: u32,
+    /// Never `0`, so that a valid handle never equals Java's "no native object" sentinel
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+// Derives would add unnecessary bounds on T
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle")
+            .field("index", &self.index)
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+impl<T> Handle<T> {
+    pub fn from_raw(raw: i64) -> Self {
+        let raw = raw as u64;
+        Self {
+            index: raw as u32,
+            generation: (raw >> 32) as u32,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn into_raw(self) -> i64 {
+        (((self.generation as u64) << 32) | self.index as u64) as i64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegistryError {
+    /// The handle is `0`, i.e. it was never assigned on the Java side
+    Null,
+    /// The handle does not (or no longer) refer to a live object
+    Stale { index: u32, generation: u32 },
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("Null handle"),
+            Self::Stale { index, generation } => write!(
+                f,
+                "Stale handle (index {index}, generation {generation}): used after or during removal"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+pub type Result<T, E = RegistryError> = std::result::Result<T, E>;
+
+/// [`None`] once the object is removed, for callers that looked up the slot before that
+type Entry<T> = Arc<Mutex<Option<T>>>;
+
+struct Slot<T> {
+    generation: u32,
+    entry: Option<Entry<T>>,
+}
+
+struct Slots<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+pub struct Registry<T> {
+    slots: Mutex<Slots<T>>,
+}
+
+impl<T> Registry<T> {
+    pub const fn new() -> Self {
+        Self {
+            slots: Mutex::new(Slots {
+                slots: Vec::new(),
+                free: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn insert(&self, value: T) -> Handle<T> {
+        let entry = Arc::new(Mutex::new(Some(value)));
+        let mut slots = self.slots.lock().unwrap();
+        let index = match slots.free.pop() {
+            Some(index) => index,
+            None => {
+                slots.slots.push(Slot {
+                    generation: 1,
+                    entry: None,
+                });
+                u32::try_from(slots.slots.len() - 1).expect("Registry is full")
+            }
+        };
+        let slot = &mut slots.slots[index as usize];
+        debug_assert!(slot.entry.is_none());
+        slot.entry = Some(entry);
+        Handle {
+            index,
+            generation: slot.generation,
+            _marker: PhantomData,
+        }
+    }
+
+    fn entry(&self, handle: Handle<T>) -> Result<Entry<T>> {
+        if handle.into_raw() == 0 {
+            return Err(RegistryError::Null);
+        }
+        let stale = RegistryError::Stale {
+            index: handle.index,
+            generation: handle.generation,
+        };
+        let slots = self.slots.lock().unwrap();
+        match slots.slots.get(handle.index as usize) {
+            Some(Slot {
+                generation,
+                entry: Some(entry),
+            }) if *generation == handle.generation => Ok(entry.clone()),
+            _ => Err(stale),
+        }
+    }
+
+    /// Calls `f` with exclusive access to the object behind `handle`.
+    ///
+    /// Nesting calls on different registries is allowed, as long as every call site nests them in
+    /// the same order.
+    pub fn with<R>(&self, handle: Handle<T>, f: impl FnOnce(&mut T) -> R) -> Result<R> {
+        let entry = self.entry(handle)?;
+        let mut value = entry.lock().unwrap();
+        match value.as_mut() {
+            Some(value) => Ok(f(value)),
+            // Removed after we looked up the slot
+            None => Err(RegistryError::Stale {
+                index: handle.index,
+                generation: handle.generation,
+            }),
+        }
+    }
+
+    /// Removes and returns the object behind `handle` if `predicate` returns `true` for it.
+    ///
+    /// Waits for in-flight [`Registry::with()`] calls on the same object to finish first.
+    pub fn remove_if(
+        &self,
+        handle: Handle<T>,
+        predicate: impl FnOnce(&T) -> bool,
+    ) -> Result<Option<T>> {
+        let entry = self.entry(handle)?;
+        let value = {
+            let mut value = entry.lock().unwrap();
+            match value.as_ref() {
+                Some(v) if predicate(v) => value.take().unwrap(),
+                Some(_) => return Ok(None),
+                // Lost a race against another remove
+                None => {
+                    return Err(RegistryError::Stale {
+                        index: handle.index,
+                        generation: handle.generation,
+                    })
+                }
+            }
+        };
+
+        // Only the caller that took the value gets here, so the slot still has our generation
+        let mut slots = self.slots.lock().unwrap();
+        let slot = &mut slots.slots[handle.index as usize];
+        debug_assert_eq!(slot.generation, handle.generation);
+        slot.entry = None;
+        slot.generation = slot.generation.checked_add(1).unwrap_or(1);
+        slots.free.push(handle.index);
+
+        Ok(Some(value))
+    }
+
+    pub fn remove(&self, handle: Handle<T>) -> Result<T> {
+        self.remove_if(handle, |_| true)
+            .map(|value| value.expect("Predicate is always true"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stale<T>(handle: Handle<T>) -> RegistryError {
+        RegistryError::Stale {
+            index: handle.index,
+            generation: handle.generation,
+        }
+    }
+
+    #[test]
+    fn insert_and_access() {
+        let registry = Registry::new();
+        let a = registry.insert("a".to_owned());
+        let b = registry.insert("b".to_owned());
+        assert_ne!(a.into_raw(), b.into_raw());
+
+        registry.with(a, |a| a.push('!')).unwrap();
+        assert_eq!(registry.with(a, |a| a.clone()), Ok("a!".to_owned()));
+        assert_eq!(registry.with(b, |b| b.clone()), Ok("b".to_owned()));
+    }
+
+    #[test]
+    fn removed_handles_are_stale() {
+        let registry = Registry::new();
+        let handle = registry.insert(1);
+        assert_eq!(registry.remove(handle), Ok(1));
+        assert_eq!(registry.with(handle, |_| ()), Err(stale(handle)));
+        assert_eq!(registry.remove(handle), Err(stale(handle)));
+    }
+
+    #[test]
+    fn slots_are_reused_with_a_new_generation() {
+        let registry = Registry::new();
+        let old = registry.insert(1);
+        registry.remove(old).unwrap();
+        let new = registry.insert(2);
+
+        assert_eq!(new.index, old.index);
+        assert_eq!(new.generation, old.generation + 1);
+        assert_eq!(registry.with(old, |_| ()), Err(stale(old)));
+        assert_eq!(registry.remove(old), Err(stale(old)));
+        assert_eq!(registry.with(new, |v| *v), Ok(2));
+    }
+
+    #[test]
+    fn null_and_unknown_handles_are_rejected() {
+        let registry = Registry::<i32>::new();
+        assert_eq!(
+            registry.with(Handle::from_raw(0), |_| ()),
+            Err(RegistryError::Null)
+        );
+        // Index 7, generation 1
+        let unknown = Handle::from_raw(1 << 32 | 7);
+        assert_eq!(registry.with(unknown, |_| ()), Err(stale(unknown)));
+    }
+
+    #[test]
+    fn remove_if_only_removes_on_true() {
+        let registry = Registry::new();
+        let handle = registry.insert(5);
+        assert_eq!(registry.remove_if(handle, |v| *v > 5), Ok(None));
+        assert_eq!(registry.with(handle, |v| *v), Ok(5));
+        assert_eq!(registry.remove_if(handle, |v| *v == 5), Ok(Some(5)));
+        assert_eq!(registry.remove_if(handle, |_| true), Err(stale(handle)));
+    }
+
+    #[test]
+    fn raw_handles_round_trip() {
+        let registry = Registry::new();
+        let handle = registry.insert(());
+        // Java treats 0 as "no native object"
+        assert_ne!(handle.into_raw(), 0);
+        let raw = handle.into_raw();
+        assert_eq!(registry.with(Handle::from_raw(raw), |_| ()), Ok(()));
+
+        // Every bit survives, including the sign bit in the generation
+        for raw in [1 << 32, i64::MAX, i64::MIN, -1, 0x1234_5678_9abc_def0] {
+            assert_eq!(Handle::<()>::from_raw(raw).into_raw(), raw);
+        }
+        let handle = Handle::<()>::from_raw(-1);
+        assert_eq!((handle.index, handle.generation), (u32::MAX, u32::MAX));
+    }
+}
@@ -0,0 +1,981 @@
+//! Retained 2D scene of colored rects and textured sprites, drawn on top of
+//! [`crate::support::Renderer`].
+//!
+//! A [`Scene`] is created from Kotlin and attached to any number of GL windows.  Every [`Node`]
+//! is a rect in pixels from the top-left of the window, with an affine [`Transform`], a z-order
+//! and an optional clip rect.  Each frame the nodes are turned into [`Quad`]s in z-order, which
+//! [`batch()`] groups by texture and clip: a quad joins an earlier batch when nothing that is
+//! drawn in between overlaps it, so that interleaved sprites of a few textures still take a few
+//! draw calls.  Colored rects sample a white texture, and batch with each other regardless of
+//! their color.
+//!
+//! Input from the windows that a scene is attached to is tracked per pointer through
+//! [`Scene::handle_motion()`], and can be drawn on top of the nodes for debugging.
+//!
+//! [`Scene::quads()`] and [`batch()`] do not touch GL, so that they can be tested on the host.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+};
+
+#[cfg(target_os = "android")]
+use ndk::event::MotionAction;
+
+#[cfg(target_os = "android")]
+use crate::input::MotionEvent;
+use crate::{
+    color,
+    deletion_queue::{DeletionQueue, GlObject, GlObjectKind},
+    registry::Handle,
+    support::{create_program, gl, texture::Texture},
+};
+
+/// Number of batches that a quad may be moved back over to join a batch with the same state
+const MAX_BATCH_LOOKBACK: usize = 8;
+
+/// Edge length in pixels of the marker of a pointer at zero pressure, see
+/// [`Scene::show_pointers`]
+const POINTER_SIZE: f32 = 48.0;
+const POINTER_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.6];
+/// Number of past positions of every pointer that are drawn as a trail
+const POINTER_TRAIL: usize = 32;
+const TRAIL_SIZE: f32 = 6.0;
+
+/// Axis-aligned rect in pixels, from the top-left of the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn right(&self) -> f32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> f32 {
+        self.y + self.height
+    }
+
+    /// The area that both rects cover, [`None`] if they don't overlap.  Touching edges do not
+    /// count as overlapping.
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        (right > x && bottom > y).then(|| Self::new(x, y, right - x, bottom - y))
+    }
+
+    /// Smallest rect that covers both rects
+    pub fn union(&self, other: &Self) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Self::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+}
+
+/// 2D affine transform, laid out like the first two rows of an `android.graphics.Matrix`:
+/// `x' = scale_x * x + skew_x * y + translate_x` and
+/// `y' = skew_y * x + scale_y * y + translate_y`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub scale_x: f32,
+    pub skew_x: f32,
+    pub translate_x: f32,
+    pub skew_y: f32,
+    pub scale_y: f32,
+    pub translate_y: f32,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        scale_x: 1.0,
+        skew_x: 0.0,
+        translate_x: 0.0,
+        skew_y: 0.0,
+        scale_y: 1.0,
+        translate_y: 0.0,
+    };
+
+    pub fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        (
+            self.scale_x * x + self.skew_x * y + self.translate_x,
+            self.skew_y * x + self.scale_y * y + self.translate_y,
+        )
+    }
+
+    /// Top-left, top-right, bottom-left and bottom-right corner of `rect` after transforming it
+    pub fn corners(&self, rect: &Rect) -> [(f32, f32); 4] {
+        [
+            self.apply((rect.x, rect.y)),
+            self.apply((rect.right(), rect.y)),
+            self.apply((rect.x, rect.bottom())),
+            self.apply((rect.right(), rect.bottom())),
+        ]
+    }
+
+    /// Axis-aligned bounds of `rect` after transforming it
+    pub fn bounds(&self, rect: &Rect) -> Rect {
+        let corners = self.corners(rect);
+        let (mut min, mut max) = (corners[0], corners[0]);
+        for (x, y) in corners {
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+        }
+        Rect::new(min.0, min.1, max.0 - min.0, max.1 - min.1)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Fill {
+    /// Straight-alpha RGBA
+    Color([f32; 4]),
+    /// The whole texture, multiplied by a straight-alpha RGBA `tint`
+    Texture {
+        texture: Handle<Texture>,
+        tint: [f32; 4],
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    /// Before [`Node::transform`] is applied
+    pub rect: Rect,
+    pub transform: Transform,
+    /// Nodes with a higher z are drawn on top, ties are drawn in insertion order
+    pub z: i32,
+    pub fill: Fill,
+    /// Only the part within this rect is drawn, which is not transformed
+    pub clip: Option<Rect>,
+}
+
+impl Node {
+    pub fn new(rect: Rect, z: i32, fill: Fill) -> Self {
+        Self {
+            rect,
+            transform: Transform::IDENTITY,
+            z,
+            fill,
+            clip: None,
+        }
+    }
+}
+
+/// Identifies a [`Node`] within its [`Scene`], never `0`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(u64);
+
+impl NodeId {
+    pub fn from_raw(raw: i64) -> Self {
+        Self(raw as u64)
+    }
+
+    pub fn into_raw(self) -> i64 {
+        self.0 as i64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SceneError {
+    /// The node was removed, or belongs to another scene
+    UnknownNode(NodeId),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownNode(id) => write!(f, "Scene has no node {}", id.0),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// A node resolved to window pixels and GL state, see [`Scene::quads()`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quad {
+    /// In the order of [`Transform::corners()`]
+    pub corners: [(f32, f32); 4],
+    /// Area that is actually drawn to, after clipping
+    pub bounds: Rect,
+    /// `0` for colored rects, which are drawn with a white texture
+    pub texture: gl::types::GLuint,
+    pub color: [f32; 4],
+    pub clip: Option<Rect>,
+}
+
+/// Latest state of a pointer that is down or hovering
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointerState {
+    pub position: (f32, f32),
+    pub pressure: f32,
+    pub tilt: f32,
+    pub orientation: f32,
+    /// Previous positions including historical samples, oldest first
+    pub trail: VecDeque<(f32, f32)>,
+}
+
+#[derive(Debug, Default)]
+pub struct Scene {
+    nodes: BTreeMap<NodeId, Node>,
+    /// Increasing, so that ids sort in insertion order
+    next_id: u64,
+    /// Keyed by pointer id
+    pointers: BTreeMap<i32, PointerState>,
+    /// Draw a marker for every pointer on top of all nodes
+    pub show_pointers: bool,
+}
+
+impl Scene {
+    pub fn insert(&mut self, node: Node) -> NodeId {
+        self.next_id += 1;
+        let id = NodeId(self.next_id);
+        self.nodes.insert(id, node);
+        id
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> Result<&mut Node, SceneError> {
+        self.nodes.get_mut(&id).ok_or(SceneError::UnknownNode(id))
+    }
+
+    pub fn remove(&mut self, id: NodeId) -> Result<Node, SceneError> {
+        self.nodes.remove(&id).ok_or(SceneError::UnknownNode(id))
+    }
+
+    /// Updates the pointers from `event`, in the coordinates of the window.
+    #[cfg(target_os = "android")]
+    pub fn handle_motion(&mut self, event: &MotionEvent) {
+        for sample in &event.samples {
+            for pointer in &sample.pointers {
+                let position = (pointer.x, pointer.y);
+                let state = self
+                    .pointers
+                    .entry(pointer.id)
+                    .or_insert_with(|| PointerState {
+                        position,
+                        pressure: 0.0,
+                        tilt: 0.0,
+                        orientation: 0.0,
+                        trail: VecDeque::with_capacity(POINTER_TRAIL),
+                    });
+                if state.trail.len() == POINTER_TRAIL {
+                    state.trail.pop_front();
+                }
+                state.trail.push_back(position);
+                state.position = position;
+                state.pressure = pointer.pressure;
+                state.tilt = pointer.tilt;
+                state.orientation = pointer.orientation;
+            }
+        }
+
+        match event.action {
+            MotionAction::Up | MotionAction::Cancel => self.pointers.clear(),
+            MotionAction::PointerUp | MotionAction::HoverExit => {
+                self.pointers.remove(&event.action_pointer_id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Marker and trail of every pointer, drawn over the nodes when [`Scene::show_pointers`]
+    fn pointer_quads(&self, viewport: Rect) -> impl Iterator<Item = Quad> + '_ {
+        let quad = move |rect: Rect, transform: Transform, color| {
+            Some(Quad {
+                corners: transform.corners(&rect),
+                bounds: transform.bounds(&rect).intersection(&viewport)?,
+                texture: 0,
+                color,
+                clip: None,
+            })
+        };
+        self.pointers
+            .values()
+            .filter(|_| self.show_pointers)
+            .flat_map(move |pointer| {
+                let trail = pointer
+                    .trail
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(i, &(x, y))| {
+                        let [r, g, b, a] = POINTER_COLOR;
+                        let fade = (i + 1) as f32 / pointer.trail.len() as f32;
+                        let half = TRAIL_SIZE / 2.0;
+                        let rect = Rect::new(x - half, y - half, TRAIL_SIZE, TRAIL_SIZE);
+                        quad(rect, Transform::IDENTITY, [r, g, b, a * fade])
+                    });
+
+                // Grows with pressure, and stretches along the orientation the more it is tilted
+                let width = POINTER_SIZE * (0.5 + pointer.pressure.min(1.5));
+                let height = width * (1.0 + pointer.tilt.sin());
+                let (sin, cos) = pointer.orientation.sin_cos();
+                let (x, y) = pointer.position;
+                let transform = Transform {
+                    scale_x: cos,
+                    skew_x: -sin,
+                    translate_x: x,
+                    skew_y: sin,
+                    scale_y: cos,
+                    translate_y: y,
+                };
+                let rect = Rect::new(-width / 2.0, -height / 2.0, width, height);
+                trail.chain(quad(rect, transform, POINTER_COLOR))
+            })
+    }
+
+    /// Every visible node within `viewport` followed by the pointer markers, in the order they
+    /// must be drawn.  Textures are resolved to their GL name with `texture_name`, and sprites
+    /// whose texture is gone are skipped.
+    pub fn quads(
+        &self,
+        viewport: &Rect,
+        mut texture_name: impl FnMut(Handle<Texture>) -> Option<gl::types::GLuint>,
+    ) -> Vec<Quad> {
+        let mut nodes = self.nodes.values().collect::<Vec<_>>();
+        // Stable, so that ties keep their insertion order
+        nodes.sort_by_key(|node| node.z);
+
+        nodes
+            .into_iter()
+            .filter_map(|node| {
+                let mut bounds = node.transform.bounds(&node.rect).intersection(viewport)?;
+                if let Some(clip) = &node.clip {
+                    bounds = bounds.intersection(clip)?;
+                }
+                let (texture, color) = match node.fill {
+                    Fill::Color(color) => (0, color),
+                    Fill::Texture { texture, tint } => (texture_name(texture)?, tint),
+                };
+                Some(Quad {
+                    corners: node.transform.corners(&node.rect),
+                    bounds,
+                    texture,
+                    color,
+                    clip: node.clip,
+                })
+            })
+            .chain(self.pointer_quads(*viewport))
+            .collect()
+    }
+}
+
+/// Quads that are drawn with one draw call, sharing a texture and clip
+#[derive(Clone, Debug, PartialEq)]
+pub struct Batch {
+    pub texture: gl::types::GLuint,
+    pub clip: Option<Rect>,
+    /// Indices into the quads passed to [`batch()`], in drawing order
+    pub quads: Vec<usize>,
+    /// Union of the bounds of all quads
+    pub bounds: Rect,
+}
+
+/// Groups `quads`, which are in drawing order, into as few batches as possible while drawing
+/// the same image.
+///
+/// A quad may only be moved back into an earlier batch if none of the batches after it overlaps
+/// the quad, as those would otherwise end up below it.
+pub fn batch(quads: &[Quad]) -> Vec<Batch> {
+    let mut batches = Vec::<Batch>::new();
+    for (i, quad) in quads.iter().enumerate() {
+        let target = batches
+            .iter()
+            .enumerate()
+            .rev()
+            .take(MAX_BATCH_LOOKBACK)
+            // Stop at the first batch that shares the state, or that the quad cannot move past
+            .find(|(_, batch)| {
+                (batch.texture == quad.texture && batch.clip == quad.clip)
+                    || batch.bounds.intersection(&quad.bounds).is_some()
+            })
+            .filter(|(_, batch)| batch.texture == quad.texture && batch.clip == quad.clip)
+            .map(|(index, _)| index);
+
+        match target {
+            Some(index) => {
+                let batch = &mut batches[index];
+                batch.quads.push(i);
+                batch.bounds = batch.bounds.union(&quad.bounds);
+            }
+            None => batches.push(Batch {
+                texture: quad.texture,
+                clip: quad.clip,
+                quads: vec![i],
+                bounds: quad.bounds,
+            }),
+        }
+    }
+    batches
+}
+
+/// Number of cells per row of [`gradient_ramp()`]
+pub const RAMP_STEPS: usize = 16;
+
+/// Largest difference of a linear value in [`check_gradient_ramp()`], which covers the rounding
+/// of the framebuffer to 8 bits
+const RAMP_TOLERANCE: f32 = 0.02;
+
+/// Value of ramp cell `step`, from `0` to `1`
+fn ramp_level(step: usize) -> f32 {
+    step as f32 / (RAMP_STEPS - 1) as f32
+}
+
+/// Test scene of two rows of [`RAMP_STEPS`] cells that fill a `width`x`height` viewport: opaque
+/// grays of increasing sRGB-encoded value on top, and white of increasing alpha over black below.
+pub fn gradient_ramp(width: f32, height: f32) -> Scene {
+    let mut scene = Scene::default();
+    let (cell_width, row_height) = (width / RAMP_STEPS as f32, height / 2.0);
+    let black = Fill::Color([0.0, 0.0, 0.0, 1.0]);
+    scene.insert(Node::new(
+        Rect::new(0.0, row_height, width, row_height),
+        0,
+        black,
+    ));
+    for step in 0..RAMP_STEPS {
+        let level = ramp_level(step);
+        let x = step as f32 * cell_width;
+        let gray = Fill::Color([level, level, level, 1.0]);
+        scene.insert(Node::new(
+            Rect::new(x, 0.0, cell_width, row_height),
+            0,
+            gray,
+        ));
+        let white = Fill::Color([1.0, 1.0, 1.0, level]);
+        scene.insert(Node::new(
+            Rect::new(x, row_height, cell_width, row_height),
+            1,
+            white,
+        ));
+    }
+    scene
+}
+
+/// Cell of [`gradient_ramp()`] whose color deviates from linear blending
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RampMismatch {
+    /// `0` for the grays, `1` for the blended whites
+    pub row: usize,
+    pub step: usize,
+    /// Linear values
+    pub expected: f32,
+    pub actual: f32,
+}
+
+impl fmt::Display for RampMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Gradient ramp cell {} of row {} is {:.4} instead of {:.4} linear",
+            self.step, self.row, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for RampMismatch {}
+
+/// Checks `pixels` of an sRGB framebuffer that [`gradient_ramp()`] was drawn into, as read by
+/// `glReadPixels()`: tightly packed RGBA8 with the bottom row first.  The center of every cell
+/// is decoded with [`color::srgb_to_linear()`], and must match what blending in linear space
+/// yields.
+pub fn check_gradient_ramp(pixels: &[u8], width: usize, height: usize) -> Result<(), RampMismatch> {
+    for row in 0..2 {
+        let y = height - 1 - (row * 2 + 1) * height / 4;
+        for step in 0..RAMP_STEPS {
+            let x = (step * 2 + 1) * width / (RAMP_STEPS * 2);
+            let level = ramp_level(step);
+            let expected = match row {
+                0 => color::srgb_to_linear(level),
+                _ => level,
+            };
+            let pixel = &pixels[(y * width + x) * 4..][..3];
+            for &channel in pixel {
+                let actual = color::srgb_to_linear(channel as f32 / 255.0);
+                if (actual - expected).abs() > RAMP_TOLERANCE {
+                    return Err(RampMismatch {
+                        row,
+                        step,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Interleaved `vec2 position (pixels), vec2 uv, vec4 color` per vertex
+const FLOATS_PER_VERTEX: usize = 8;
+
+/// Draws [`Scene`]s with one draw call per [`Batch`], see the [module docs][self].
+///
+/// Must only be used with the context that it was created on.
+pub struct SceneRenderer {
+    vertices: Vec<f32>,
+    // Dropped (and thus queued for deletion) in declaration order
+    program: GlObject,
+    vao: GlObject,
+    vbo: GlObject,
+    /// 1x1 white pixel, sampled by colored rects
+    white: GlObject,
+    viewport_location: gl::types::GLint,
+    gl: gl::Gl,
+}
+
+impl SceneRenderer {
+    pub fn new(gl: &gl::Gl, deletion_queue: &DeletionQueue) -> Self {
+        unsafe {
+            let program = create_program(
+                gl,
+                ("shaders/scene.vert", VERTEX_SHADER_SOURCE),
+                ("shaders/scene.frag", FRAGMENT_SHADER_SOURCE),
+                &[],
+            );
+
+            let viewport_location = gl.GetUniformLocation(program, c"viewport".as_ptr());
+            let texture_location = gl.GetUniformLocation(program, c"image".as_ptr());
+            gl.UseProgram(program);
+            gl.Uniform1i(texture_location, 0);
+
+            let mut vao = std::mem::zeroed();
+            gl.GenVertexArrays(1, &mut vao);
+            gl.BindVertexArray(vao);
+
+            let mut vbo = std::mem::zeroed();
+            gl.GenBuffers(1, &mut vbo);
+            gl.BindBuffer(gl::ARRAY_BUFFER, vbo);
+
+            let stride = (FLOATS_PER_VERTEX * std::mem::size_of::<f32>()) as gl::types::GLsizei;
+            for (name, size, offset) in [(c"position", 2, 0), (c"uv", 2, 2), (c"color", 4, 4)] {
+                let attrib = gl.GetAttribLocation(program, name.as_ptr()) as gl::types::GLuint;
+                gl.VertexAttribPointer(
+                    attrib,
+                    size,
+                    gl::FLOAT,
+                    0,
+                    stride,
+                    (offset * std::mem::size_of::<f32>()) as *const _,
+                );
+                gl.EnableVertexAttribArray(attrib);
+            }
+
+            let mut white = std::mem::zeroed();
+            gl.GenTextures(1, &mut white);
+            gl.BindTexture(gl::TEXTURE_2D, white);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
+            gl.TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA8 as _,
+                1,
+                1,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                [0xffu8; 4].as_ptr().cast(),
+            );
+
+            Self {
+                vertices: Vec::new(),
+                program: deletion_queue.wrap(GlObjectKind::Program, program),
+                vao: deletion_queue.wrap(GlObjectKind::VertexArray, vao),
+                vbo: deletion_queue.wrap(GlObjectKind::Buffer, vbo),
+                white: deletion_queue.wrap(GlObjectKind::Texture, white),
+                viewport_location,
+                gl: gl.clone(),
+            }
+        }
+    }
+
+    /// Draws `scene` on a `width` x `height` viewport, blended over the current framebuffer
+    /// contents.  Colors are decoded to linear for an `srgb` framebuffer.  Returns the number of
+    /// draw calls.
+    pub fn draw(
+        &mut self,
+        scene: &Scene,
+        width: i32,
+        height: i32,
+        srgb: bool,
+        texture_name: impl FnMut(Handle<Texture>) -> Option<gl::types::GLuint>,
+    ) -> usize {
+        let viewport = Rect::new(0.0, 0.0, width as f32, height as f32);
+        let quads = scene.quads(&viewport, texture_name);
+        let batches = batch(&quads);
+        if batches.is_empty() {
+            return 0;
+        }
+
+        self.vertices.clear();
+        let mut ranges = Vec::with_capacity(batches.len());
+        for batch in &batches {
+            let start = self.vertices.len() / FLOATS_PER_VERTEX;
+            for &i in &batch.quads {
+                let quad = &quads[i];
+                let [tl, tr, bl, br] = quad.corners;
+                let [r, g, b, a] = if srgb {
+                    color::linearize(quad.color)
+                } else {
+                    quad.color
+                };
+                for ((x, y), (u, v)) in [
+                    (tl, (0.0, 0.0)),
+                    (tr, (1.0, 0.0)),
+                    (bl, (0.0, 1.0)),
+                    (bl, (0.0, 1.0)),
+                    (tr, (1.0, 0.0)),
+                    (br, (1.0, 1.0)),
+                ] {
+                    self.vertices.extend_from_slice(&[x, y, u, v, r, g, b, a]);
+                }
+            }
+            ranges.push((start, self.vertices.len() / FLOATS_PER_VERTEX - start));
+        }
+
+        let gl = &self.gl;
+        unsafe {
+            gl.UseProgram(self.program.name());
+            gl.Uniform2f(self.viewport_location, width as f32, height as f32);
+            gl.BindVertexArray(self.vao.name());
+            gl.BindBuffer(gl::ARRAY_BUFFER, self.vbo.name());
+            gl.BufferData(
+                gl::ARRAY_BUFFER,
+                (self.vertices.len() * std::mem::size_of::<f32>()) as gl::types::GLsizeiptr,
+                self.vertices.as_ptr().cast(),
+                gl::STREAM_DRAW,
+            );
+
+            gl.Enable(gl::BLEND);
+            gl.BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl.ActiveTexture(gl::TEXTURE0);
+            for (batch, (start, count)) in batches.iter().zip(ranges) {
+                let texture = match batch.texture {
+                    0 => self.white.name(),
+                    texture => texture,
+                };
+                gl.BindTexture(gl::TEXTURE_2D, texture);
+                match &batch.clip {
+                    Some(clip) => {
+                        gl.Enable(gl::SCISSOR_TEST);
+                        // GL counts from the bottom-left
+                        gl.Scissor(
+                            clip.x.round() as _,
+                            (height as f32 - clip.bottom()).round() as _,
+                            clip.width.round() as _,
+                            clip.height.round() as _,
+                        );
+                    }
+                    None => gl.Disable(gl::SCISSOR_TEST),
+                }
+                gl.DrawArrays(gl::TRIANGLES, start as _, count as _);
+            }
+            gl.Disable(gl::SCISSOR_TEST);
+            gl.Disable(gl::BLEND);
+        }
+        batches.len()
+    }
+}
+
+const VERTEX_SHADER_SOURCE: &[u8] = b"
+#version 100
+precision mediump float;
+
+uniform vec2 viewport;
+
+attribute vec2 position;
+attribute vec2 uv;
+attribute vec4 color;
+
+varying vec2 v_uv;
+varying vec4 v_color;
+
+void main() {
+    // Pixels from the top-left to NDC
+    vec2 ndc = position / viewport * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    v_uv = uv;
+    v_color = color;
+}
+\0";
+
+const FRAGMENT_SHADER_SOURCE: &[u8] = b"
+#version 100
+precision mediump float;
+
+uniform sampler2D image;
+
+varying vec2 v_uv;
+varying vec4 v_color;
+
+void main() {
+    gl_FragColor = texture2D(image, v_uv) * v_color;
+}
+\0";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEWPORT: Rect = Rect {
+        x: 0.0,
+        y: 0.0,
+        width: 1000.0,
+        height: 1000.0,
+    };
+
+    /// Distinguishes nodes by the red channel of their color
+    fn colored(x: f32, z: i32, id: f32) -> Node {
+        Node::new(
+            Rect::new(x, 0.0, 10.0, 10.0),
+            z,
+            Fill::Color([id, 0.0, 0.0, 1.0]),
+        )
+    }
+
+    fn ids(quads: &[Quad]) -> Vec<f32> {
+        quads.iter().map(|quad| quad.color[0]).collect()
+    }
+
+    /// Untransformed quad at `x` sampling `texture`
+    fn quad(x: f32, texture: gl::types::GLuint) -> Quad {
+        let rect = Rect::new(x, 0.0, 10.0, 10.0);
+        Quad {
+            corners: Transform::IDENTITY.corners(&rect),
+            bounds: rect,
+            texture,
+            color: [1.0; 4],
+            clip: None,
+        }
+    }
+
+    fn batched_quads(batches: &[Batch]) -> Vec<Vec<usize>> {
+        batches.iter().map(|batch| batch.quads.clone()).collect()
+    }
+
+    #[test]
+    fn z_order_ties_keep_insertion_order() {
+        let mut scene = Scene::default();
+        scene.insert(colored(0.0, 1, 1.0));
+        scene.insert(colored(0.0, 0, 2.0));
+        let removed = scene.insert(colored(0.0, 1, 3.0));
+        scene.insert(colored(0.0, 1, 4.0));
+        scene.insert(colored(0.0, -1, 5.0));
+        scene.remove(removed).unwrap();
+
+        let quads = scene.quads(&VIEWPORT, |_| unreachable!());
+        assert_eq!(ids(&quads), [5.0, 2.0, 1.0, 4.0]);
+    }
+
+    #[test]
+    fn sprites_of_stale_textures_are_skipped() {
+        let mut scene = Scene::default();
+        let live = Handle::from_raw(1 << 32 | 1);
+        let stale = Handle::from_raw(1 << 32 | 2);
+        for (x, texture) in [(0.0, live), (20.0, stale)] {
+            let fill = Fill::Texture {
+                texture,
+                tint: [1.0; 4],
+            };
+            scene.insert(Node::new(Rect::new(x, 0.0, 10.0, 10.0), 0, fill));
+        }
+
+        let quads = scene.quads(&VIEWPORT, |texture| {
+            (texture.into_raw() == live.into_raw()).then_some(7)
+        });
+        assert_eq!(quads.len(), 1);
+        assert_eq!(quads[0].texture, 7);
+    }
+
+    #[test]
+    fn nodes_are_clipped_to_the_viewport_and_their_clip() {
+        let mut scene = Scene::default();
+        let mut node = Node::new(
+            Rect::new(-50.0, 0.0, 100.0, 100.0),
+            0,
+            Fill::Color([1.0; 4]),
+        );
+        node.clip = Some(Rect::new(25.0, 50.0, 100.0, 100.0));
+        scene.insert(node.clone());
+        // Entirely outside of its clip
+        node.clip = Some(Rect::new(60.0, 0.0, 10.0, 10.0));
+        scene.insert(node.clone());
+        // Entirely outside of the viewport
+        node.clip = None;
+        node.rect.x = -200.0;
+        scene.insert(node);
+
+        let quads = scene.quads(&VIEWPORT, |_| unreachable!());
+        assert_eq!(quads.len(), 1);
+        assert_eq!(quads[0].bounds, Rect::new(25.0, 50.0, 25.0, 50.0));
+        // The corners are not clipped, the clip rect is applied when drawing
+        assert_eq!(quads[0].corners[0], (-50.0, 0.0));
+        assert_eq!(quads[0].clip, Some(Rect::new(25.0, 50.0, 100.0, 100.0)));
+    }
+
+    #[test]
+    fn rect_intersection_and_union() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(5.0, -5.0, 10.0, 10.0);
+        assert_eq!(a.intersection(&b), Some(Rect::new(5.0, 0.0, 5.0, 5.0)));
+        assert_eq!(a.union(&b), Rect::new(0.0, -5.0, 15.0, 15.0));
+        // Touching edges don't overlap
+        assert_eq!(a.intersection(&Rect::new(10.0, 0.0, 10.0, 10.0)), None);
+    }
+
+    #[test]
+    fn bounds_of_rotated_and_skewed_rects() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 20.0);
+
+        // 90° clockwise on screen, around the origin
+        let rotation = Transform {
+            scale_x: 0.0,
+            skew_x: -1.0,
+            skew_y: 1.0,
+            scale_y: 0.0,
+            translate_x: 100.0,
+            ..Transform::IDENTITY
+        };
+        assert_eq!(
+            rotation.corners(&rect),
+            [(100.0, 0.0), (100.0, 10.0), (80.0, 0.0), (80.0, 10.0)]
+        );
+        assert_eq!(rotation.bounds(&rect), Rect::new(80.0, 0.0, 20.0, 10.0));
+
+        let skew = Transform {
+            skew_x: 0.5,
+            ..Transform::IDENTITY
+        };
+        assert_eq!(skew.bounds(&rect), Rect::new(0.0, 0.0, 20.0, 20.0));
+
+        // Mirrored, so that the first corner is no longer the minimum
+        let mirror = Transform {
+            scale_x: -2.0,
+            scale_y: -1.0,
+            ..Transform::IDENTITY
+        };
+        assert_eq!(mirror.bounds(&rect), Rect::new(-20.0, -20.0, 20.0, 20.0));
+    }
+
+    #[test]
+    fn quads_move_back_past_non_overlapping_batches() {
+        let quads = [quad(0.0, 1), quad(20.0, 2), quad(40.0, 1), quad(60.0, 2)];
+        let batches = batch(&quads);
+        assert_eq!(batched_quads(&batches), [vec![0, 2], vec![1, 3]]);
+        assert_eq!(batches[0].bounds, Rect::new(0.0, 0.0, 50.0, 10.0));
+    }
+
+    #[test]
+    fn quads_do_not_move_back_past_overlapping_batches() {
+        // The second quad of texture 1 would end up below the overlapping one of texture 2
+        let quads = [quad(0.0, 1), quad(5.0, 2), quad(10.0, 1)];
+        assert_eq!(batched_quads(&batch(&quads)), [vec![0], vec![1], vec![2]]);
+
+        // Different clips don't batch either
+        let mut clipped = quad(40.0, 1);
+        clipped.clip = Some(Rect::new(0.0, 0.0, 100.0, 100.0));
+        assert_eq!(
+            batched_quads(&batch(&[quad(0.0, 1), clipped])),
+            [vec![0], vec![1]]
+        );
+    }
+
+    #[test]
+    fn quads_move_back_at_most_max_batch_lookback_batches() {
+        let quads = |between: usize| {
+            let mut quads = vec![quad(0.0, 1)];
+            quads.extend((0..between).map(|i| quad(20.0 * (i + 1) as f32, i as u32 + 2)));
+            quads.push(quad(20.0 * (between + 1) as f32, 1));
+            quads
+        };
+
+        let batches = batch(&quads(MAX_BATCH_LOOKBACK - 1));
+        assert_eq!(batches.len(), MAX_BATCH_LOOKBACK);
+        assert_eq!(batches[0].quads, [0, MAX_BATCH_LOOKBACK]);
+
+        let batches = batch(&quads(MAX_BATCH_LOOKBACK));
+        assert_eq!(batches.len(), MAX_BATCH_LOOKBACK + 2);
+        assert_eq!(batches[0].quads, [0]);
+    }
+
+    /// Pixels of [`gradient_ramp()`] as a framebuffer would store them, bottom row first, from
+    /// the row and level of every cell
+    fn ramp_pixels(width: usize, height: usize, store: impl Fn(usize, f32) -> u8) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let level = ramp_level(x * RAMP_STEPS / width);
+                let row = usize::from(y >= height / 2);
+                let value = store(row, level);
+                pixels.extend_from_slice(&[value, value, value, 255]);
+            }
+        }
+        pixels
+    }
+
+    /// The 8-bit sRGB encoding that decodes closest to `linear`
+    fn encode(linear: f32) -> u8 {
+        (0..=255)
+            .min_by(|&a, &b| {
+                let error = |v: u8| (color::srgb_to_linear(v as f32 / 255.0) - linear).abs();
+                error(a).total_cmp(&error(b))
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn gradient_ramp_fills_the_viewport() {
+        let viewport = Rect::new(0.0, 0.0, 128.0, 32.0);
+        let quads = gradient_ramp(128.0, 32.0).quads(&viewport, |_| unreachable!());
+        assert_eq!(quads.len(), 1 + 2 * RAMP_STEPS);
+        let covered = quads
+            .iter()
+            .map(|quad| quad.bounds)
+            .reduce(|a, b| a.union(&b));
+        assert_eq!(covered, Some(viewport));
+        // The black background is drawn first
+        assert_eq!(quads[0].color, [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn gradient_ramp_of_an_srgb_framebuffer_matches() {
+        // Grays are decoded to linear and blending happens in linear space, then both are encoded
+        let pixels = ramp_pixels(128, 32, |row, level| match row {
+            0 => encode(color::srgb_to_linear(level)),
+            _ => encode(level),
+        });
+        assert_eq!(check_gradient_ramp(&pixels, 128, 32), Ok(()));
+    }
+
+    #[test]
+    fn gradient_ramp_of_a_linear_framebuffer_is_rejected() {
+        // Without sRGB encoding, the grays are stored as is and still match, but blending the
+        // encoded values darkens the whites
+        let pixels = ramp_pixels(128, 32, |_, level| (level * 255.0).round() as u8);
+        let mismatch = check_gradient_ramp(&pixels, 128, 32).unwrap_err();
+        assert_eq!((mismatch.row, mismatch.step), (1, 1));
+    }
+}
@@ -0,0 +1,373 @@
+//! CPU rendering backend that skips EGL entirely.
+//!
+//! The buffer is acquired with [`NativeWindow::lock()`], rasterized in Rust and posted when the
+//! lock guard is dropped.  This is useful on devices with broken GL drivers, and for debugging.
+//!
+//! Note that a [`NativeWindow`] can only be connected to a single producer API at a time, hence a
+//! window that is used here must never be passed to EGL and vice-versa.
+
+use std::mem::MaybeUninit;
+
+#[cfg(target_os = "android")]
+use ndk::{hardware_buffer_format::HardwareBufferFormat, native_window::NativeWindow};
+
+use crate::support::{CLEAR_COLOR, VERTEX_DATA};
+
+/// Pixel formats that [`Framebuffer`] knows how to write
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba8,
+    /// Same layout as [`PixelFormat::Rgba8`], but the alpha channel is ignored by the consumer.
+    Rgbx8,
+    Rgb565,
+}
+
+impl PixelFormat {
+    #[cfg(target_os = "android")]
+    pub fn from_hardware_buffer_format(format: HardwareBufferFormat) -> Option<Self> {
+        Some(match format {
+            HardwareBufferFormat::R8G8B8A8_UNORM => Self::Rgba8,
+            HardwareBufferFormat::R8G8B8X8_UNORM => Self::Rgbx8,
+            HardwareBufferFormat::R5G6B5_UNORM => Self::Rgb565,
+            _ => return None,
+        })
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Rgba8 | Self::Rgbx8 => 4,
+            Self::Rgb565 => 2,
+        }
+    }
+
+    /// Encode a linear `[0, 1]` RGBA color into the native byte layout of this format.
+    fn encode(self, [r, g, b, a]: [f32; 4]) -> [u8; 4] {
+        let unorm = |c: f32, max: f32| (c.clamp(0.0, 1.0) * max).round() as u16;
+        match self {
+            Self::Rgba8 => [r, g, b, a].map(|c| unorm(c, 255.0) as u8),
+            Self::Rgbx8 => [r, g, b, 1.0].map(|c| unorm(c, 255.0) as u8),
+            Self::Rgb565 => {
+                let p = (unorm(r, 31.0) << 11) | (unorm(g, 63.0) << 5) | unorm(b, 31.0);
+                let [lo, hi] = p.to_le_bytes();
+                [lo, hi, 0, 0]
+            }
+        }
+    }
+}
+
+/// A CPU-accessible pixel buffer with a row `stride` (in pixels) that may exceed its `width`.
+///
+/// The backing memory is [`MaybeUninit`] because that is how [`NativeWindow::lock()`] exposes it;
+/// it is only ever written to.
+pub struct Framebuffer<'a> {
+    data: &'a mut [MaybeUninit<u8>],
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: PixelFormat,
+}
+
+impl<'a> Framebuffer<'a> {
+    pub fn new(
+        data: &'a mut [MaybeUninit<u8>],
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: PixelFormat,
+    ) -> Self {
+        assert!(
+            stride >= width,
+            "Stride {stride} is smaller than width {width}"
+        );
+        assert!(
+            data.len() >= stride * height * format.bytes_per_pixel(),
+            "Buffer of {} bytes too small for {stride}x{height} {format:?}",
+            data.len()
+        );
+        Self {
+            data,
+            width,
+            height,
+            stride,
+            format,
+        }
+    }
+
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: [f32; 4]) {
+        debug_assert!(x < self.width && y < self.height);
+        let bpp = self.format.bytes_per_pixel();
+        let offset = (y * self.stride + x) * bpp;
+        let encoded = self.format.encode(color);
+        for (dst, src) in self.data[offset..offset + bpp].iter_mut().zip(encoded) {
+            dst.write(src);
+        }
+    }
+
+    pub fn clear(&mut self, color: [f32; 4]) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    /// Rasterize a triangle with per-vertex colors, given in GL normalized device coordinates.
+    ///
+    /// Pixels are sampled at their center and colors are interpolated barycentrically, matching
+    /// what the GL backend produces for the same input.  Centers that lie exactly on an edge are
+    /// only filled for top and left edges, so that triangles which share an edge never both fill
+    /// the same pixel.
+    pub fn fill_triangle(&mut self, positions: [[f32; 2]; 3], colors: [[f32; 3]; 3]) {
+        let (w, h) = (self.width as f32, self.height as f32);
+        // NDC has +Y pointing up while buffer rows go down
+        let mut p = positions.map(|[x, y]| [(x + 1.0) * 0.5 * w, (1.0 - y) * 0.5 * h]);
+        let mut colors = colors;
+
+        let edge = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| {
+            (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+        };
+        let mut area = edge(p[0], p[1], p[2]);
+        if area == 0.0 {
+            return;
+        }
+        // Make the winding clockwise on screen, which the top-left rule below relies on
+        if area < 0.0 {
+            p.swap(1, 2);
+            colors.swap(1, 2);
+            area = -area;
+        }
+        // With clockwise winding and rows going down, top edges run exactly to the right and left
+        // edges run up
+        let top_left = |a: [f32; 2], b: [f32; 2]| (a[1] == b[1] && b[0] > a[0]) || b[1] < a[1];
+        let inside = |e: f32, a, b| e > 0.0 || (e == 0.0 && top_left(a, b));
+
+        let min_x = p
+            .iter()
+            .map(|v| v[0])
+            .fold(f32::INFINITY, f32::min)
+            .max(0.0) as usize;
+        let max_x = p.iter().map(|v| v[0]).fold(0.0, f32::max).ceil().min(w) as usize;
+        let min_y = p
+            .iter()
+            .map(|v| v[1])
+            .fold(f32::INFINITY, f32::min)
+            .max(0.0) as usize;
+        let max_y = p.iter().map(|v| v[1]).fold(0.0, f32::max).ceil().min(h) as usize;
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let c = [x as f32 + 0.5, y as f32 + 0.5];
+                let e0 = edge(p[1], p[2], c);
+                let e1 = edge(p[2], p[0], c);
+                let e2 = edge(p[0], p[1], c);
+                if !(inside(e0, p[1], p[2]) && inside(e1, p[2], p[0]) && inside(e2, p[0], p[1])) {
+                    continue;
+                }
+                let (b0, b1, b2) = (e0 / area, e1 / area, e2 / area);
+                let channel = |i: usize| b0 * colors[0][i] + b1 * colors[1][i] + b2 * colors[2][i];
+                self.put_pixel(x, y, [channel(0), channel(1), channel(2), 1.0]);
+            }
+        }
+    }
+}
+
+/// Draws the same scene as [`crate::support::Renderer::draw()`].
+pub fn draw(framebuffer: &mut Framebuffer<'_>) {
+    framebuffer.clear(CLEAR_COLOR);
+
+    let vertex = |i: usize| &VERTEX_DATA[i * 5..i * 5 + 5];
+    let positions = [0, 1, 2].map(|i| [vertex(i)[0], vertex(i)[1]]);
+    let colors = [0, 1, 2].map(|i| [vertex(i)[2], vertex(i)[3], vertex(i)[4]]);
+    framebuffer.fill_triangle(positions, colors);
+}
+
+/// Window that is rendered to by the CPU, see the [module docs][self].
+#[cfg(target_os = "android")]
+#[derive(Debug)]
+pub struct SoftwareWindow {
+    pub window: NativeWindow,
+}
+
+#[cfg(target_os = "android")]
+impl SoftwareWindow {
+    pub fn new(window: NativeWindow) -> Self {
+        if PixelFormat::from_hardware_buffer_format(window.format()).is_none() {
+            // Keep the default size, only override the format
+            window
+                .set_buffers_geometry(0, 0, Some(HardwareBufferFormat::R8G8B8A8_UNORM))
+                .expect("Failed to set window format");
+        }
+        Self { window }
+    }
+
+    pub fn render(&self) {
+        let mut buffer = self.window.lock(None).expect("Cannot lock window");
+        let format = PixelFormat::from_hardware_buffer_format(buffer.format())
+            .unwrap_or_else(|| panic!("Unsupported window format {:?}", buffer.format()));
+        let (width, height, stride) = (buffer.width(), buffer.height(), buffer.stride());
+        let data = buffer
+            .bytes()
+            .expect("Window format has no bytes_per_pixel()");
+
+        let mut framebuffer = Framebuffer::new(data, width, height, stride, format);
+        draw(&mut framebuffer);
+        // Unlocked and posted on drop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSPARENT: [f32; 4] = [0.0; 4];
+    const WHITE: [[f32; 3]; 3] = [[1.0; 3]; 3];
+
+    /// Buffer of `stride` x `height` pixels, filled with `0xAA` to tell untouched bytes apart
+    fn buffer(stride: usize, height: usize, format: PixelFormat) -> Vec<MaybeUninit<u8>> {
+        vec![MaybeUninit::new(0xAA); stride * height * format.bytes_per_pixel()]
+    }
+
+    fn bytes(data: &[MaybeUninit<u8>]) -> Vec<u8> {
+        // SAFETY: Every buffer is fully initialized by `buffer()`
+        data.iter().map(|b| unsafe { b.assume_init() }).collect()
+    }
+
+    /// Pixels of an `Rgba8` buffer that were filled by a triangle, which always writes alpha 1
+    fn covered(data: &[MaybeUninit<u8>], width: usize) -> Vec<(usize, usize)> {
+        bytes(data)
+            .chunks(4)
+            .enumerate()
+            .filter(|(_, pixel)| pixel[3] == 255)
+            .map(|(i, _)| (i % width, i / width))
+            .collect()
+    }
+
+    /// Fills `triangle` into a cleared `width` x `height` `Rgba8` buffer.
+    fn fill(width: usize, height: usize, triangle: [[f32; 2]; 3]) -> Vec<(usize, usize)> {
+        let mut data = buffer(width, height, PixelFormat::Rgba8);
+        let mut framebuffer = Framebuffer::new(&mut data, width, height, width, PixelFormat::Rgba8);
+        framebuffer.clear(TRANSPARENT);
+        framebuffer.fill_triangle(triangle, WHITE);
+        covered(&data, width)
+    }
+
+    #[test]
+    fn covers_pixels_with_center_inside() {
+        // Lower left half of an 8x8 buffer, the diagonal runs through the corners of pixels
+        let covered = fill(8, 8, [[-1.0, 1.0], [-1.0, -1.0], [1.0, -1.0]]);
+        let expected = (0..8)
+            .flat_map(|y| (0..8).map(move |x| (x, y)))
+            .filter(|&(x, y)| x < y)
+            .collect::<Vec<_>>();
+        assert_eq!(covered, expected);
+    }
+
+    #[test]
+    fn winding_order_does_not_matter() {
+        let triangle = [[-0.8, -0.6], [0.7, -0.9], [0.1, 0.8]];
+        let [a, b, c] = triangle;
+        assert_eq!(fill(16, 16, triangle), fill(16, 16, [a, c, b]));
+        assert!(!fill(16, 16, triangle).is_empty());
+    }
+
+    #[test]
+    fn degenerate_triangle_covers_nothing() {
+        assert!(fill(8, 8, [[-1.0, -1.0], [0.0, 0.0], [1.0, 1.0]]).is_empty());
+    }
+
+    #[test]
+    fn shared_edges_are_filled_once() {
+        // Every pixel center of a 4x4 buffer lies on a multiple of 0.5 NDC units from -0.75, so
+        // that the outer and the shared edges of this quad run exactly through pixel centers
+        let [l, t, r, b] = [-0.75, 0.75, 0.25, -0.25];
+        let first = fill(4, 4, [[l, t], [r, t], [r, b]]);
+        let second = fill(4, 4, [[l, t], [r, b], [l, b]]);
+
+        assert!(
+            first.iter().all(|p| !second.contains(p)),
+            "{first:?} {second:?}"
+        );
+        let mut all = [first, second].concat();
+        all.sort_by_key(|&(x, y)| (y, x));
+        // Only the top and left edges of the quad are filled
+        assert_eq!(all, [(0, 0), (1, 0), (0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn fan_covers_every_pixel_exactly_once() {
+        let (w, h) = (13, 7);
+        let center = [0.1, -0.2];
+        let corners = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
+        let mut counts = vec![0; w * h];
+        for i in 0..4 {
+            for (x, y) in fill(w, h, [center, corners[i], corners[(i + 1) % 4]]) {
+                counts[y * w + x] += 1;
+            }
+        }
+        assert!(counts.iter().all(|&c| c == 1), "{counts:?}");
+    }
+
+    #[test]
+    fn rgb565_packs_little_endian() {
+        let encode = |color| PixelFormat::Rgb565.encode(color);
+        assert_eq!(encode([1.0, 0.0, 0.0, 1.0]), [0x00, 0xF8, 0, 0]);
+        assert_eq!(encode([0.0, 1.0, 0.0, 1.0]), [0xE0, 0x07, 0, 0]);
+        assert_eq!(encode([0.0, 0.0, 1.0, 1.0]), [0x1F, 0x00, 0, 0]);
+        // Rounded to the nearest step, and clamped
+        assert_eq!(encode([0.5, 0.5, 0.5, 1.0]), [0x10, 0x84, 0, 0]);
+        assert_eq!(encode([2.0, -1.0, 0.0, 0.0]), [0x00, 0xF8, 0, 0]);
+
+        let mut data = buffer(2, 1, PixelFormat::Rgb565);
+        let mut framebuffer = Framebuffer::new(&mut data, 2, 1, 2, PixelFormat::Rgb565);
+        framebuffer.put_pixel(1, 0, [1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(bytes(&data), [0xAA, 0xAA, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn rgbx8_ignores_alpha() {
+        assert_eq!(
+            PixelFormat::Rgbx8.encode([1.0, 0.5, 0.0, 0.0]),
+            [255, 128, 0, 255]
+        );
+    }
+
+    #[test]
+    fn padding_beyond_width_is_untouched() {
+        let (width, height, stride) = (3, 2, 5);
+        let mut data = buffer(stride, height, PixelFormat::Rgba8);
+        let mut framebuffer =
+            Framebuffer::new(&mut data, width, height, stride, PixelFormat::Rgba8);
+        framebuffer.clear([0.0, 0.0, 0.0, 1.0]);
+        framebuffer.fill_triangle([[-1.0, -1.0], [3.0, -1.0], [-1.0, 3.0]], WHITE);
+
+        for (i, pixel) in bytes(&data).chunks(4).enumerate() {
+            let expected = if i % stride < width {
+                [255; 4]
+            } else {
+                [0xAA; 4]
+            };
+            assert_eq!(pixel, expected, "pixel {i}");
+        }
+    }
+
+    #[test]
+    #[should_panic = "Stride 2 is smaller than width 3"]
+    fn stride_below_width_is_rejected() {
+        let mut data = buffer(3, 1, PixelFormat::Rgba8);
+        Framebuffer::new(&mut data, 3, 1, 2, PixelFormat::Rgba8);
+    }
+
+    #[test]
+    fn draw_fills_the_triangle_over_the_clear_color() {
+        let (width, height) = (32, 32);
+        let mut data = buffer(width, height, PixelFormat::Rgba8);
+        let mut framebuffer = Framebuffer::new(&mut data, width, height, width, PixelFormat::Rgba8);
+        draw(&mut framebuffer);
+
+        let data = bytes(&data);
+        let pixel = |x: usize, y: usize| &data[(y * width + x) * 4..][..4];
+        let clear = PixelFormat::Rgba8.encode(CLEAR_COLOR);
+        assert_eq!(pixel(0, 0), clear);
+        assert_ne!(pixel(width / 2, height / 2), clear);
+    }
+}
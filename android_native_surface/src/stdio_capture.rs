@@ -0,0 +1,293 @@
+//! Forwards everything that is written to `stdout` and `stderr`, such as [`println!()`] and panic
+//! messages, into [`log`].  Android discards both by default.
+//!
+//! Each stream gets its own pipe and thread, logging `stdout` at [`Level::Info`] and `stderr` at
+//! [`Level::Warn`] under a configurable logcat tag.  The global logger tags by module path, so
+//! every stream writes through its own [`AndroidLogger`] instead, after checking that the global
+//! filter enables its tag as the target.  Output is split into lines without losing any bytes:
+//! invalid UTF-8 is escaped as `\xNN`, overly long lines are split, and a final line without a
+//! trailing newline is still logged when the capture is [stopped][StdioCapture::stop()].
+//!
+//! The tags default to [`DEFAULT_STDOUT_TAG`] and [`DEFAULT_STDERR_TAG`], and are overridden by the
+//! `android_native_surface.stdout_tag` and `android_native_surface.stderr_tag` Java system
+//! properties when they are set before `System.loadLibrary()`.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, Read as _},
+    os::fd::OwnedFd,
+    thread::{self, JoinHandle},
+};
+
+use android_logger::AndroidLogger;
+use log::{debug, warn, Level, LevelFilter, Log, Metadata, Record};
+
+/// Longest line that is logged at once, in bytes, to stay under the logcat entry limit
+const MAX_LINE_LEN: usize = 4000;
+
+pub const DEFAULT_STDOUT_TAG: &str = "RustStdout";
+pub const DEFAULT_STDERR_TAG: &str = "RustStderr";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn redirect(self, fd: &OwnedFd) -> rustix::io::Result<()> {
+        match self {
+            Self::Stdout => rustix::stdio::dup2_stdout(fd),
+            Self::Stderr => rustix::stdio::dup2_stderr(fd),
+        }
+    }
+
+    fn duplicate(self) -> rustix::io::Result<OwnedFd> {
+        match self {
+            Self::Stdout => rustix::io::dup(rustix::stdio::stdout()),
+            Self::Stderr => rustix::io::dup(rustix::stdio::stderr()),
+        }
+    }
+
+    fn level(self) -> Level {
+        match self {
+            Self::Stdout => Level::Info,
+            Self::Stderr => Level::Warn,
+        }
+    }
+}
+
+/// Splits a byte stream into lines, that are returned without their trailing `\n` or `\r\n`.
+#[derive(Debug, Default)]
+pub struct LineSplitter {
+    /// Bytes of the line that is not terminated yet
+    pending: Vec<u8>,
+}
+
+impl LineSplitter {
+    /// Appends `bytes` and calls `emit` for every line that is complete, or that reached
+    /// [`MAX_LINE_LEN`].
+    pub fn push(&mut self, mut bytes: &[u8], mut emit: impl FnMut(String)) {
+        while let Some(newline) = bytes.iter().position(|&b| b == b'\n') {
+            self.pending.extend_from_slice(&bytes[..newline]);
+            if self.pending.last() == Some(&b'\r') {
+                self.pending.pop();
+            }
+            self.flush_long_lines(&mut emit);
+            emit(escape(&self.pending));
+            self.pending.clear();
+            bytes = &bytes[newline + 1..];
+        }
+        self.pending.extend_from_slice(bytes);
+        self.flush_long_lines(&mut emit);
+    }
+
+    /// Calls `emit` for the line that was not terminated by a newline, if any.
+    pub fn finish(&mut self, mut emit: impl FnMut(String)) {
+        if !self.pending.is_empty() {
+            emit(escape(&self.pending));
+            self.pending.clear();
+        }
+    }
+
+    fn flush_long_lines(&mut self, emit: &mut impl FnMut(String)) {
+        while self.pending.len() > MAX_LINE_LEN {
+            // Don't split a UTF-8 sequence, unless there is none to be found
+            let split = (MAX_LINE_LEN - 3..=MAX_LINE_LEN)
+                .rev()
+                .find(|&i| self.pending[i] & 0xC0 != 0x80)
+                .unwrap_or(MAX_LINE_LEN);
+            emit(escape(&self.pending[..split]));
+            self.pending.drain(..split);
+        }
+    }
+}
+
+/// Decodes `bytes` as UTF-8, escaping invalid bytes as `\xNN`.
+fn escape(bytes: &[u8]) -> String {
+    let mut string = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        string.push_str(chunk.valid());
+        for b in chunk.invalid() {
+            write!(string, "\\x{b:02x}").unwrap();
+        }
+    }
+    string
+}
+
+/// A stream that is redirected into a pipe, and the thread that logs from it
+#[derive(Debug)]
+struct Capture {
+    stream: Stream,
+    /// Duplicate of the original fd, to restore on [`StdioCapture::stop()`]
+    original: OwnedFd,
+    thread: JoinHandle<io::Result<()>>,
+}
+
+impl Capture {
+    fn start(stream: Stream, mut tag: String) -> rustix::io::Result<Self> {
+        // Not representable in a logcat tag
+        tag.retain(|c| c != '\0');
+        let logger = AndroidLogger::new(
+            android_logger::Config::default()
+                .with_tag(tag.as_str())
+                .with_max_level(LevelFilter::Trace)
+                // Without the module path that is prepended for custom tags
+                .format(|f, record| write!(f, "{}", record.args())),
+        );
+
+        let original = stream.duplicate()?;
+        let (read, write) = rustix::pipe::pipe()?;
+        stream.redirect(&write)?;
+        // Only the stdio fd keeps the write end open now, so that restoring it ends the thread
+        drop(write);
+
+        let thread = thread::Builder::new()
+            .name(format!("{stream:?} capture"))
+            .spawn(move || {
+                let mut file = File::from(read);
+                let mut splitter = LineSplitter::default();
+                let level = stream.level();
+                let emit = |line: String| {
+                    let metadata = Metadata::builder().level(level).target(&tag).build();
+                    if log::logger().enabled(&metadata) {
+                        logger.log(
+                            &Record::builder()
+                                .metadata(metadata)
+                                .args(format_args!("{line}"))
+                                .build(),
+                        );
+                    }
+                };
+                let mut buffer = [0; 1024];
+                loop {
+                    match file.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(len) => splitter.push(&buffer[..len], emit),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+                splitter.finish(emit);
+                Ok(())
+            })
+            .expect("Failed to spawn stdio capture thread");
+
+        Ok(Self {
+            stream,
+            original,
+            thread,
+        })
+    }
+
+    fn stop(self) {
+        // Closes the write end of the pipe, so that the thread sees EOF
+        self.stream.redirect(&self.original).unwrap();
+        match self.thread.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("{:?} capture failed: {e}", self.stream),
+            Err(_) => warn!("{:?} capture thread panicked", self.stream),
+        }
+    }
+}
+
+/// Redirection of `stdout` and `stderr` into [`log`]
+#[derive(Debug)]
+pub struct StdioCapture {
+    stdout: Capture,
+    stderr: Capture,
+}
+
+impl StdioCapture {
+    /// Redirects `stdout` and `stderr` into pipes and starts logging from them under the given
+    /// tags.
+    pub fn start(stdout_tag: String, stderr_tag: String) -> rustix::io::Result<Self> {
+        let stdout = Capture::start(Stream::Stdout, stdout_tag)?;
+        let stderr = match Capture::start(Stream::Stderr, stderr_tag) {
+            Ok(stderr) => stderr,
+            Err(e) => {
+                stdout.stop();
+                return Err(e);
+            }
+        };
+        debug!("Capturing stdout and stderr");
+        Ok(Self { stdout, stderr })
+    }
+
+    /// Restores the original `stdout` and `stderr`, after logging everything that was written to
+    /// them so far.
+    pub fn stop(self) {
+        self.stdout.stop();
+        self.stderr.stop();
+        debug!("Stopped capturing stdout and stderr");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `chunks` to a [`LineSplitter`] one [`LineSplitter::push()`] at a time, and returns
+    /// the lines that were emitted before and by [`LineSplitter::finish()`].
+    fn split(chunks: &[&[u8]]) -> (Vec<String>, Vec<String>) {
+        let mut splitter = LineSplitter::default();
+        let mut lines = Vec::new();
+        for chunk in chunks {
+            splitter.push(chunk, |line| lines.push(line));
+        }
+        let mut tail = Vec::new();
+        splitter.finish(|line| tail.push(line));
+        (lines, tail)
+    }
+
+    #[test]
+    fn lines_across_pushes() {
+        let (lines, tail) = split(&[b"first", b" line\nsecond\n", b"\n", b"third"]);
+        assert_eq!(lines, ["first line", "second", ""]);
+        assert_eq!(tail, ["third"]);
+    }
+
+    #[test]
+    fn crlf_split_across_pushes() {
+        let (lines, tail) = split(&[b"dos\r", b"\nunix\n", b"mixed\r\r", b"\n"]);
+        assert_eq!(lines, ["dos", "unix", "mixed\r"]);
+        assert!(tail.is_empty());
+    }
+
+    #[test]
+    fn invalid_utf8_is_escaped() {
+        let (lines, tail) = split(&[b"caf\xc3", b"\xa9 \xff\xfe\n", b"\xe2\x82"]);
+        // The sequence split across pushes is still decoded
+        assert_eq!(lines, ["caf\u{e9} \\xff\\xfe"]);
+        // An incomplete sequence at the end is not
+        assert_eq!(tail, ["\\xe2\\x82"]);
+    }
+
+    #[test]
+    fn long_lines_are_split_between_utf8_sequences() {
+        // A 3-byte sequence straddles MAX_LINE_LEN
+        let mut line = vec![b'a'; MAX_LINE_LEN - 1];
+        line.extend_from_slice("\u{20ac}".as_bytes());
+        line.extend_from_slice(b"b\n");
+        let (lines, tail) = split(&[&line]);
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "a".repeat(MAX_LINE_LEN - 1));
+        assert_eq!(lines[1], "\u{20ac}b");
+        assert!(tail.is_empty());
+    }
+
+    #[test]
+    fn long_lines_without_newline_are_flushed() {
+        let (lines, tail) = split(&[&[b'x'; MAX_LINE_LEN], b"x", b"yz"]);
+        assert_eq!(lines, ["x".repeat(MAX_LINE_LEN)]);
+        assert_eq!(tail, ["xyz"]);
+    }
+
+    #[test]
+    fn finish_only_emits_an_unterminated_tail() {
+        assert_eq!(split(&[b"done\n"]), (vec!["done".to_owned()], vec![]));
+        assert_eq!(split(&[]), (vec![], vec![]));
+    }
+}
@@ -4,18 +4,52 @@
 //! with `winit` support stripped out
 
 use std::{
+    borrow::Cow,
     ffi::{CStr, CString},
-    num::NonZeroU32,
 };
+#[cfg(target_os = "android")]
+use std::{num::NonZeroU32, sync::Arc};
 
 use glutin::{
-    config::{ColorBufferType, Config, ConfigSurfaceTypes, ConfigTemplate, ConfigTemplateBuilder},
+    config::Config,
+    context::{ContextApi, ContextAttributesBuilder, NotCurrentContext, Version},
     display::{Display, DisplayApiPreference},
     prelude::*,
+};
+#[cfg(target_os = "android")]
+use glutin::{
+    config::{ColorBufferType, ConfigSurfaceTypes, ConfigTemplate, ConfigTemplateBuilder},
+    error::ErrorKind,
     surface::{Surface, SurfaceAttributes, SurfaceAttributesBuilder, WindowSurface},
 };
+use log::{error, warn};
+#[cfg(target_os = "android")]
 use ndk::{hardware_buffer_format::HardwareBufferFormat, native_window::NativeWindow};
-use raw_window_handle::{DisplayHandle, HasWindowHandle as _};
+use raw_window_handle::DisplayHandle;
+#[cfg(target_os = "android")]
+use raw_window_handle::HasWindowHandle as _;
+
+use crate::{
+    assets, color, debug_output,
+    deletion_queue::{DeletionQueue, GlObject, GlObjectKind},
+    post::PostRenderer,
+    scene::SceneRenderer,
+    text::{self, TextRenderer},
+};
+#[cfg(target_os = "android")]
+use crate::{
+    bitmap::{self, BitmapError},
+    gpu_timer::GpuTimer,
+    hud::Hud,
+    msaa::Msaa,
+    post::PostChain,
+    registry::Handle,
+    scene::Scene,
+};
+use capabilities::{Capabilities, Feature};
+
+pub mod capabilities;
+pub mod texture;
 
 pub mod gl {
     #![allow(clippy::all)]
@@ -25,21 +59,100 @@ pub mod gl {
 }
 
 /// Structure to hold winit window and gl surface.
+#[cfg(target_os = "android")]
 #[derive(Debug)]
 pub struct GlWindow {
-    pub surface: Surface<WindowSurface>,
+    /// Only [`None`] after [`GlWindow::recreate_surface()`] failed
+    surface: Option<Surface<WindowSurface>>,
     pub window: NativeWindow,
+    config: Config,
+    /// Drawn over the window when [`Hud::enabled`]
+    pub hud: Hud,
+    pub gpu_timer: GpuTimer,
+    /// Multisampled framebuffer that frames are drawn into, if enabled
+    pub msaa: Msaa,
+    /// Full-screen passes between drawing and presenting, if any
+    pub post: PostChain,
+    /// Drawn over the window on every frame, if attached
+    pub scene: Option<Handle<Scene>>,
+    /// Whether the surface was asked for the sRGB colorspace, see [`GlWindow::srgb()`]
+    srgb: bool,
+    /// Shared with the owner of `display`, to track how many windows still depend on it
+    _alive: Arc<()>,
 }
 
+#[cfg(target_os = "android")]
 impl GlWindow {
-    pub fn from_existing(display: &Display, window: NativeWindow, config: &Config) -> Self {
-        let attrs = surface_attributes(&window);
+    pub fn from_existing(
+        display: &Display,
+        window: NativeWindow,
+        config: &Config,
+        alive: Arc<()>,
+    ) -> Self {
+        let attrs = surface_attributes(&window, false);
         let surface = unsafe { display.create_window_surface(config, &attrs).unwrap() };
-        Self { window, surface }
+        Self {
+            window,
+            surface: Some(surface),
+            config: config.clone(),
+            hud: Hud::default(),
+            gpu_timer: GpuTimer::default(),
+            msaa: Msaa::default(),
+            post: PostChain::default(),
+            scene: None,
+            srgb: false,
+            _alive: alive,
+        }
+    }
+
+    /// Fails with [`ErrorKind::BadSurface`] after [`GlWindow::recreate_surface()`] failed, so that
+    /// rendering recovers by recreating it.
+    pub fn surface(&self) -> Result<&Surface<WindowSurface>, ErrorKind> {
+        self.surface.as_ref().ok_or(ErrorKind::BadSurface)
+    }
+
+    /// The config that the surface was created with
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Whether the surface encodes to sRGB on write, so that colors must be linear when drawn.
+    /// Only when requested and the config supports `EGL_KHR_gl_colorspace`.
+    pub fn srgb(&self) -> bool {
+        self.srgb && self.config.srgb_capable()
+    }
+
+    /// Recreates the surface with the sRGB or the linear colorspace.
+    pub fn set_srgb(
+        &mut self,
+        display: &Display,
+        config: &Config,
+        srgb: bool,
+    ) -> glutin::error::Result<()> {
+        if srgb && !config.srgb_capable() {
+            warn!("EGL_KHR_gl_colorspace is not supported, the surface stays linear");
+        }
+        self.srgb = srgb;
+        self.recreate_surface(display, config)
+    }
+
+    /// Replace the [`Surface`] on the same [`NativeWindow`], after the previous one became invalid.
+    pub fn recreate_surface(
+        &mut self,
+        display: &Display,
+        config: &Config,
+    ) -> glutin::error::Result<()> {
+        // A NativeWindow can only be connected to one EGLSurface at a time, so the old one must be
+        // destroyed first
+        self.surface = None;
+        let attrs = surface_attributes(&self.window, self.srgb);
+        self.surface = Some(unsafe { display.create_window_surface(config, &attrs)? });
+        Ok(())
     }
 }
 
 /// Create template to find OpenGL config, which is compatible with the given Android [`HardwareBufferFormat`]
+#[cfg(target_os = "android")]
 pub fn config_template(format: HardwareBufferFormat) -> ConfigTemplate {
     // The default is RGBA8
     let builder = ConfigTemplateBuilder::new().with_surface_type(ConfigSurfaceTypes::WINDOW);
@@ -98,14 +211,50 @@ pub fn config_template(format: HardwareBufferFormat) -> ConfigTemplate {
     builder.build()
 }
 
-/// Create surface attributes for window surface.
-pub fn surface_attributes(window: &NativeWindow) -> SurfaceAttributes<WindowSurface> {
+/// Create surface attributes for window surface, with `EGL_GL_COLORSPACE_SRGB` if `srgb` and the
+/// config supports it.
+#[cfg(target_os = "android")]
+pub fn surface_attributes(window: &NativeWindow, srgb: bool) -> SurfaceAttributes<WindowSurface> {
     let window_handle = window.window_handle().unwrap();
-    SurfaceAttributesBuilder::<WindowSurface>::new().build(
-        window_handle.as_raw(),
-        NonZeroU32::new(window.width().try_into().unwrap()).unwrap(),
-        NonZeroU32::new(window.height().try_into().unwrap()).unwrap(),
-    )
+    SurfaceAttributesBuilder::<WindowSurface>::new()
+        .with_srgb(Some(srgb))
+        .build(
+            window_handle.as_raw(),
+            NonZeroU32::new(window.width().try_into().unwrap()).unwrap(),
+            NonZeroU32::new(window.height().try_into().unwrap()).unwrap(),
+        )
+}
+
+/// Versions that [`create_context()`] asks for in turn.  EGL hands out the newest version that is
+/// compatible with the requested one, so GLES 3.0 may well yield 3.2.  Older versions are only
+/// created to report them through [`capabilities::CapabilitiesError::Unsupported`].
+const CONTEXT_VERSIONS: [Version; 2] = [Version::new(3, 0), Version::new(2, 0)];
+
+/// Create a GLES context for `config`, falling back to older versions.  A `debug` context makes
+/// the driver validate more and report more through `KHR_debug`, at a cost.
+pub fn create_context(display: &Display, config: &Config, debug: bool) -> NotCurrentContext {
+    // The context creation part. It can be created before surface and that's how
+    // it's expected in multithreaded + multiwindow operation mode, since you
+    // can send NotCurrentContext, but not Surface.
+    CONTEXT_VERSIONS
+        .into_iter()
+        .map(Some)
+        // Whatever the driver defaults to
+        .chain([None])
+        .find_map(|version| {
+            let context_attributes = ContextAttributesBuilder::new()
+                .with_context_api(ContextApi::Gles(version))
+                .with_debug(debug)
+                .build(None);
+            unsafe { display.create_context(config, &context_attributes) }
+                .inspect_err(|e| {
+                    let version = version
+                        .map_or_else(|| "any".to_owned(), |v| format!("{}.{}", v.major, v.minor));
+                    warn!("Cannot create a GLES {version} context: {e}");
+                })
+                .ok()
+        })
+        .expect("failed to create context")
 }
 
 /// Create the display.
@@ -116,48 +265,54 @@ pub fn create_display(display: DisplayHandle<'_>) -> Display {
     unsafe { Display::new(display.as_raw(), preference).unwrap() }
 }
 
+/// Loads the GLES entry points of `gl_display`, which are shared by all of its contexts
+pub fn load_gl(gl_display: &Display) -> gl::Gl {
+    gl::Gl::load_with(|symbol| {
+        let symbol = CString::new(symbol).unwrap();
+        gl_display.get_proc_address(symbol.as_c_str()).cast()
+    })
+}
+
 pub struct Renderer {
-    program: gl::types::GLuint,
-    vao: gl::types::GLuint,
-    vbo: gl::types::GLuint,
+    // Dropped (and thus queued for deletion) in declaration order
+    program: GlObject,
+    vao: GlObject,
+    vbo: GlObject,
+    /// [`None`] when no usable system font was found
+    text: Option<TextRenderer>,
+    scene: SceneRenderer,
+    post: PostRenderer,
+    /// Of the context that this renderer was created on
+    capabilities: Capabilities,
+    /// Number of `GL_GPU_DISJOINT_EXT` events seen by [`Renderer::poll_gpu_disjoint()`]
+    gpu_disjoint: u64,
     gl: gl::Gl,
 }
 
 impl Renderer {
+    /// `capabilities` must have been queried on a context of `gl_display` and the same config.
     // TODO: Api-wise this should take a CurrentContext on which we call .display()
-    pub fn new(gl_display: &Display) -> Self {
+    pub fn new(
+        gl_display: &Display,
+        deletion_queue: &DeletionQueue,
+        capabilities: Capabilities,
+    ) -> Self {
         unsafe {
-            let gl = gl::Gl::load_with(|symbol| {
-                let symbol = CString::new(symbol).unwrap();
-                gl_display.get_proc_address(symbol.as_c_str()).cast()
-            });
+            let gl = load_gl(gl_display);
 
-            if let Some(renderer) = get_gl_string(&gl, gl::RENDERER) {
-                println!("Running on {}", renderer.to_string_lossy());
-            }
-            if let Some(version) = get_gl_string(&gl, gl::VERSION) {
-                println!("OpenGL Version {}", version.to_string_lossy());
+            if capabilities.supports(Feature::DebugOutput) {
+                debug_output::enable_gl_debug_output(&gl);
             }
 
-            if let Some(shaders_version) = get_gl_string(&gl, gl::SHADING_LANGUAGE_VERSION) {
-                println!("Shaders version on {}", shaders_version.to_string_lossy());
-            }
-
-            let vertex_shader = create_shader(&gl, gl::VERTEX_SHADER, VERTEX_SHADER_SOURCE);
-            let fragment_shader = create_shader(&gl, gl::FRAGMENT_SHADER, FRAGMENT_SHADER_SOURCE);
-
-            let program = gl.CreateProgram();
-
-            gl.AttachShader(program, vertex_shader);
-            gl.AttachShader(program, fragment_shader);
-
-            gl.LinkProgram(program);
+            let program = create_program(
+                &gl,
+                ("shaders/triangle.vert", VERTEX_SHADER_SOURCE),
+                ("shaders/triangle.frag", FRAGMENT_SHADER_SOURCE),
+                &[],
+            );
 
             gl.UseProgram(program);
 
-            gl.DeleteShader(vertex_shader);
-            gl.DeleteShader(fragment_shader);
-
             let mut vao = std::mem::zeroed();
             gl.GenVertexArrays(1, &mut vao);
             gl.BindVertexArray(vao);
@@ -193,23 +348,80 @@ impl Renderer {
             gl.EnableVertexAttribArray(pos_attrib as gl::types::GLuint);
             gl.EnableVertexAttribArray(color_attrib as gl::types::GLuint);
 
+            let text = text::system_font()
+                .map(|font| TextRenderer::new(&gl, deletion_queue, font.clone()));
+            let scene = SceneRenderer::new(&gl, deletion_queue);
+            let post = PostRenderer::new(&gl, deletion_queue);
+
             Self {
-                program,
-                vao,
-                vbo,
+                program: deletion_queue.wrap(GlObjectKind::Program, program),
+                vao: deletion_queue.wrap(GlObjectKind::VertexArray, vao),
+                vbo: deletion_queue.wrap(GlObjectKind::Buffer, vbo),
+                text,
+                scene,
+                post,
+                capabilities,
+                gpu_disjoint: 0,
                 gl,
             }
         }
     }
 
-    pub fn draw(&self) {
+    pub fn gl(&self) -> &gl::Gl {
+        &self.gl
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Reads and thereby clears the `GL_GPU_DISJOINT_EXT` flag of the current context.  The flag
+    /// belongs to the context rather than to a window, so this must be called once per frame
+    /// before any [`crate::gpu_timer::GpuTimer`] reads back its results.
+    pub fn poll_gpu_disjoint(&mut self) {
+        if !self.capabilities.supports(Feature::TimerQuery) {
+            return;
+        }
+        let mut disjoint = 0;
+        unsafe { self.gl.GetIntegerv(gl::GPU_DISJOINT_EXT, &mut disjoint) };
+        if disjoint != 0 {
+            self.gpu_disjoint += 1;
+        }
+    }
+
+    /// Number of disjoint events that [`Renderer::poll_gpu_disjoint()`] saw so far, which
+    /// invalidate all timer queries that were in flight at the time
+    pub fn gpu_disjoint(&self) -> u64 {
+        self.gpu_disjoint
+    }
+
+    pub fn text(&mut self) -> Option<&mut TextRenderer> {
+        self.text.as_mut()
+    }
+
+    pub fn scene(&mut self) -> &mut SceneRenderer {
+        &mut self.scene
+    }
+
+    pub fn post(&mut self) -> &mut PostRenderer {
+        &mut self.post
+    }
+
+    /// Draws the background, with its colors decoded for an `srgb` framebuffer.
+    pub fn draw(&self, srgb: bool) {
+        let clear_color = if srgb {
+            color::linearize(CLEAR_COLOR)
+        } else {
+            CLEAR_COLOR
+        };
         unsafe {
-            self.gl.UseProgram(self.program);
+            self.gl.UseProgram(self.program.name());
 
-            self.gl.BindVertexArray(self.vao);
-            self.gl.BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            self.gl.BindVertexArray(self.vao.name());
+            self.gl.BindBuffer(gl::ARRAY_BUFFER, self.vbo.name());
 
-            self.gl.ClearColor(0.1, 0.1, 0.1, 0.9);
+            let [r, g, b, a] = clear_color;
+            self.gl.ClearColor(r, g, b, a);
             self.gl.Clear(gl::COLOR_BUFFER_BIT);
             self.gl.DrawArrays(gl::TRIANGLES, 0, 3);
         }
@@ -220,24 +432,78 @@ impl Renderer {
             self.gl.Viewport(0, 0, width, height);
         }
     }
-}
 
-impl Drop for Renderer {
-    // TODO: Note that this needs a "current" context with a surface!
-    fn drop(&mut self) {
+    /// Calls `draw` with a new `width`x`height` framebuffer bound instead of a window, returning
+    /// its pixels as read by `glReadPixels()`: tightly packed RGBA8, with the bottom row first.
+    /// An `srgb` framebuffer encodes on write like an sRGB surface, and its pixels stay encoded.
+    #[cfg(target_os = "android")]
+    pub fn draw_offscreen(
+        &mut self,
+        deletion_queue: &DeletionQueue,
+        width: i32,
+        height: i32,
+        srgb: bool,
+        draw: impl FnOnce(&mut Self),
+    ) -> bitmap::Result<Vec<u8>> {
+        let format = if srgb { gl::SRGB8_ALPHA8 } else { gl::RGBA8 };
         unsafe {
-            self.gl.DeleteProgram(self.program);
-            self.gl.DeleteBuffers(1, &self.vbo);
-            self.gl.DeleteVertexArrays(1, &self.vao);
+            let mut renderbuffer = 0;
+            self.gl.GenRenderbuffers(1, &mut renderbuffer);
+            let renderbuffer = deletion_queue.wrap(GlObjectKind::Renderbuffer, renderbuffer);
+            self.gl
+                .BindRenderbuffer(gl::RENDERBUFFER, renderbuffer.name());
+            self.gl
+                .RenderbufferStorage(gl::RENDERBUFFER, format, width, height);
+
+            let mut framebuffer = 0;
+            self.gl.GenFramebuffers(1, &mut framebuffer);
+            let framebuffer = deletion_queue.wrap(GlObjectKind::Framebuffer, framebuffer);
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer.name());
+            self.gl.FramebufferRenderbuffer(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::RENDERBUFFER,
+                renderbuffer.name(),
+            );
+            let status = self.gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                // The objects are deleted on the next drain
+                self.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+                return Err(BitmapError::IncompleteFramebuffer {
+                    width,
+                    height,
+                    status,
+                });
+            }
+
+            self.resize(width, height);
+            draw(self);
+
+            let mut pixels = vec![0; width as usize * height as usize * 4];
+            self.gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+            self.gl.ReadPixels(
+                0,
+                0,
+                width,
+                height,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr().cast(),
+            );
+
+            // Back to the window surface, the objects are deleted on the next drain
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+            Ok(pixels)
         }
     }
 }
 
-unsafe fn create_shader(
+/// Compiles the NUL-terminated `source`, failing with the info log after deleting the shader.
+pub unsafe fn create_shader(
     gl: &gl::Gl,
     shader: gl::types::GLenum,
     source: &[u8],
-) -> gl::types::GLuint {
+) -> Result<gl::types::GLuint, String> {
     let shader = gl.CreateShader(shader);
     gl.ShaderSource(
         shader,
@@ -246,7 +512,112 @@ unsafe fn create_shader(
         std::ptr::null(),
     );
     gl.CompileShader(shader);
-    shader
+
+    let mut status = 0;
+    gl.GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
+    if status != 0 {
+        return Ok(shader);
+    }
+    let mut len = 0;
+    gl.GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
+    let mut log = vec![0; len.max(1) as usize];
+    gl.GetShaderInfoLog(shader, len, std::ptr::null_mut(), log.as_mut_ptr().cast());
+    gl.DeleteShader(shader);
+    Err(info_log(log))
+}
+
+/// Links the shaders `vertex` and `fragment`, each given by its asset path and NUL-terminated
+/// source, with the `attributes` bound to their locations.  Fails with the info log of the shader
+/// or program that failed, naming the asset paths.
+unsafe fn link_program(
+    gl: &gl::Gl,
+    (vertex_path, vertex_source): (&str, &[u8]),
+    (fragment_path, fragment_source): (&str, &[u8]),
+    attributes: &[(gl::types::GLuint, &CStr)],
+) -> Result<gl::types::GLuint, String> {
+    let vertex_shader = create_shader(gl, gl::VERTEX_SHADER, vertex_source)
+        .map_err(|log| format!("Cannot compile {vertex_path:?}: {log}"))?;
+    let fragment_shader = match create_shader(gl, gl::FRAGMENT_SHADER, fragment_source) {
+        Ok(shader) => shader,
+        Err(log) => {
+            gl.DeleteShader(vertex_shader);
+            return Err(format!("Cannot compile {fragment_path:?}: {log}"));
+        }
+    };
+
+    let program = gl.CreateProgram();
+    gl.AttachShader(program, vertex_shader);
+    gl.AttachShader(program, fragment_shader);
+    for &(location, name) in attributes {
+        gl.BindAttribLocation(program, location, name.as_ptr());
+    }
+    gl.LinkProgram(program);
+    // Only flagged for deletion until the program is
+    gl.DeleteShader(vertex_shader);
+    gl.DeleteShader(fragment_shader);
+
+    let mut status = 0;
+    gl.GetProgramiv(program, gl::LINK_STATUS, &mut status);
+    if status != 0 {
+        return Ok(program);
+    }
+    let mut len = 0;
+    gl.GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
+    let mut log = vec![0; len.max(1) as usize];
+    gl.GetProgramInfoLog(program, len, std::ptr::null_mut(), log.as_mut_ptr().cast());
+    gl.DeleteProgram(program);
+    Err(format!(
+        "Cannot link {vertex_path:?} and {fragment_path:?}: {}",
+        info_log(log)
+    ))
+}
+
+/// Info log as written by `glGet*InfoLog()`, without its NUL terminator and trailing newline
+fn info_log(log: Vec<u8>) -> String {
+    let len = log.iter().position(|&b| b == 0).unwrap_or(log.len());
+    String::from_utf8_lossy(&log[..len]).trim_end().to_owned()
+}
+
+/// Builds the program of the shaders `vertex` and `fragment`, each given by its asset path and
+/// built-in source, which are loaded through [`assets::shader()`] and may thus be overridden.  The
+/// `attributes` are bound to their locations before linking.
+///
+/// When an overridden shader fails to compile or link, the error is logged and the program is
+/// built from the built-in sources instead.  Should even those fail, which is a bug, the error is
+/// logged and program `0` is returned, which draws nothing.
+pub unsafe fn create_program(
+    gl: &gl::Gl,
+    (vertex_path, vertex_builtin): (&str, &'static [u8]),
+    (fragment_path, fragment_builtin): (&str, &'static [u8]),
+    attributes: &[(gl::types::GLuint, &CStr)],
+) -> gl::types::GLuint {
+    let vertex_source = assets::shader(vertex_path, vertex_builtin);
+    let fragment_source = assets::shader(fragment_path, fragment_builtin);
+    let overridden = [&vertex_source, &fragment_source]
+        .iter()
+        .any(|source| matches!(source, Cow::Owned(_)));
+    let result = link_program(
+        gl,
+        (vertex_path, &vertex_source),
+        (fragment_path, &fragment_source),
+        attributes,
+    );
+    let result = match result {
+        Err(e) if overridden => {
+            warn!("{e}, using the built-in shaders");
+            link_program(
+                gl,
+                (vertex_path, vertex_builtin),
+                (fragment_path, fragment_builtin),
+                attributes,
+            )
+        }
+        result => result,
+    };
+    result.unwrap_or_else(|e| {
+        error!("{e}");
+        0
+    })
 }
 
 fn get_gl_string(gl: &gl::Gl, variant: gl::types::GLenum) -> Option<&'static CStr> {
@@ -256,8 +627,11 @@ fn get_gl_string(gl: &gl::Gl, variant: gl::types::GLenum) -> Option<&'static CSt
     }
 }
 
+pub const CLEAR_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 0.9];
+
+/// Interleaved `vec2 position, vec3 color` per vertex
 #[rustfmt::skip]
-static VERTEX_DATA: [f32; 15] = [
+pub static VERTEX_DATA: [f32; 15] = [
     -0.5, -0.5,  1.0,  0.0,  0.0,
      0.0,  0.5,  0.0,  1.0,  0.0,
      0.5, -0.5,  0.0,  0.0,  1.0,
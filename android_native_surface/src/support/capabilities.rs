@@ -0,0 +1,403 @@
+//! Probe of what the current GLES context and its EGL display provide.
+//!
+//! The bindings from `build.rs` contain every GLES 3.2 entry point plus those of a curated set of
+//! extensions, but EGL may hand out an older context, and drivers advertise only some of the
+//! extensions.  Use [`Capabilities::supports()`] before calling into anything beyond GLES 3.0.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::CStr,
+    fmt,
+};
+
+use glutin::{
+    display::{Display, GetDisplayExtensions as _, GlDisplay as _},
+    error::ErrorKind,
+};
+
+use super::{get_gl_string, gl};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GlesVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl GlesVersion {
+    pub const ES_3_0: Self = Self::new(3, 0);
+    pub const ES_3_2: Self = Self::new(3, 2);
+
+    pub const fn new(major: u8, minor: u8) -> Self {
+        Self { major, minor }
+    }
+
+    /// Parses `GL_VERSION`, which is formatted as `OpenGL ES <major>.<minor> <vendor info>`, or
+    /// as `OpenGL ES-CM <major>.<minor>` for GLES 1.
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.strip_prefix("OpenGL ES")?;
+        let version = version
+            .strip_prefix("-CM")
+            .or_else(|| version.strip_prefix("-CL"))
+            .unwrap_or(version);
+        let number = version.split_whitespace().next()?;
+        let (major, minor) = number.split_once('.')?;
+        Some(Self::new(major.parse().ok()?, minor.parse().ok()?))
+    }
+}
+
+impl fmt::Display for GlesVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GLES {}.{}", self.major, self.minor)
+    }
+}
+
+/// Groups of entry points and enums in the bindings that not every context provides
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// `glDebugMessageCallback()` and friends, which are loaded from their `KHR`-suffixed names
+    /// when only the extension is available
+    DebugOutput,
+    /// `glQueryCounterEXT()`, `GL_TIME_ELAPSED_EXT` and `GL_GPU_DISJOINT_EXT`
+    TimerQuery,
+    /// `GL_TEXTURE_EXTERNAL_OES` and `glEGLImageTargetTexture2DOES()`
+    ExternalImage,
+    /// `glFramebufferTexture2DMultisampleEXT()` and `glRenderbufferStorageMultisampleEXT()`
+    MultisampledRenderToTexture,
+    /// `GL_FRAMEBUFFER_SRGB_EXT` for `glEnable()`/`glDisable()`
+    SrgbWriteControl,
+    /// Rendering to `GL_RGBA16F`, which also allows generating its mipmaps
+    ColorBufferHalfFloat,
+    /// `GL_MAX_TEXTURE_MAX_ANISOTROPY_EXT` and `GL_TEXTURE_MAX_ANISOTROPY_EXT`
+    AnisotropicFiltering,
+}
+
+impl Feature {
+    pub const ALL: [Self; 7] = [
+        Self::DebugOutput,
+        Self::TimerQuery,
+        Self::ExternalImage,
+        Self::MultisampledRenderToTexture,
+        Self::SrgbWriteControl,
+        Self::ColorBufferHalfFloat,
+        Self::AnisotropicFiltering,
+    ];
+
+    /// First version that has this feature in core
+    fn core_version(self) -> Option<GlesVersion> {
+        match self {
+            Self::DebugOutput | Self::ColorBufferHalfFloat => Some(GlesVersion::ES_3_2),
+            _ => None,
+        }
+    }
+
+    /// Extensions that each provide this feature
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Self::DebugOutput => &["GL_KHR_debug"],
+            Self::TimerQuery => &["GL_EXT_disjoint_timer_query"],
+            Self::ExternalImage => &["GL_OES_EGL_image_external"],
+            Self::MultisampledRenderToTexture => &["GL_EXT_multisampled_render_to_texture"],
+            Self::SrgbWriteControl => &["GL_EXT_sRGB_write_control"],
+            Self::ColorBufferHalfFloat => &[
+                "GL_EXT_color_buffer_half_float",
+                "GL_EXT_color_buffer_float",
+            ],
+            Self::AnisotropicFiltering => &["GL_EXT_texture_filter_anisotropic"],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CapabilitiesError {
+    /// The context could not be made current to query it
+    Egl(ErrorKind),
+    /// `GL_VERSION` is not formatted as specified
+    UnknownVersion(String),
+    /// The context is older than GLES 3.0
+    Unsupported(GlesVersion),
+}
+
+impl fmt::Display for CapabilitiesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Egl(kind) => write!(f, "Cannot make the GL context current: {kind:?}"),
+            Self::UnknownVersion(version) => write!(f, "Cannot parse GL_VERSION {version:?}"),
+            Self::Unsupported(version) => {
+                write!(f, "{version} is too old, at least GLES 3.0 is required")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CapabilitiesError {}
+
+impl From<ErrorKind> for CapabilitiesError {
+    fn from(kind: ErrorKind) -> Self {
+        Self::Egl(kind)
+    }
+}
+
+pub type Result<T, E = CapabilitiesError> = std::result::Result<T, E>;
+
+/// Filled once per display by [`Capabilities::query()`], contexts that are recreated on the same
+/// display and config provide the same
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    pub version: GlesVersion,
+    /// `GL_SHADING_LANGUAGE_VERSION`
+    pub glsl_version: String,
+    /// `GL_RENDERER`
+    pub renderer: String,
+    /// `GL_VENDOR`
+    pub vendor: String,
+    pub max_texture_size: u32,
+    pub max_renderbuffer_size: u32,
+    /// `GL_MAX_SAMPLES`, for multisampled renderbuffers
+    pub max_samples: u32,
+    /// `GL_MAX_SAMPLES_EXT`, when [`Feature::MultisampledRenderToTexture`] is supported
+    pub max_samples_render_to_texture: Option<u32>,
+    pub egl_version: String,
+    gl_extensions: BTreeSet<String>,
+    egl_extensions: BTreeSet<String>,
+}
+
+impl Capabilities {
+    /// # Safety
+    /// A context of `gl_display` must be current, which is what is queried.
+    pub unsafe fn query(gl: &gl::Gl, gl_display: &Display) -> Result<Self> {
+        let string = |name| {
+            get_gl_string(gl, name).map_or_else(
+                || "unknown".to_owned(),
+                |s| s.to_string_lossy().into_owned(),
+            )
+        };
+        let integer = |name| {
+            let mut value = 0;
+            gl.GetIntegerv(name, &mut value);
+            value as u32
+        };
+
+        let version = string(gl::VERSION);
+        let version = GlesVersion::parse(&version)
+            .ok_or_else(|| CapabilitiesError::UnknownVersion(version.clone()))?;
+        if version < GlesVersion::ES_3_0 {
+            return Err(CapabilitiesError::Unsupported(version));
+        }
+
+        let gl_extensions = (0..integer(gl::NUM_EXTENSIONS))
+            .filter_map(|i| {
+                let s = gl.GetStringi(gl::EXTENSIONS, i);
+                (!s.is_null()).then(|| CStr::from_ptr(s.cast()).to_string_lossy().into_owned())
+            })
+            .collect();
+
+        let Display::Egl(egl_display) = gl_display;
+        let egl_extensions = egl_display
+            .extensions()
+            .iter()
+            .map(|&extension| extension.to_owned())
+            .collect();
+
+        let mut capabilities = Self {
+            version,
+            glsl_version: string(gl::SHADING_LANGUAGE_VERSION),
+            renderer: string(gl::RENDERER),
+            vendor: string(gl::VENDOR),
+            max_texture_size: integer(gl::MAX_TEXTURE_SIZE),
+            max_renderbuffer_size: integer(gl::MAX_RENDERBUFFER_SIZE),
+            max_samples: integer(gl::MAX_SAMPLES),
+            max_samples_render_to_texture: None,
+            egl_version: gl_display.version_string(),
+            gl_extensions,
+            egl_extensions,
+        };
+        if capabilities.supports(Feature::MultisampledRenderToTexture) {
+            capabilities.max_samples_render_to_texture = Some(integer(gl::MAX_SAMPLES_EXT));
+        }
+        Ok(capabilities)
+    }
+
+    pub fn has_gl_extension(&self, name: &str) -> bool {
+        self.gl_extensions.contains(name)
+    }
+
+    /// Whether the entry points and enums of `feature` can be used
+    pub fn supports(&self, feature: Feature) -> bool {
+        feature
+            .core_version()
+            .is_some_and(|core| self.version >= core)
+            || feature
+                .extensions()
+                .iter()
+                .any(|extension| self.has_gl_extension(extension))
+    }
+
+    /// Flattened to strings for reporting, with extensions separated by spaces like
+    /// `GL_EXTENSIONS`.  Keys are stable and prefixed with `gl.` or `egl.`.
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        let join = |set: &BTreeSet<String>| set.iter().cloned().collect::<Vec<_>>().join(" ");
+        let features = Feature::ALL
+            .into_iter()
+            .filter(|&feature| self.supports(feature))
+            .map(|feature| format!("{feature:?}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        [
+            ("gl.version", self.version.to_string()),
+            ("gl.glsl_version", self.glsl_version.clone()),
+            ("gl.renderer", self.renderer.clone()),
+            ("gl.vendor", self.vendor.clone()),
+            ("gl.max_texture_size", self.max_texture_size.to_string()),
+            (
+                "gl.max_renderbuffer_size",
+                self.max_renderbuffer_size.to_string(),
+            ),
+            ("gl.max_samples", self.max_samples.to_string()),
+            (
+                "gl.max_samples_render_to_texture",
+                self.max_samples_render_to_texture
+                    .map_or_else(String::new, |samples| samples.to_string()),
+            ),
+            ("gl.features", features),
+            ("gl.extensions", join(&self.gl_extensions)),
+            ("egl.version", self.egl_version.clone()),
+            ("egl.extensions", join(&self.egl_extensions)),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), value))
+        .collect()
+    }
+}
+
+/// Multi-line summary for the log, without the extension lists
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} on {} ({})", self.version, self.renderer, self.vendor)?;
+        writeln!(f, "GLSL {}, {}", self.glsl_version, self.egl_version)?;
+        writeln!(
+            f,
+            "Max texture size {}, renderbuffer size {}, samples {}, render-to-texture samples {:?}",
+            self.max_texture_size,
+            self.max_renderbuffer_size,
+            self.max_samples,
+            self.max_samples_render_to_texture
+        )?;
+        let features = Feature::ALL
+            .into_iter()
+            .filter(|&feature| self.supports(feature))
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "{} GL and {} EGL extensions, supporting {features:?}",
+            self.gl_extensions.len(),
+            self.egl_extensions.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(version: GlesVersion, gl_extensions: &[&str]) -> Capabilities {
+        Capabilities {
+            version,
+            glsl_version: String::new(),
+            renderer: String::new(),
+            vendor: String::new(),
+            max_texture_size: 0,
+            max_renderbuffer_size: 0,
+            max_samples: 0,
+            max_samples_render_to_texture: None,
+            egl_version: String::new(),
+            gl_extensions: gl_extensions.iter().map(|&e| e.to_owned()).collect(),
+            egl_extensions: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn parse_version() {
+        assert_eq!(
+            GlesVersion::parse("OpenGL ES 3.2 V@0502.0 (GIT@5eaa426, I1e2a9ea3ee) (Date:10/19/20)"),
+            Some(GlesVersion::ES_3_2)
+        );
+        assert_eq!(
+            GlesVersion::parse("OpenGL ES 3.0 build 1.13@5776728"),
+            Some(GlesVersion::ES_3_0)
+        );
+        assert_eq!(
+            GlesVersion::parse("OpenGL ES 3.1 Mesa 23.2.1"),
+            Some(GlesVersion::new(3, 1))
+        );
+        assert_eq!(
+            GlesVersion::parse("OpenGL ES-CM 1.1"),
+            Some(GlesVersion::new(1, 1))
+        );
+        assert_eq!(
+            GlesVersion::parse("OpenGL ES-CL 1.0"),
+            Some(GlesVersion::new(1, 0))
+        );
+    }
+
+    #[test]
+    fn parse_invalid_version() {
+        // Desktop GL has no prefix
+        assert_eq!(GlesVersion::parse("4.6.0 NVIDIA 535.154.05"), None);
+        assert_eq!(GlesVersion::parse("4.6 (Core Profile) Mesa 23.2.1"), None);
+        assert_eq!(GlesVersion::parse("OpenGL ES"), None);
+        assert_eq!(GlesVersion::parse("OpenGL ES 3"), None);
+        assert_eq!(GlesVersion::parse("OpenGL ES x.y"), None);
+        assert_eq!(GlesVersion::parse(""), None);
+    }
+
+    #[test]
+    fn versions_are_ordered() {
+        assert!(GlesVersion::new(1, 1) < GlesVersion::ES_3_0);
+        assert!(GlesVersion::ES_3_0 < GlesVersion::new(3, 1));
+        assert!(GlesVersion::new(3, 1) < GlesVersion::ES_3_2);
+        assert_eq!(GlesVersion::ES_3_2.to_string(), "GLES 3.2");
+    }
+
+    #[test]
+    fn supports_features_of_core_version() {
+        let es_3_0 = capabilities(GlesVersion::ES_3_0, &[]);
+        let es_3_2 = capabilities(GlesVersion::ES_3_2, &[]);
+        for feature in [Feature::DebugOutput, Feature::ColorBufferHalfFloat] {
+            assert!(!es_3_0.supports(feature), "{feature:?}");
+            assert!(es_3_2.supports(feature), "{feature:?}");
+        }
+        // Extensions only, in any version
+        for feature in [
+            Feature::TimerQuery,
+            Feature::ExternalImage,
+            Feature::MultisampledRenderToTexture,
+            Feature::SrgbWriteControl,
+            Feature::AnisotropicFiltering,
+        ] {
+            assert!(!es_3_2.supports(feature), "{feature:?}");
+        }
+    }
+
+    #[test]
+    fn supports_features_of_extensions() {
+        let capabilities = capabilities(
+            GlesVersion::ES_3_0,
+            &[
+                "GL_KHR_debug",
+                "GL_EXT_disjoint_timer_query",
+                "GL_EXT_color_buffer_float",
+            ],
+        );
+        assert!(capabilities.supports(Feature::DebugOutput));
+        assert!(capabilities.supports(Feature::TimerQuery));
+        // Either of its extensions will do
+        assert!(capabilities.supports(Feature::ColorBufferHalfFloat));
+        assert!(!capabilities.supports(Feature::ExternalImage));
+        assert!(!capabilities.supports(Feature::MultisampledRenderToTexture));
+        assert_eq!(
+            capabilities.to_map()["gl.features"],
+            "DebugOutput TimerQuery ColorBufferHalfFloat"
+        );
+    }
+}
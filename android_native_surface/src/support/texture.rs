@@ -0,0 +1,731 @@
+//! Decoding of PNG, JPEG and KTX2 images, and their upload to GL textures.
+//!
+//! Decoding produces an [`Image`] without touching GL.  KTX2 containers may carry ETC2 or ASTC
+//! payloads, which [`Texture::new()`] uploads as-is when the GPU lists them in
+//! `GL_COMPRESSED_TEXTURE_FORMATS`, and otherwise decompresses on the CPU with
+//! [`Image::decompress()`].
+
+use std::{fmt, io::Cursor};
+
+use log::debug;
+use zune_jpeg::{
+    zune_core::{colorspace::ColorSpace, options::DecoderOptions},
+    JpegDecoder,
+};
+
+use super::{
+    capabilities::{Capabilities, Feature},
+    gl::{self, types::GLenum},
+};
+use crate::deletion_queue::{DeletionQueue, GlObject, GlObjectKind};
+
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_MAGIC: &[u8] = b"\xff\xd8\xff";
+const KTX2_MAGIC: &[u8] = b"\xabKTX 20\xbb\r\n\x1a\n";
+
+/// Block sizes of the 2D ASTC formats, in the order of both their `VkFormat` and GL enum values
+const ASTC_BLOCK_SIZES: [(u8, u8); 14] = [
+    (4, 4),
+    (5, 4),
+    (5, 5),
+    (6, 5),
+    (6, 6),
+    (8, 5),
+    (8, 6),
+    (8, 8),
+    (10, 5),
+    (10, 6),
+    (10, 8),
+    (10, 10),
+    (12, 10),
+    (12, 12),
+];
+
+#[derive(Debug)]
+pub enum TextureError {
+    Png(png::DecodingError),
+    Jpeg(zune_jpeg::errors::DecodeErrors),
+    Ktx2(ktx2::ParseError),
+    /// The data does not start with a known magic number
+    UnknownFormat,
+    /// A valid image that uses a feature we cannot handle
+    Unsupported(String),
+    /// Inconsistent or truncated pixel data
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Png(e) => write!(f, "Invalid PNG: {e}"),
+            Self::Jpeg(e) => write!(f, "Invalid JPEG: {e}"),
+            Self::Ktx2(e) => write!(f, "Invalid KTX2: {e}"),
+            Self::UnknownFormat => f.write_str("Not a PNG, JPEG or KTX2 image"),
+            Self::Unsupported(what) => write!(f, "Unsupported image: {what}"),
+            Self::Corrupt(what) => write!(f, "Corrupt image: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+impl From<png::DecodingError> for TextureError {
+    fn from(e: png::DecodingError) -> Self {
+        Self::Png(e)
+    }
+}
+
+impl From<zune_jpeg::errors::DecodeErrors> for TextureError {
+    fn from(e: zune_jpeg::errors::DecodeErrors) -> Self {
+        Self::Jpeg(e)
+    }
+}
+
+impl From<ktx2::ParseError> for TextureError {
+    fn from(e: ktx2::ParseError) -> Self {
+        Self::Ktx2(e)
+    }
+}
+
+pub type Result<T, E = TextureError> = std::result::Result<T, E>;
+
+/// Block-compressed formats that can be uploaded with `glCompressedTexImage2D()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressedFormat {
+    Etc2Rgb8,
+    /// 1-bit "punch-through" alpha
+    Etc2Rgb8A1,
+    /// ETC2 color with EAC alpha
+    Etc2Rgba8,
+    Astc {
+        block_width: u8,
+        block_height: u8,
+    },
+}
+
+impl CompressedFormat {
+    /// Size of a block in pixels
+    pub fn block_size(self) -> (u32, u32) {
+        match self {
+            Self::Etc2Rgb8 | Self::Etc2Rgb8A1 | Self::Etc2Rgba8 => (4, 4),
+            Self::Astc {
+                block_width,
+                block_height,
+            } => (block_width.into(), block_height.into()),
+        }
+    }
+
+    pub fn bytes_per_block(self) -> usize {
+        match self {
+            Self::Etc2Rgb8 | Self::Etc2Rgb8A1 => 8,
+            Self::Etc2Rgba8 | Self::Astc { .. } => 16,
+        }
+    }
+
+    pub fn gl_internal_format(self, srgb: bool) -> GLenum {
+        match (self, srgb) {
+            (Self::Etc2Rgb8, false) => gl::COMPRESSED_RGB8_ETC2,
+            (Self::Etc2Rgb8, true) => gl::COMPRESSED_SRGB8_ETC2,
+            (Self::Etc2Rgb8A1, false) => gl::COMPRESSED_RGB8_PUNCHTHROUGH_ALPHA1_ETC2,
+            (Self::Etc2Rgb8A1, true) => gl::COMPRESSED_SRGB8_PUNCHTHROUGH_ALPHA1_ETC2,
+            (Self::Etc2Rgba8, false) => gl::COMPRESSED_RGBA8_ETC2_EAC,
+            (Self::Etc2Rgba8, true) => gl::COMPRESSED_SRGB8_ALPHA8_ETC2_EAC,
+            (
+                Self::Astc {
+                    block_width,
+                    block_height,
+                },
+                srgb,
+            ) => {
+                let index = ASTC_BLOCK_SIZES
+                    .iter()
+                    .position(|&size| size == (block_width, block_height))
+                    .expect("Not a 2D ASTC block size") as GLenum;
+                let base = if srgb {
+                    gl::COMPRESSED_SRGB8_ALPHA8_ASTC_4x4
+                } else {
+                    gl::COMPRESSED_RGBA_ASTC_4x4
+                };
+                base + index
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    /// Tightly packed 8-bit RGBA
+    Rgba8,
+    /// Tightly packed native-endian `u16`s with 5 bits of red, 6 of green and 5 of blue, from the
+    /// most significant bit down.  There is no sRGB variant, hence [`Image::srgb`] is ignored.
+    Rgb565,
+    /// Tightly packed native-endian half floats in RGBA order, linear and not mipmapped
+    Rgba16F,
+    Compressed(CompressedFormat),
+}
+
+/// A decoded image with one or more mip levels
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+    /// Whether the color channels are sRGB-encoded, rather than linear
+    pub srgb: bool,
+    /// Largest first.  A single level is expanded into a full mip chain on upload, when the format
+    /// allows it.
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl Image {
+    /// Size of mip `level` in pixels
+    pub fn level_size(&self, level: usize) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    /// Number of bytes that mip `level` must have
+    pub fn level_byte_len(&self, level: usize) -> usize {
+        let (width, height) = self.level_size(level);
+        match self.format {
+            TextureFormat::Rgba8 => width as usize * height as usize * 4,
+            TextureFormat::Rgb565 => width as usize * height as usize * 2,
+            TextureFormat::Rgba16F => width as usize * height as usize * 8,
+            TextureFormat::Compressed(format) => {
+                let (block_width, block_height) = format.block_size();
+                let blocks = width.div_ceil(block_width) * height.div_ceil(block_height);
+                blocks as usize * format.bytes_per_block()
+            }
+        }
+    }
+
+    fn validate(self) -> Result<Self> {
+        if self.width == 0 || self.height == 0 {
+            return Err(TextureError::Corrupt("Image has no pixels"));
+        }
+        if self.levels.is_empty() {
+            return Err(TextureError::Corrupt("Image has no mip levels"));
+        }
+        for (level, data) in self.levels.iter().enumerate() {
+            if data.len() < self.level_byte_len(level) {
+                return Err(TextureError::Corrupt("Mip level is truncated"));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Converts a compressed image to [`TextureFormat::Rgba8`], keeping all mip levels.
+    pub fn decompress(self) -> Result<Self> {
+        let TextureFormat::Compressed(format) = self.format else {
+            return Ok(self);
+        };
+
+        let levels = self
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, data)| {
+                let (width, height) = self.level_size(level);
+                let (width, height) = (width as usize, height as usize);
+                // BGRA, as the little-endian bytes of every u32
+                let mut pixels = vec![0u32; width * height];
+                match format {
+                    CompressedFormat::Etc2Rgb8 => {
+                        texture2ddecoder::decode_etc2_rgb(data, width, height, &mut pixels)
+                    }
+                    CompressedFormat::Etc2Rgb8A1 => {
+                        texture2ddecoder::decode_etc2_rgba1(data, width, height, &mut pixels)
+                    }
+                    CompressedFormat::Etc2Rgba8 => {
+                        texture2ddecoder::decode_etc2_rgba8(data, width, height, &mut pixels)
+                    }
+                    CompressedFormat::Astc {
+                        block_width,
+                        block_height,
+                    } => texture2ddecoder::decode_astc(
+                        data,
+                        width,
+                        height,
+                        block_width.into(),
+                        block_height.into(),
+                        &mut pixels,
+                    ),
+                }
+                .map_err(TextureError::Corrupt)?;
+
+                Ok(pixels
+                    .into_iter()
+                    .flat_map(|pixel| {
+                        let [b, g, r, a] = pixel.to_le_bytes();
+                        [r, g, b, a]
+                    })
+                    .collect())
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            format: TextureFormat::Rgba8,
+            levels,
+            ..self
+        })
+    }
+}
+
+/// Decodes a PNG, JPEG or KTX2 image, detected by its magic number.
+pub fn decode(data: &[u8]) -> Result<Image> {
+    if data.starts_with(PNG_MAGIC) {
+        decode_png(data)
+    } else if data.starts_with(JPEG_MAGIC) {
+        decode_jpeg(data)
+    } else if data.starts_with(KTX2_MAGIC) {
+        decode_ktx2(data)
+    } else {
+        Err(TextureError::UnknownFormat)
+    }
+}
+
+pub fn decode_png(data: &[u8]) -> Result<Image> {
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    // Palette, low bit depth and 16-bit images to 8-bit, always with an alpha channel
+    decoder.set_transformations(
+        png::Transformations::EXPAND | png::Transformations::STRIP_16 | png::Transformations::ALPHA,
+    );
+    let mut reader = decoder.read_info()?;
+    let size = reader
+        .output_buffer_size()
+        .ok_or(TextureError::Corrupt("PNG does not fit in memory"))?;
+    let mut pixels = vec![0; size];
+    let info = reader.next_frame(&mut pixels)?;
+    pixels.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => pixels,
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|la| [la[0], la[0], la[0], la[1]])
+            .collect(),
+        color_type => {
+            return Err(TextureError::Unsupported(format!(
+                "PNG decoded to {color_type:?}"
+            )))
+        }
+    };
+
+    Image {
+        width: info.width,
+        height: info.height,
+        format: TextureFormat::Rgba8,
+        srgb: true,
+        levels: vec![pixels],
+    }
+    .validate()
+}
+
+pub fn decode_jpeg(data: &[u8]) -> Result<Image> {
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGBA);
+    let mut decoder = JpegDecoder::new_with_options(Cursor::new(data), options);
+    let pixels = decoder.decode()?;
+    let info = decoder.info().expect("Headers are decoded");
+
+    Image {
+        width: info.width.into(),
+        height: info.height.into(),
+        format: TextureFormat::Rgba8,
+        srgb: true,
+        levels: vec![pixels],
+    }
+    .validate()
+}
+
+/// Maps a `VkFormat` to the format and whether it is sRGB-encoded.
+fn ktx2_format(format: ktx2::Format) -> Result<(TextureFormat, bool)> {
+    use ktx2::Format as F;
+
+    let astc_first = F::ASTC_4x4_UNORM_BLOCK.value();
+    let astc_last = F::ASTC_12x12_SRGB_BLOCK.value();
+
+    Ok(match format {
+        F::R8G8B8A8_UNORM => (TextureFormat::Rgba8, false),
+        F::R8G8B8A8_SRGB => (TextureFormat::Rgba8, true),
+        F::ETC2_R8G8B8_UNORM_BLOCK => (CompressedFormat::Etc2Rgb8.into(), false),
+        F::ETC2_R8G8B8_SRGB_BLOCK => (CompressedFormat::Etc2Rgb8.into(), true),
+        F::ETC2_R8G8B8A1_UNORM_BLOCK => (CompressedFormat::Etc2Rgb8A1.into(), false),
+        F::ETC2_R8G8B8A1_SRGB_BLOCK => (CompressedFormat::Etc2Rgb8A1.into(), true),
+        F::ETC2_R8G8B8A8_UNORM_BLOCK => (CompressedFormat::Etc2Rgba8.into(), false),
+        F::ETC2_R8G8B8A8_SRGB_BLOCK => (CompressedFormat::Etc2Rgba8.into(), true),
+        // UNORM and SRGB alternate for every block size
+        format if (astc_first..=astc_last).contains(&format.value()) => {
+            let offset = format.value() - astc_first;
+            let (block_width, block_height) = ASTC_BLOCK_SIZES[offset as usize / 2];
+            let format = CompressedFormat::Astc {
+                block_width,
+                block_height,
+            };
+            (format.into(), offset % 2 == 1)
+        }
+        format => return Err(TextureError::Unsupported(format!("KTX2 format {format:?}"))),
+    })
+}
+
+impl From<CompressedFormat> for TextureFormat {
+    fn from(format: CompressedFormat) -> Self {
+        Self::Compressed(format)
+    }
+}
+
+pub fn decode_ktx2(data: &[u8]) -> Result<Image> {
+    let reader = ktx2::Reader::new(data)?;
+    let header = reader.header();
+
+    if let Some(scheme) = header.supercompression_scheme {
+        return Err(TextureError::Unsupported(format!(
+            "KTX2 supercompression {scheme:?}"
+        )));
+    }
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count != 1 {
+        return Err(TextureError::Unsupported(
+            "KTX2 array, cubemap or 3D texture".to_owned(),
+        ));
+    }
+    let format = header.format.ok_or_else(|| {
+        TextureError::Unsupported("KTX2 without a format (Basis Universal)".to_owned())
+    })?;
+    let (format, srgb) = ktx2_format(format)?;
+
+    Image {
+        width: header.pixel_width,
+        // 1D textures have a height of 0
+        height: header.pixel_height.max(1),
+        format,
+        srgb,
+        levels: reader.levels().map(|level| level.data.to_vec()).collect(),
+    }
+    .validate()
+}
+
+/// Whether the GPU lists `internal_format` in `GL_COMPRESSED_TEXTURE_FORMATS`.
+pub fn supports_compressed_format(gl: &gl::Gl, internal_format: GLenum) -> bool {
+    unsafe {
+        let mut count = 0;
+        gl.GetIntegerv(gl::NUM_COMPRESSED_TEXTURE_FORMATS, &mut count);
+        let mut formats = vec![0; count as usize];
+        if count > 0 {
+            gl.GetIntegerv(gl::COMPRESSED_TEXTURE_FORMATS, formats.as_mut_ptr());
+        }
+        formats.contains(&(internal_format as gl::types::GLint))
+    }
+}
+
+/// Owned `GL_TEXTURE_2D`, queued for deletion on [`Drop`]
+#[derive(Debug)]
+pub struct Texture {
+    texture: GlObject,
+    /// [`DeletionQueue::epoch()`] that the texture was uploaded in
+    epoch: u64,
+    width: u32,
+    height: u32,
+}
+
+impl Texture {
+    /// Uploads `image` to a new texture with mipmaps, decompressing it first when the GPU does not
+    /// support its format.
+    ///
+    /// Must be called with a context current, which the texture is then bound to.
+    pub fn new(
+        gl: &gl::Gl,
+        capabilities: &Capabilities,
+        deletion_queue: &DeletionQueue,
+        image: Image,
+    ) -> Result<Self> {
+        let max_size = capabilities.max_texture_size;
+        if image.width > max_size || image.height > max_size {
+            return Err(TextureError::Unsupported(format!(
+                "{}x{} exceeds GL_MAX_TEXTURE_SIZE of {max_size}",
+                image.width, image.height
+            )));
+        }
+
+        let image = match image.format {
+            TextureFormat::Compressed(format)
+                if !supports_compressed_format(gl, format.gl_internal_format(image.srgb)) =>
+            {
+                debug!("{format:?} is not supported by the GPU, decompressing");
+                image.decompress()?
+            }
+            _ => image,
+        };
+
+        unsafe {
+            let mut texture = 0;
+            gl.GenTextures(1, &mut texture);
+            // Wrap immediately so that it is not leaked if anything below panics
+            let texture = deletion_queue.wrap(GlObjectKind::Texture, texture);
+            gl.BindTexture(gl::TEXTURE_2D, texture.name());
+            gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+
+            for (level, data) in image.levels.iter().enumerate() {
+                let (width, height) = image.level_size(level);
+                match image.format {
+                    TextureFormat::Rgba8 => gl.TexImage2D(
+                        gl::TEXTURE_2D,
+                        level as _,
+                        if image.srgb {
+                            gl::SRGB8_ALPHA8
+                        } else {
+                            gl::RGBA8
+                        } as _,
+                        width as _,
+                        height as _,
+                        0,
+                        gl::RGBA,
+                        gl::UNSIGNED_BYTE,
+                        data.as_ptr().cast(),
+                    ),
+                    TextureFormat::Rgb565 => gl.TexImage2D(
+                        gl::TEXTURE_2D,
+                        level as _,
+                        gl::RGB565 as _,
+                        width as _,
+                        height as _,
+                        0,
+                        gl::RGB,
+                        gl::UNSIGNED_SHORT_5_6_5,
+                        data.as_ptr().cast(),
+                    ),
+                    TextureFormat::Rgba16F => gl.TexImage2D(
+                        gl::TEXTURE_2D,
+                        level as _,
+                        gl::RGBA16F as _,
+                        width as _,
+                        height as _,
+                        0,
+                        gl::RGBA,
+                        gl::HALF_FLOAT,
+                        data.as_ptr().cast(),
+                    ),
+                    TextureFormat::Compressed(format) => gl.CompressedTexImage2D(
+                        gl::TEXTURE_2D,
+                        level as _,
+                        format.gl_internal_format(image.srgb),
+                        width as _,
+                        height as _,
+                        0,
+                        image.level_byte_len(level) as _,
+                        data.as_ptr().cast(),
+                    ),
+                }
+            }
+
+            // Compressed formats cannot be rendered to, hence their mips must come with the image.
+            // Neither can RGBA16F without EXT_color_buffer_half_float.
+            let renderable = match image.format {
+                TextureFormat::Rgba8 | TextureFormat::Rgb565 => true,
+                TextureFormat::Rgba16F => capabilities.supports(Feature::ColorBufferHalfFloat),
+                TextureFormat::Compressed(_) => false,
+            };
+            let mipmapped = match (renderable, image.levels.len()) {
+                (true, 1) => {
+                    gl.GenerateMipmap(gl::TEXTURE_2D);
+                    true
+                }
+                (_, levels) => {
+                    // The chain may stop before 1x1
+                    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, levels as i32 - 1);
+                    levels > 1
+                }
+            };
+            let min_filter = if mipmapped {
+                gl::LINEAR_MIPMAP_LINEAR
+            } else {
+                gl::LINEAR
+            };
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as _);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
+
+            Ok(Self {
+                texture,
+                epoch: deletion_queue.epoch(),
+                width: image.width,
+                height: image.height,
+            })
+        }
+    }
+
+    /// [`None`] once the context that the texture was uploaded to is lost, after which it must be
+    /// uploaded again
+    pub fn name(&self, deletion_queue: &DeletionQueue) -> Option<gl::types::GLuint> {
+        (self.epoch == deletion_queue.epoch()).then(|| self.texture.name())
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_bytes!(concat!("../../tests/fixtures/", $name)).as_slice()
+        };
+    }
+
+    fn pixels(image: &Image) -> Vec<[u8; 4]> {
+        assert_eq!(image.format, TextureFormat::Rgba8);
+        image.levels[0]
+            .chunks_exact(4)
+            .map(|p| p.try_into().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn png_rgba() {
+        let image = decode(fixture!("rgba.png")).unwrap();
+        assert_eq!((image.width, image.height, image.srgb), (2, 2, true));
+        assert_eq!(
+            pixels(&image),
+            [
+                [255, 0, 0, 255],
+                [0, 255, 0, 128],
+                [0, 0, 255, 0],
+                [255, 255, 255, 255]
+            ]
+        );
+    }
+
+    #[test]
+    fn png_gray_is_expanded() {
+        let image = decode(fixture!("gray.png")).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(pixels(&image), [[0, 0, 0, 255], [200, 200, 200, 255]]);
+    }
+
+    #[test]
+    fn jpeg() {
+        let image = decode(fixture!("flat.jpg")).unwrap();
+        assert_eq!((image.width, image.height, image.srgb), (5, 3, true));
+        let pixels = pixels(&image);
+        assert_eq!(pixels.len(), 15);
+        for pixel in pixels {
+            // Only off by the rounding of the YCbCr conversion
+            let expected = [200, 100, 50, 255];
+            let close = pixel.iter().zip(expected).all(|(&a, b)| a.abs_diff(b) <= 2);
+            assert!(close, "{pixel:?} is not close to {expected:?}");
+        }
+    }
+
+    #[test]
+    fn ktx2_rgba8_with_mips() {
+        let image = decode(fixture!("rgba8.ktx2")).unwrap();
+        assert_eq!((image.width, image.height, image.srgb), (2, 2, true));
+        assert_eq!(image.format, TextureFormat::Rgba8);
+        assert_eq!(
+            image.levels,
+            [(0..16).collect::<Vec<_>>(), vec![16, 17, 18, 19]]
+        );
+        assert_eq!(image.level_size(1), (1, 1));
+    }
+
+    #[test]
+    fn ktx2_etc2_decompresses() {
+        let image = decode(fixture!("etc2.ktx2")).unwrap();
+        assert_eq!(image.format, CompressedFormat::Etc2Rgb8.into());
+        assert_eq!((image.width, image.height, image.srgb), (6, 6, false));
+        // 2x2 blocks of 8 bytes
+        assert_eq!(image.level_byte_len(0), 32);
+
+        let image = image.decompress().unwrap();
+        assert_eq!((image.width, image.height, image.srgb), (6, 6, false));
+        // 4-bit colors expanded to 8 bits, plus the modifier of 2
+        assert_eq!(pixels(&image), [[206, 104, 53, 255]; 36]);
+    }
+
+    #[test]
+    fn ktx2_etc2_eac_decompresses() {
+        let image = decode(fixture!("etc2_eac.ktx2")).unwrap();
+        assert_eq!(image.format, CompressedFormat::Etc2Rgba8.into());
+        assert!(image.srgb);
+
+        let image = image.decompress().unwrap();
+        assert!(image.srgb);
+        // Alpha is the base of 128 plus the modifier of -3
+        assert_eq!(pixels(&image), [[206, 104, 53, 125]; 16]);
+    }
+
+    #[test]
+    fn ktx2_astc_decompresses() {
+        let image = decode(fixture!("astc.ktx2")).unwrap();
+        let format = CompressedFormat::Astc {
+            block_width: 4,
+            block_height: 4,
+        };
+        assert_eq!(image.format, format.into());
+        assert!(!image.srgb);
+
+        let image = image.decompress().unwrap();
+        assert_eq!(pixels(&image), [[200, 100, 50, 255]; 16]);
+    }
+
+    #[test]
+    fn decompressing_uncompressed_images_is_a_no_op() {
+        let image = decode(fixture!("rgba.png")).unwrap();
+        let levels = image.levels.clone();
+        assert_eq!(image.decompress().unwrap().levels, levels);
+    }
+
+    #[test]
+    fn astc_block_sizes_follow_the_vk_format() {
+        use ktx2::Format as F;
+
+        for (format, size, srgb) in [
+            (F::ASTC_4x4_UNORM_BLOCK, (4, 4), false),
+            (F::ASTC_4x4_SRGB_BLOCK, (4, 4), true),
+            (F::ASTC_6x5_SRGB_BLOCK, (6, 5), true),
+            (F::ASTC_10x8_UNORM_BLOCK, (10, 8), false),
+            (F::ASTC_12x12_SRGB_BLOCK, (12, 12), true),
+        ] {
+            let (block_width, block_height) = size;
+            let expected = CompressedFormat::Astc {
+                block_width,
+                block_height,
+            };
+            assert_eq!(ktx2_format(format).unwrap(), (expected.into(), srgb));
+        }
+    }
+
+    /// `rgba8.ktx2` with its `vkFormat` replaced
+    fn ktx2_with_format(vk_format: u32) -> Vec<u8> {
+        let mut data = fixture!("rgba8.ktx2").to_vec();
+        data[12..16].copy_from_slice(&vk_format.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn rejects_unknown_and_broken_images() {
+        assert!(matches!(
+            decode(b"GIF89a"),
+            Err(TextureError::UnknownFormat)
+        ));
+        let png = fixture!("rgba.png");
+        assert!(matches!(
+            decode(&png[..png.len() - 20]),
+            Err(TextureError::Png(_))
+        ));
+        // VK_FORMAT_UNDEFINED is used by Basis Universal
+        assert!(matches!(
+            decode(&ktx2_with_format(0)),
+            Err(TextureError::Unsupported(_))
+        ));
+        // VK_FORMAT_R8_UNORM
+        assert!(matches!(
+            decode(&ktx2_with_format(9)),
+            Err(TextureError::Unsupported(_))
+        ));
+
+        // Claims to be larger than its levels are
+        let mut data = fixture!("rgba8.ktx2").to_vec();
+        data[20..24].copy_from_slice(&4u32.to_le_bytes());
+        assert!(matches!(decode(&data), Err(TextureError::Corrupt(_))));
+    }
+}
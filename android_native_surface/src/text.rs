@@ -0,0 +1,815 @@
+//! Text rendering for labels and overlays on top of [`crate::support::Renderer`].
+//!
+//! Fonts come from the system: through `AFontMatcher` on API 29+, which is loaded at runtime like
+//! [`crate::frame_rate`], or by scanning `/system/fonts` otherwise.  Glyphs are rasterized on the
+//! CPU with [`ab_glyph`] into a single-channel atlas texture, and every string queued during a
+//! frame is drawn as one batch of textured quads.
+//!
+//! Shaping is basic: one glyph per `char`, with kerning, newlines and tabs.  [`layout()`] and
+//! [`ShelfPacker`] do not touch GL or Android, so that they can be tested on the host with a stub
+//! [`Font`].
+
+use std::{
+    collections::HashMap,
+    ffi::{c_char, c_void, CStr, CString},
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use ab_glyph::{point, Font, FontArc, FontVec, GlyphId, Point, PxScale, ScaleFont};
+use libloading::Library;
+use log::{debug, warn};
+
+use crate::{
+    deletion_queue::{DeletionQueue, GlObject, GlObjectKind},
+    support::{create_program, gl},
+};
+
+/// Family that is matched when no specific font is requested
+const DEFAULT_FAMILY: &str = "sans-serif";
+/// Regular weight, in the 1-1000 scale of `AFontMatcher_setStyle()`
+const DEFAULT_WEIGHT: u16 = 400;
+/// Used when `AFontMatcher` is unavailable, present on every device since Android 5
+const FALLBACK_FONT: &str = "/system/fonts/Roboto-Regular.ttf";
+const SYSTEM_FONT_DIR: &str = "/system/fonts";
+
+/// Tabs advance to the next multiple of this many spaces
+const TAB_WIDTH: f32 = 4.0;
+/// Empty pixels around every glyph in the atlas, so that linear filtering never samples a neighbour
+const PADDING: u32 = 1;
+const ATLAS_SIZE: u32 = 1024;
+
+type CreateFn = unsafe extern "C" fn() -> *mut c_void;
+type DestroyFn = unsafe extern "C" fn(matcher: *mut c_void);
+type SetStyleFn = unsafe extern "C" fn(matcher: *mut c_void, weight: u16, italic: bool);
+type MatchFn = unsafe extern "C" fn(
+    matcher: *const c_void,
+    family_name: *const c_char,
+    text: *const u16,
+    text_length: u32,
+    run_length_out: *mut u32,
+) -> *mut c_void;
+type GetFontFilePathFn = unsafe extern "C" fn(font: *const c_void) -> *const c_char;
+type GetCollectionIndexFn = unsafe extern "C" fn(font: *const c_void) -> usize;
+type CloseFn = unsafe extern "C" fn(font: *mut c_void);
+
+/// `AFontMatcher_*()` and `AFont_*()`, which were introduced in API 29
+struct Functions {
+    create: CreateFn,
+    destroy: DestroyFn,
+    set_style: SetStyleFn,
+    match_: MatchFn,
+    get_font_file_path: GetFontFilePathFn,
+    get_collection_index: GetCollectionIndexFn,
+    close: CloseFn,
+    /// Keeps the function pointers above alive
+    _lib: Library,
+}
+
+fn functions() -> Option<&'static Functions> {
+    static FUNCTIONS: OnceLock<Option<Functions>> = OnceLock::new();
+    FUNCTIONS
+        .get_or_init(|| {
+            // SAFETY: libandroid.so is already loaded by virtue of linking against the NDK, this
+            // only increments its refcount and doesn't run any (new) initializers.
+            let lib = unsafe { Library::new("libandroid.so") }
+                .inspect_err(|e| warn!("Failed to open libandroid.so: {e}"))
+                .ok()?;
+            // SAFETY: The signatures match the NDK headers.  They were all added in the same API
+            // level, so either all or none are present.
+            unsafe {
+                Some(Functions {
+                    create: *lib.get(b"AFontMatcher_create\0").ok()?,
+                    destroy: *lib.get(b"AFontMatcher_destroy\0").ok()?,
+                    set_style: *lib.get(b"AFontMatcher_setStyle\0").ok()?,
+                    match_: *lib.get(b"AFontMatcher_match\0").ok()?,
+                    get_font_file_path: *lib.get(b"AFont_getFontFilePath\0").ok()?,
+                    get_collection_index: *lib.get(b"AFont_getCollectionIndex\0").ok()?,
+                    close: *lib.get(b"AFont_close\0").ok()?,
+                    _lib: lib,
+                })
+            }
+        })
+        .as_ref()
+}
+
+/// Path and index into a (possibly collection) font file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FontSource {
+    pub path: PathBuf,
+    pub index: u32,
+}
+
+/// Asks `AFontMatcher` for the font that the system would use to draw `text` in `family`.
+fn match_font(family: &CStr, weight: u16, italic: bool, text: &str) -> Option<FontSource> {
+    let f = functions()?;
+    let text = text.encode_utf16().collect::<Vec<_>>();
+    unsafe {
+        let matcher = (f.create)();
+        (f.set_style)(matcher, weight, italic);
+        let font = (f.match_)(
+            matcher,
+            family.as_ptr(),
+            text.as_ptr(),
+            text.len() as u32,
+            std::ptr::null_mut(),
+        );
+        (f.destroy)(matcher);
+        // Never null: the matcher falls back to the default font
+        let path = CStr::from_ptr((f.get_font_file_path)(font));
+        let source = FontSource {
+            path: PathBuf::from(path.to_str().ok()?),
+            index: (f.get_collection_index)(font) as u32,
+        };
+        (f.close)(font);
+        Some(source)
+    }
+}
+
+/// Picks a font from `/system/fonts` when `AFontMatcher` is unavailable.
+fn scan_system_fonts() -> Option<FontSource> {
+    let path = Path::new(FALLBACK_FONT);
+    if path.exists() {
+        return Some(FontSource {
+            path: path.to_owned(),
+            index: 0,
+        });
+    }
+
+    let mut fonts = fs::read_dir(SYSTEM_FONT_DIR)
+        .inspect_err(|e| warn!("Cannot list {SYSTEM_FONT_DIR}: {e}"))
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ttf"))
+        .collect::<Vec<_>>();
+    // Be deterministic across boots
+    fonts.sort();
+    Some(FontSource {
+        path: fonts.into_iter().next()?,
+        index: 0,
+    })
+}
+
+/// Finds a system font for `family` (e.g. `sans-serif` or `monospace`).
+pub fn find_system_font(family: &str, weight: u16, italic: bool) -> Option<FontSource> {
+    let family = CString::new(family).ok()?;
+    match_font(&family, weight, italic, "A").or_else(scan_system_fonts)
+}
+
+pub fn load_font(source: &FontSource) -> Option<FontArc> {
+    let data = fs::read(&source.path)
+        .inspect_err(|e| warn!("Cannot read font {:?}: {e}", source.path))
+        .ok()?;
+    let font = FontVec::try_from_vec_and_index(data, source.index)
+        .inspect_err(|e| warn!("Cannot parse font {source:?}: {e}"))
+        .ok()?;
+    Some(FontArc::new(font))
+}
+
+/// The default system font, loaded once per process.
+pub fn system_font() -> Option<&'static FontArc> {
+    static FONT: OnceLock<Option<FontArc>> = OnceLock::new();
+    FONT.get_or_init(|| {
+        let source = find_system_font(DEFAULT_FAMILY, DEFAULT_WEIGHT, false)?;
+        debug!("Using system font {source:?}");
+        load_font(&source)
+    })
+    .as_ref()
+}
+
+/// A glyph placed by [`layout()`], with `position` on the baseline relative to the top-left of
+/// the first line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionedGlyph {
+    pub id: GlyphId,
+    pub position: Point,
+}
+
+/// Places the glyphs of `text` at `px` pixels per em, from left to right.
+///
+/// Pen positions are rounded to whole pixels so that every glyph of the same size can share one
+/// rasterization in the atlas.
+pub fn layout(font: &impl Font, px: f32, text: &str) -> Vec<PositionedGlyph> {
+    let font = font.as_scaled(PxScale::from(px));
+    let line_height = font.height() + font.line_gap();
+    let tab = font.h_advance(font.glyph_id(' ')) * TAB_WIDTH;
+
+    let mut glyphs = Vec::with_capacity(text.len());
+    let mut pen = point(0.0, font.ascent());
+    let mut previous = None;
+    for c in text.chars() {
+        match c {
+            '\n' => {
+                pen = point(0.0, pen.y + line_height);
+                previous = None;
+                continue;
+            }
+            '\t' => {
+                pen.x = ((pen.x / tab).floor() + 1.0) * tab;
+                previous = None;
+                continue;
+            }
+            c if c.is_control() => continue,
+            _ => {}
+        }
+
+        // Unknown characters map to the .notdef glyph, which is usually a box
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            pen.x += font.kern(previous, id);
+        }
+        glyphs.push(PositionedGlyph {
+            id,
+            position: point(pen.x.round(), pen.y.round()),
+        });
+        pen.x += font.h_advance(id);
+        previous = Some(id);
+    }
+    glyphs
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    /// Start of the free space on this shelf
+    x: u32,
+}
+
+/// Packs rectangles into rows ("shelves") of a fixed-size area.
+///
+/// Glyphs of the same size have similar heights, which makes this waste little space while being
+/// far simpler than a skyline or guillotine packer.
+#[derive(Debug)]
+pub struct ShelfPacker {
+    width: u32,
+    height: u32,
+    shelves: Vec<Shelf>,
+}
+
+impl ShelfPacker {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            shelves: Vec::new(),
+        }
+    }
+
+    /// Returns the top-left corner of a free `width` x `height` area, or [`None`] when full.
+    pub fn pack(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if width > self.width {
+            return None;
+        }
+
+        // The shortest shelf that fits, to keep tall shelves free for tall glyphs
+        let best = self
+            .shelves
+            .iter_mut()
+            .filter(|s| s.height >= height && self.width - s.x >= width)
+            .min_by_key(|s| s.height);
+        if let Some(shelf) = best {
+            let x = shelf.x;
+            shelf.x += width;
+            return Some((x, shelf.y));
+        }
+
+        let y = self.shelves.last().map_or(0, |s| s.y + s.height);
+        if self.height - y < height {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y,
+            height,
+            x: width,
+        });
+        Some((0, y))
+    }
+
+    pub fn clear(&mut self) {
+        self.shelves.clear();
+    }
+}
+
+/// Location of a rasterized glyph in the atlas, relative to its pen position
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AtlasGlyph {
+    /// Offset of the top-left corner from the pen position, in pixels
+    offset: Point,
+    /// Top-left corner in the atlas, in pixels
+    origin: (u32, u32),
+    size: (u32, u32),
+}
+
+/// CPU copy of the atlas texture, see [`TextRenderer`]
+#[derive(Debug)]
+struct GlyphAtlas {
+    packer: ShelfPacker,
+    /// One coverage byte per pixel
+    pixels: Vec<u8>,
+    /// Keyed by the bit pattern of the pixel size.  [`None`] for glyphs without an outline, such
+    /// as spaces.
+    glyphs: HashMap<(GlyphId, u32), Option<AtlasGlyph>>,
+    /// Rows that changed since the last upload
+    dirty: Option<(u32, u32)>,
+    /// Set when a glyph did not fit, to start over on the next frame
+    full: bool,
+}
+
+impl GlyphAtlas {
+    fn new() -> Self {
+        Self {
+            packer: ShelfPacker::new(ATLAS_SIZE, ATLAS_SIZE),
+            pixels: vec![0; (ATLAS_SIZE * ATLAS_SIZE) as usize],
+            glyphs: HashMap::new(),
+            dirty: None,
+            full: false,
+        }
+    }
+
+    fn clear(&mut self) {
+        debug!("Glyph atlas is full, evicting {} glyphs", self.glyphs.len());
+        self.packer.clear();
+        self.pixels.fill(0);
+        self.glyphs.clear();
+        self.dirty = Some((0, ATLAS_SIZE));
+        self.full = false;
+    }
+
+    fn get_or_insert(&mut self, font: &FontArc, id: GlyphId, px: f32) -> Option<AtlasGlyph> {
+        if let Some(&glyph) = self.glyphs.get(&(id, px.to_bits())) {
+            return glyph;
+        }
+
+        let glyph = font
+            .outline_glyph(id.with_scale_and_position(px, point(0.0, 0.0)))
+            .and_then(|outline| {
+                let bounds = outline.px_bounds();
+                let size = (bounds.width() as u32, bounds.height() as u32);
+                let Some((x, y)) = self.packer.pack(size.0 + 2 * PADDING, size.1 + 2 * PADDING)
+                else {
+                    self.full = true;
+                    return None;
+                };
+                let origin = (x + PADDING, y + PADDING);
+                outline.draw(|gx, gy, coverage| {
+                    let i = (origin.1 + gy) * ATLAS_SIZE + origin.0 + gx;
+                    self.pixels[i as usize] = (coverage.clamp(0.0, 1.0) * 255.0) as u8;
+                });
+
+                let (start, end) = self.dirty.unwrap_or((origin.1, origin.1));
+                self.dirty = Some((start.min(origin.1), end.max(origin.1 + size.1)));
+                Some(AtlasGlyph {
+                    offset: bounds.min,
+                    origin,
+                    size,
+                })
+            });
+
+        // Don't remember misses from a full atlas, they are retried after clearing it
+        if glyph.is_some() || !self.full {
+            self.glyphs.insert((id, px.to_bits()), glyph);
+        }
+        glyph
+    }
+}
+
+/// Interleaved `vec2 position (pixels), vec2 uv, vec4 color` per vertex
+const FLOATS_PER_VERTEX: usize = 8;
+
+/// Draws batches of strings in a single draw call per frame, see the [module docs][self].
+///
+/// Must only be used with the context that it was created on.
+pub struct TextRenderer {
+    font: FontArc,
+    atlas: GlyphAtlas,
+    vertices: Vec<f32>,
+    // Dropped (and thus queued for deletion) in declaration order
+    program: GlObject,
+    vao: GlObject,
+    vbo: GlObject,
+    texture: GlObject,
+    viewport_location: gl::types::GLint,
+    gl: gl::Gl,
+}
+
+impl TextRenderer {
+    pub fn new(gl: &gl::Gl, deletion_queue: &DeletionQueue, font: FontArc) -> Self {
+        unsafe {
+            let program = create_program(
+                gl,
+                ("shaders/text.vert", VERTEX_SHADER_SOURCE),
+                ("shaders/text.frag", FRAGMENT_SHADER_SOURCE),
+                &[],
+            );
+
+            let viewport_location = gl.GetUniformLocation(program, c"viewport".as_ptr());
+            let texture_location = gl.GetUniformLocation(program, c"atlas".as_ptr());
+            gl.UseProgram(program);
+            gl.Uniform1i(texture_location, 0);
+
+            let mut vao = std::mem::zeroed();
+            gl.GenVertexArrays(1, &mut vao);
+            gl.BindVertexArray(vao);
+
+            let mut vbo = std::mem::zeroed();
+            gl.GenBuffers(1, &mut vbo);
+            gl.BindBuffer(gl::ARRAY_BUFFER, vbo);
+
+            let stride = (FLOATS_PER_VERTEX * std::mem::size_of::<f32>()) as gl::types::GLsizei;
+            for (name, size, offset) in [(c"position", 2, 0), (c"uv", 2, 2), (c"color", 4, 4)] {
+                let attrib = gl.GetAttribLocation(program, name.as_ptr()) as gl::types::GLuint;
+                gl.VertexAttribPointer(
+                    attrib,
+                    size,
+                    gl::FLOAT,
+                    0,
+                    stride,
+                    (offset * std::mem::size_of::<f32>()) as *const _,
+                );
+                gl.EnableVertexAttribArray(attrib);
+            }
+
+            let mut texture = std::mem::zeroed();
+            gl.GenTextures(1, &mut texture);
+            gl.BindTexture(gl::TEXTURE_2D, texture);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
+            gl.TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::R8 as _,
+                ATLAS_SIZE as _,
+                ATLAS_SIZE as _,
+                0,
+                gl::RED,
+                gl::UNSIGNED_BYTE,
+                std::ptr::null(),
+            );
+
+            let mut atlas = GlyphAtlas::new();
+            // The texture starts out undefined
+            atlas.dirty = Some((0, ATLAS_SIZE));
+
+            Self {
+                font,
+                atlas,
+                vertices: Vec::new(),
+                program: deletion_queue.wrap(GlObjectKind::Program, program),
+                vao: deletion_queue.wrap(GlObjectKind::VertexArray, vao),
+                vbo: deletion_queue.wrap(GlObjectKind::Buffer, vbo),
+                texture: deletion_queue.wrap(GlObjectKind::Texture, texture),
+                viewport_location,
+                gl: gl.clone(),
+            }
+        }
+    }
+
+    /// Queues `text` with its top-left corner at `(x, y)` pixels from the top-left of the window.
+    pub fn queue(&mut self, text: &str, (x, y): (f32, f32), px: f32, color: [f32; 4]) {
+        let inv_size = 1.0 / ATLAS_SIZE as f32;
+        for glyph in layout(&self.font, px, text) {
+            let Some(g) = self.atlas.get_or_insert(&self.font, glyph.id, px) else {
+                continue;
+            };
+
+            let x0 = x + glyph.position.x + g.offset.x;
+            let y0 = y + glyph.position.y + g.offset.y;
+            let (x1, y1) = (x0 + g.size.0 as f32, y0 + g.size.1 as f32);
+            let u0 = g.origin.0 as f32 * inv_size;
+            let v0 = g.origin.1 as f32 * inv_size;
+            let u1 = (g.origin.0 + g.size.0) as f32 * inv_size;
+            let v1 = (g.origin.1 + g.size.1) as f32 * inv_size;
+
+            let [r, gr, b, a] = color;
+            for (vx, vy, u, v) in [
+                (x0, y0, u0, v0),
+                (x1, y0, u1, v0),
+                (x0, y1, u0, v1),
+                (x0, y1, u0, v1),
+                (x1, y0, u1, v0),
+                (x1, y1, u1, v1),
+            ] {
+                self.vertices
+                    .extend_from_slice(&[vx, vy, u, v, r, gr, b, a]);
+            }
+        }
+    }
+
+    /// Draws everything queued since the last call on a `width` x `height` viewport, blended
+    /// over the current framebuffer contents.
+    pub fn flush(&mut self, width: i32, height: i32) {
+        let gl = &self.gl;
+        unsafe {
+            gl.ActiveTexture(gl::TEXTURE0);
+            gl.BindTexture(gl::TEXTURE_2D, self.texture.name());
+            if let Some((start, end)) = self.atlas.dirty.take() {
+                gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+                gl.TexSubImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    0,
+                    start as _,
+                    ATLAS_SIZE as _,
+                    (end - start) as _,
+                    gl::RED,
+                    gl::UNSIGNED_BYTE,
+                    self.atlas.pixels[(start * ATLAS_SIZE) as usize..]
+                        .as_ptr()
+                        .cast(),
+                );
+            }
+
+            if !self.vertices.is_empty() {
+                gl.UseProgram(self.program.name());
+                gl.Uniform2f(self.viewport_location, width as f32, height as f32);
+                gl.BindVertexArray(self.vao.name());
+                gl.BindBuffer(gl::ARRAY_BUFFER, self.vbo.name());
+                gl.BufferData(
+                    gl::ARRAY_BUFFER,
+                    (self.vertices.len() * std::mem::size_of::<f32>()) as gl::types::GLsizeiptr,
+                    self.vertices.as_ptr().cast(),
+                    gl::STREAM_DRAW,
+                );
+
+                gl.Enable(gl::BLEND);
+                gl.BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+                gl.DrawArrays(
+                    gl::TRIANGLES,
+                    0,
+                    (self.vertices.len() / FLOATS_PER_VERTEX) as _,
+                );
+                gl.Disable(gl::BLEND);
+            }
+        }
+        self.vertices.clear();
+
+        // Glyphs that did not fit this frame are drawn again after starting over
+        if self.atlas.full {
+            self.atlas.clear();
+        }
+    }
+}
+
+const VERTEX_SHADER_SOURCE: &[u8] = b"
+#version 100
+precision mediump float;
+
+uniform vec2 viewport;
+
+attribute vec2 position;
+attribute vec2 uv;
+attribute vec4 color;
+
+varying vec2 v_uv;
+varying vec4 v_color;
+
+void main() {
+    // Pixels from the top-left to NDC
+    vec2 ndc = position / viewport * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    v_uv = uv;
+    v_color = color;
+}
+\0";
+
+const FRAGMENT_SHADER_SOURCE: &[u8] = b"
+#version 100
+precision mediump float;
+
+uniform sampler2D atlas;
+
+varying vec2 v_uv;
+varying vec4 v_color;
+
+void main() {
+    gl_FragColor = vec4(v_color.rgb, v_color.a * texture2D(atlas, v_uv).r);
+}
+\0";
+
+#[cfg(test)]
+mod tests {
+    use ab_glyph::{v2, CodepointIdIter, Outline};
+
+    use super::*;
+
+    /// Font with round metrics in a 1000 units per em, where every glyph is as wide as `A` except
+    /// for the space, and `AV` is kerned
+    struct StubFont;
+
+    impl Font for StubFont {
+        fn units_per_em(&self) -> Option<f32> {
+            Some(1000.0)
+        }
+
+        fn ascent_unscaled(&self) -> f32 {
+            800.0
+        }
+
+        fn descent_unscaled(&self) -> f32 {
+            -200.0
+        }
+
+        fn line_gap_unscaled(&self) -> f32 {
+            100.0
+        }
+
+        /// The code point for ASCII, and `.notdef` for everything else
+        fn glyph_id(&self, c: char) -> GlyphId {
+            GlyphId(if c.is_ascii() { c as u16 } else { 0 })
+        }
+
+        fn h_advance_unscaled(&self, id: GlyphId) -> f32 {
+            match id {
+                GlyphId(0) => 600.0,
+                GlyphId(0x20) => 250.0,
+                _ => 500.0,
+            }
+        }
+
+        fn h_side_bearing_unscaled(&self, _id: GlyphId) -> f32 {
+            0.0
+        }
+
+        fn v_advance_unscaled(&self, _id: GlyphId) -> f32 {
+            1000.0
+        }
+
+        fn v_side_bearing_unscaled(&self, _id: GlyphId) -> f32 {
+            0.0
+        }
+
+        fn kern_unscaled(&self, first: GlyphId, second: GlyphId) -> f32 {
+            if (first, second) == (self.glyph_id('A'), self.glyph_id('V')) {
+                -100.0
+            } else {
+                0.0
+            }
+        }
+
+        fn outline(&self, _id: GlyphId) -> Option<Outline> {
+            None
+        }
+
+        fn glyph_count(&self) -> usize {
+            128
+        }
+
+        fn codepoint_ids(&self) -> CodepointIdIter<'_> {
+            unimplemented!("Not used by layout()")
+        }
+
+        fn glyph_raster_image2(
+            &self,
+            _id: GlyphId,
+            _pixel_size: u16,
+        ) -> Option<v2::GlyphImage<'_>> {
+            None
+        }
+    }
+
+    /// At this size one font unit is a tenth of a pixel: the ascent is 80, lines are 110 apart,
+    /// glyphs advance by 50 and tabs are 100 wide
+    const PX: f32 = 100.0;
+
+    fn positions(text: &str) -> Vec<(char, f32, f32)> {
+        let glyphs = layout(&StubFont, PX, text);
+        let chars = text.chars().filter(|c| !c.is_control());
+        assert_eq!(glyphs.len(), chars.clone().count());
+        chars
+            .zip(glyphs)
+            .map(|(c, g)| (c, g.position.x, g.position.y))
+            .collect()
+    }
+
+    #[test]
+    fn glyphs_advance_on_the_baseline() {
+        assert_eq!(
+            positions("AB C"),
+            [
+                ('A', 0.0, 80.0),
+                ('B', 50.0, 80.0),
+                (' ', 100.0, 80.0),
+                ('C', 125.0, 80.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn kerning_applies_to_pairs_in_order() {
+        assert_eq!(positions("AV"), [('A', 0.0, 80.0), ('V', 40.0, 80.0)]);
+        assert_eq!(positions("VA"), [('V', 0.0, 80.0), ('A', 50.0, 80.0)]);
+    }
+
+    #[test]
+    fn newlines_start_at_the_left_without_kerning() {
+        assert_eq!(
+            positions("AB\nV\n\nA"),
+            [
+                ('A', 0.0, 80.0),
+                ('B', 50.0, 80.0),
+                ('V', 0.0, 190.0),
+                ('A', 0.0, 410.0)
+            ]
+        );
+        // The pair is not kerned across the line break
+        assert_eq!(positions("A\nV")[1], ('V', 0.0, 190.0));
+    }
+
+    #[test]
+    fn tabs_advance_to_the_next_stop() {
+        assert_eq!(positions("\tA")[0], ('A', 100.0, 80.0));
+        assert_eq!(positions("A\tB")[1], ('B', 100.0, 80.0));
+        // A pen exactly on a stop still advances to the next one
+        assert_eq!(positions("AB\tC")[2], ('C', 200.0, 80.0));
+        // Tabs break kerning pairs too
+        assert_eq!(positions("A\tV")[1], ('V', 100.0, 80.0));
+    }
+
+    #[test]
+    fn other_control_characters_are_skipped() {
+        assert_eq!(positions("A\rB\u{7}C")[2], ('C', 100.0, 80.0));
+    }
+
+    #[test]
+    fn unknown_characters_use_notdef() {
+        let glyphs = layout(&StubFont, PX, "\u{e9}A");
+        assert_eq!(glyphs[0].id, GlyphId(0));
+        assert_eq!(glyphs[1].position.x, 60.0);
+    }
+
+    #[test]
+    fn positions_are_rounded_to_pixels() {
+        // At this size advances are 5.5 pixels
+        let glyphs = layout(&StubFont, 11.0, "AAA");
+        let xs = glyphs.iter().map(|g| g.position.x).collect::<Vec<_>>();
+        assert_eq!(xs, [0.0, 6.0, 11.0]);
+        assert_eq!(glyphs[0].position.y, 9.0);
+    }
+
+    #[test]
+    fn packer_fills_shelves_left_to_right() {
+        let mut packer = ShelfPacker::new(100, 100);
+        assert_eq!(packer.pack(40, 10), Some((0, 0)));
+        assert_eq!(packer.pack(40, 10), Some((40, 0)));
+        // Does not fit next to the others anymore
+        assert_eq!(packer.pack(40, 10), Some((0, 10)));
+        // Taller than every shelf
+        assert_eq!(packer.pack(10, 20), Some((0, 20)));
+        // Shorter glyphs still fit on the first shelf
+        assert_eq!(packer.pack(20, 5), Some((80, 0)));
+    }
+
+    #[test]
+    fn packer_prefers_the_shortest_shelf_that_fits() {
+        let mut packer = ShelfPacker::new(100, 100);
+        packer.pack(50, 30).unwrap();
+        // Too wide for the rest of the first shelf
+        assert_eq!(packer.pack(60, 10), Some((0, 30)));
+        assert_eq!(packer.pack(10, 8), Some((60, 30)));
+        assert_eq!(packer.pack(10, 20), Some((50, 0)));
+    }
+
+    #[test]
+    fn packer_rejects_what_does_not_fit() {
+        let mut packer = ShelfPacker::new(100, 50);
+        assert_eq!(packer.pack(101, 1), None);
+        assert_eq!(packer.pack(100, 40), Some((0, 0)));
+        assert_eq!(packer.pack(10, 11), None);
+        assert_eq!(packer.pack(100, 10), Some((0, 40)));
+
+        packer.clear();
+        assert_eq!(packer.pack(100, 50), Some((0, 0)));
+    }
+
+    #[test]
+    fn packed_rectangles_never_overlap() {
+        let (width, height) = (64, 64);
+        let mut packer = ShelfPacker::new(width, height);
+        let mut packed = Vec::new();
+        // Deterministic mix of glyph-like sizes
+        for i in 0..200u32 {
+            let size = (3 + i * 7 % 11, 4 + i * 5 % 9);
+            if let Some(origin) = packer.pack(size.0, size.1) {
+                packed.push((origin, size));
+            }
+        }
+        assert!(packed.len() > 20, "Only packed {}", packed.len());
+
+        for (i, &((x, y), (w, h))) in packed.iter().enumerate() {
+            assert!(x + w <= width && y + h <= height);
+            for &((ox, oy), (ow, oh)) in &packed[..i] {
+                let disjoint = x + w <= ox || ox + ow <= x || y + h <= oy || oy + oh <= y;
+                assert!(
+                    disjoint,
+                    "{:?} overlaps {:?}",
+                    ((x, y), (w, h)),
+                    ((ox, oy), (ow, oh))
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! Async trace sections and counters, on top of the synchronous [`ndk::trace::Section`]s.
+//!
+//! A [`Section`][ndk::trace::Section] must end on the thread and in the scope that began it, so
+//! it cannot show work that overlaps across threads or frames.  An [`AsyncSection`] is keyed by a
+//! cookie instead, typically a [`next_frame_id()`], and may end anywhere.  [`set_counter()`]
+//! tracks values such as the fps or queue depths over time.
+//!
+//! Both were introduced in API 29, while this crate only requires API 28.  Like
+//! [`crate::frame_rate`], the `ATrace_*()` functions are looked up in `libandroid.so` on first use,
+//! falling back to a no-op [`Backend`] on older devices and on the host.  Tests install a
+//! `Recorder` with `set_backend()` instead, to assert which events were emitted.
+//!
+//! Without the `trace` feature, everything in this module compiles to nothing.
+
+pub use imp::*;
+
+#[cfg(target_os = "android")]
+pub use ndk::trace::Section;
+
+/// Stand-in for [`ndk::trace::Section`] on the host, which has no `ATrace`
+#[cfg(not(target_os = "android"))]
+#[derive(Debug)]
+pub struct Section;
+
+#[cfg(not(target_os = "android"))]
+impl Section {
+    pub fn new(_name: &str) -> Result<Self, std::ffi::NulError> {
+        Ok(Self)
+    }
+}
+
+#[cfg(feature = "trace")]
+mod imp {
+    #[cfg(test)]
+    use std::sync::Mutex;
+    use std::{
+        ffi::{c_char, CStr, CString},
+        sync::{
+            atomic::{AtomicI32, Ordering},
+            OnceLock,
+        },
+    };
+
+    #[cfg(target_os = "android")]
+    use libloading::Library;
+    #[cfg(target_os = "android")]
+    use log::warn;
+
+    /// Receiver of all trace events
+    pub trait Backend: Send + Sync {
+        /// Whether events are currently recorded, to skip formatting them otherwise
+        fn is_enabled(&self) -> bool;
+        fn begin_async_section(&self, name: &CStr, cookie: i32);
+        fn end_async_section(&self, name: &CStr, cookie: i32);
+        fn set_counter(&self, name: &CStr, value: i64);
+    }
+
+    type AsyncSectionFn = unsafe extern "C" fn(section_name: *const c_char, cookie: i32);
+    type SetCounterFn = unsafe extern "C" fn(counter_name: *const c_char, counter_value: i64);
+
+    /// The `ATrace_*()` functions from API 29
+    #[cfg(target_os = "android")]
+    struct ATrace {
+        begin_async_section: AsyncSectionFn,
+        end_async_section: AsyncSectionFn,
+        set_counter: SetCounterFn,
+        /// Keeps the function pointers above alive
+        _lib: Library,
+    }
+
+    #[cfg(target_os = "android")]
+    impl ATrace {
+        fn load() -> Option<Self> {
+            // SAFETY: libandroid.so is already loaded by virtue of linking against the NDK, this
+            // only increments its refcount and doesn't run any (new) initializers.
+            let lib = unsafe { Library::new("libandroid.so") }
+                .inspect_err(|e| warn!("Failed to open libandroid.so: {e}"))
+                .ok()?;
+            // SAFETY: The signatures match the NDK headers.
+            unsafe {
+                Some(Self {
+                    begin_async_section: *lib
+                        .get::<AsyncSectionFn>(b"ATrace_beginAsyncSection\0")
+                        .ok()?,
+                    end_async_section: *lib
+                        .get::<AsyncSectionFn>(b"ATrace_endAsyncSection\0")
+                        .ok()?,
+                    set_counter: *lib.get::<SetCounterFn>(b"ATrace_setCounter\0").ok()?,
+                    _lib: lib,
+                })
+            }
+        }
+    }
+
+    #[cfg(target_os = "android")]
+    impl Backend for ATrace {
+        fn is_enabled(&self) -> bool {
+            ndk::trace::is_trace_enabled()
+        }
+
+        fn begin_async_section(&self, name: &CStr, cookie: i32) {
+            unsafe { (self.begin_async_section)(name.as_ptr(), cookie) }
+        }
+
+        fn end_async_section(&self, name: &CStr, cookie: i32) {
+            unsafe { (self.end_async_section)(name.as_ptr(), cookie) }
+        }
+
+        fn set_counter(&self, name: &CStr, value: i64) {
+            unsafe { (self.set_counter)(name.as_ptr(), value) }
+        }
+    }
+
+    /// Drops all events, for devices below API 29 and the host
+    struct Noop;
+
+    impl Backend for Noop {
+        fn is_enabled(&self) -> bool {
+            false
+        }
+
+        fn begin_async_section(&self, _name: &CStr, _cookie: i32) {}
+
+        fn end_async_section(&self, _name: &CStr, _cookie: i32) {}
+
+        fn set_counter(&self, _name: &CStr, _value: i64) {}
+    }
+
+    /// Event that was emitted to a [`Recorder`]
+    #[cfg(test)]
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum Event {
+        BeginAsyncSection { name: String, cookie: i32 },
+        EndAsyncSection { name: String, cookie: i32 },
+        Counter { name: String, value: i64 },
+    }
+
+    /// Keeps all events in memory instead of emitting them, for host tests
+    #[cfg(test)]
+    #[derive(Debug, Default)]
+    pub struct Recorder {
+        events: Mutex<Vec<Event>>,
+    }
+
+    #[cfg(test)]
+    impl Recorder {
+        /// Returns and clears the events that were emitted so far, oldest first
+        pub fn take_events(&self) -> Vec<Event> {
+            std::mem::take(&mut *self.events.lock().unwrap())
+        }
+
+        fn push(&self, event: Event) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    #[cfg(test)]
+    impl Backend for Recorder {
+        fn is_enabled(&self) -> bool {
+            true
+        }
+
+        fn begin_async_section(&self, name: &CStr, cookie: i32) {
+            self.push(Event::BeginAsyncSection {
+                name: name.to_string_lossy().into_owned(),
+                cookie,
+            });
+        }
+
+        fn end_async_section(&self, name: &CStr, cookie: i32) {
+            self.push(Event::EndAsyncSection {
+                name: name.to_string_lossy().into_owned(),
+                cookie,
+            });
+        }
+
+        fn set_counter(&self, name: &CStr, value: i64) {
+            self.push(Event::Counter {
+                name: name.to_string_lossy().into_owned(),
+                value,
+            });
+        }
+    }
+
+    static BACKEND: OnceLock<&'static dyn Backend> = OnceLock::new();
+
+    fn backend() -> &'static dyn Backend {
+        *BACKEND.get_or_init(|| {
+            #[cfg(target_os = "android")]
+            if let Some(atrace) = ATrace::load() {
+                return Box::leak(Box::new(atrace));
+            }
+            &Noop
+        })
+    }
+
+    /// Replaces the `ATrace` backend, which is only possible before the first event is emitted.
+    /// Returns `backend` back if that already happened.
+    #[cfg(test)]
+    pub fn set_backend(backend: &'static dyn Backend) -> Result<(), &'static dyn Backend> {
+        BACKEND.set(backend)
+    }
+
+    /// Process-wide unique and increasing frame id, to key [`AsyncSection`]s of a frame by
+    pub fn next_frame_id() -> i32 {
+        static FRAME_ID: AtomicI32 = AtomicI32::new(0);
+        FRAME_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Section that may end on another thread or after the scope that began it, on [`Drop`].
+    /// Sections with the same name must use distinct cookies while they overlap.
+    #[derive(Debug)]
+    #[must_use = "The section ends when dropped"]
+    pub struct AsyncSection {
+        /// [`None`] when tracing was disabled at the start
+        name: Option<CString>,
+        cookie: i32,
+    }
+
+    impl AsyncSection {
+        pub fn begin(name: &str, cookie: i32) -> Self {
+            let backend = backend();
+            let name = backend.is_enabled().then(|| {
+                let name = CString::new(name).unwrap();
+                backend.begin_async_section(&name, cookie);
+                name
+            });
+            Self { name, cookie }
+        }
+    }
+
+    impl Drop for AsyncSection {
+        fn drop(&mut self) {
+            if let Some(name) = &self.name {
+                backend().end_async_section(name, self.cookie);
+            }
+        }
+    }
+
+    /// Sets the value of the counter `name` in the trace.
+    pub fn set_counter(name: &str, value: i64) {
+        let backend = backend();
+        if backend.is_enabled() {
+            backend.set_counter(&CString::new(name).unwrap(), value);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::{sync::MutexGuard, thread};
+
+        use super::*;
+
+        /// Installed for all tests of this process, as the backend can only be set once
+        static RECORDER: Recorder = Recorder {
+            events: Mutex::new(Vec::new()),
+        };
+
+        /// Installs [`RECORDER`] and clears its events.  The tests hold the returned guard, so
+        /// that they don't see each other's events.
+        fn recorder() -> MutexGuard<'static, ()> {
+            static LOCK: Mutex<()> = Mutex::new(());
+            let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = set_backend(&RECORDER);
+            assert!(
+                std::ptr::addr_eq(backend(), &RECORDER),
+                "An event was emitted before the recorder was installed"
+            );
+            RECORDER.take_events();
+            guard
+        }
+
+        fn begin(name: &str, cookie: i32) -> Event {
+            Event::BeginAsyncSection {
+                name: name.to_owned(),
+                cookie,
+            }
+        }
+
+        fn end(name: &str, cookie: i32) -> Event {
+            Event::EndAsyncSection {
+                name: name.to_owned(),
+                cookie,
+            }
+        }
+
+        #[test]
+        fn async_sections_end_on_drop() {
+            let _recorder = recorder();
+            let first = AsyncSection::begin("frame", 1);
+            let second = AsyncSection::begin("frame", 2);
+            assert_eq!(
+                RECORDER.take_events(),
+                [begin("frame", 1), begin("frame", 2)]
+            );
+
+            // Overlapping sections end in any order, each with its own cookie
+            drop(first);
+            drop(second);
+            assert_eq!(RECORDER.take_events(), [end("frame", 1), end("frame", 2)]);
+        }
+
+        #[test]
+        fn async_sections_end_on_another_thread() {
+            let _recorder = recorder();
+            let section = AsyncSection::begin("GPU frame", 7);
+            thread::spawn(move || drop(section)).join().unwrap();
+            assert_eq!(
+                RECORDER.take_events(),
+                [begin("GPU frame", 7), end("GPU frame", 7)]
+            );
+        }
+
+        #[test]
+        fn counters_are_emitted_in_order() {
+            let _recorder = recorder();
+            set_counter("post passes", 2);
+            set_counter("post passes", 0);
+            set_counter("GL deletion queue", -1);
+            let counter = |name: &str, value| Event::Counter {
+                name: name.to_owned(),
+                value,
+            };
+            assert_eq!(
+                RECORDER.take_events(),
+                [
+                    counter("post passes", 2),
+                    counter("post passes", 0),
+                    counter("GL deletion queue", -1),
+                ]
+            );
+        }
+
+        #[test]
+        fn frame_ids_increase() {
+            let first = next_frame_id();
+            let second = next_frame_id();
+            assert!(second > first);
+        }
+    }
+}
+
+#[cfg(not(feature = "trace"))]
+mod imp {
+    #[inline(always)]
+    pub fn next_frame_id() -> i32 {
+        0
+    }
+
+    #[derive(Debug)]
+    #[must_use = "The section ends when dropped"]
+    pub struct AsyncSection;
+
+    impl AsyncSection {
+        #[inline(always)]
+        pub fn begin(_name: &str, _cookie: i32) -> Self {
+            Self
+        }
+    }
+
+    #[inline(always)]
+    pub fn set_counter(_name: &str, _value: i64) {}
+}
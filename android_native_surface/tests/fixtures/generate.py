@@ -0,0 +1,161 @@
+#!/usr/bin/env python3
+"""Writes the tiny images that src/support/texture.rs decodes in its tests.
+
+Every image is a flat color (or a few known pixels), so that the expected values can be written
+down by hand.  Run from this directory; only the standard library is needed.
+"""
+
+import struct
+import zlib
+
+
+def png(name, width, height, color_type, rows):
+    def chunk(kind, data):
+        body = kind + data
+        return struct.pack(">I", len(data)) + body + struct.pack(">I", zlib.crc32(body))
+
+    ihdr = struct.pack(">IIBBBBB", width, height, 8, color_type, 0, 0, 0)
+    raw = b"".join(b"\0" + bytes(row) for row in rows)
+    data = (
+        b"\x89PNG\r\n\x1a\n"
+        + chunk(b"IHDR", ihdr)
+        + chunk(b"IDAT", zlib.compress(raw))
+        + chunk(b"IEND", b"")
+    )
+    open(name, "wb").write(data)
+
+
+def huffman_codes(bits, values):
+    """Canonical Huffman codes as {value: (code, length)}, from a JPEG DHT table."""
+    codes, code, i = {}, 0, 0
+    for length, count in enumerate(bits, 1):
+        for _ in range(count):
+            codes[values[i]] = (code, length)
+            code += 1
+            i += 1
+        code <<= 1
+    return codes
+
+
+def jpeg(name, width, height, ycbcr):
+    """Baseline 4:4:4 JPEG of a single flat color, which only needs DC coefficients."""
+    dc_bits = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0]
+    dc_values = list(range(12))
+    # End of block is the only AC symbol
+    ac_bits = [1] + [0] * 15
+    ac_values = [0x00]
+    dc_codes = huffman_codes(dc_bits, dc_values)
+    eob = huffman_codes(ac_bits, ac_values)[0x00]
+
+    def segment(marker, data):
+        return struct.pack(">BBH", 0xFF, marker, len(data) + 2) + data
+
+    out = b"\xff\xd8"
+    out += segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0")
+    # All quantizers are 1, so that coefficients are exact
+    out += segment(0xDB, b"\0" + b"\x01" * 64)
+    out += segment(
+        0xC0,
+        struct.pack(">BHHB", 8, height, width, 3)
+        + b"".join(struct.pack(">BBB", i + 1, 0x11, 0) for i in range(3)),
+    )
+    out += segment(0xC4, b"\x00" + bytes(dc_bits) + bytes(dc_values))
+    out += segment(0xC4, b"\x10" + bytes(ac_bits) + bytes(ac_values))
+    out += segment(0xDA, b"\x03" + b"".join(bytes([i + 1, 0x00]) for i in range(3)) + b"\x00\x3f\x00")
+
+    bits = []
+
+    def put(code, length):
+        bits.extend((code >> (length - 1 - i)) & 1 for i in range(length))
+
+    blocks_x, blocks_y = (width + 7) // 8, (height + 7) // 8
+    previous = [0, 0, 0]
+    for _ in range(blocks_x * blocks_y):
+        for component, value in enumerate(ycbcr):
+            # The DC coefficient of a flat 8x8 block is 8 times its level-shifted value
+            dc = 8 * (value - 128)
+            diff = dc - previous[component]
+            previous[component] = dc
+            category = abs(diff).bit_length()
+            put(*dc_codes[category])
+            if category:
+                put(diff if diff > 0 else diff + (1 << category) - 1, category)
+            put(*eob)
+    bits.extend([1] * (-len(bits) % 8))
+    scan = bytes(int("".join(map(str, bits[i : i + 8])), 2) for i in range(0, len(bits), 8))
+    out += scan.replace(b"\xff", b"\xff\x00")
+    out += b"\xff\xd9"
+    open(name, "wb").write(out)
+
+
+def ktx2(name, vk_format, width, height, levels):
+    """KTX2 without supercompression or key/value data, with an empty data format descriptor."""
+    level_index_len = 24 * len(levels)
+    dfd_offset = 80 + level_index_len
+    dfd = struct.pack("<I", 4)
+    offset = dfd_offset + len(dfd)
+    data = b""
+    index = b""
+    for level in levels:
+        # Level data is aligned to 16 bytes, which satisfies every block size used here
+        padding = -(offset + len(data)) % 16
+        data += b"\0" * padding
+        index += struct.pack("<QQQ", offset + len(data), len(level), len(level))
+        data += level
+    header = b"\xabKTX 20\xbb\r\n\x1a\n" + struct.pack(
+        "<9I4I2Q",
+        vk_format,
+        1,
+        width,
+        height,
+        0,
+        0,
+        1,
+        len(levels),
+        0,
+        dfd_offset,
+        len(dfd),
+        0,
+        0,
+        0,
+        0,
+    )
+    open(name, "wb").write(header + index + dfd + data)
+
+
+def etc1_block(r, g, b, table=0):
+    """ETC1/ETC2 block in individual mode with 4-bit colors, where every pixel uses modifier
+    index 0, i.e. +2 for table 0."""
+    return bytes([r << 4 | r, g << 4 | g, b << 4 | b, table << 5 | table << 2, 0, 0, 0, 0])
+
+
+def eac_alpha_block(base, multiplier, table=0):
+    """EAC alpha block where every pixel uses modifier index 0, i.e. -3 for table 0."""
+    return bytes([base, multiplier << 4 | table]) + b"\0" * 6
+
+
+def astc_void_extent(r, g, b, a):
+    """ASTC block of a single LDR color, given as 16-bit UNORM."""
+    return struct.pack("<Q4H", 0xFFFF_FFFF_FFFF_FDFC, r, g, b, a)
+
+
+R8G8B8A8_SRGB = 43
+ETC2_R8G8B8_UNORM_BLOCK = 147
+ETC2_R8G8B8A8_SRGB_BLOCK = 152
+ASTC_4X4_UNORM_BLOCK = 157
+
+png("rgba.png", 2, 2, 6, [[255, 0, 0, 255, 0, 255, 0, 128], [0, 0, 255, 0, 255, 255, 255, 255]])
+png("gray.png", 2, 1, 0, [[0, 200]])
+# (200, 100, 50) in RGB, rounded
+jpeg("flat.jpg", 5, 3, (124, 86, 182))
+ktx2("rgba8.ktx2", R8G8B8A8_SRGB, 2, 2, [bytes(range(16)), bytes([16, 17, 18, 19])])
+# 6x6 needs 2x2 blocks, of which the right and bottom ones are cut off
+ktx2("etc2.ktx2", ETC2_R8G8B8_UNORM_BLOCK, 6, 6, [etc1_block(0xC, 0x6, 0x3) * 4])
+ktx2(
+    "etc2_eac.ktx2",
+    ETC2_R8G8B8A8_SRGB_BLOCK,
+    4,
+    4,
+    [eac_alpha_block(128, 1) + etc1_block(0xC, 0x6, 0x3)],
+)
+ktx2("astc.ktx2", ASTC_4X4_UNORM_BLOCK, 4, 4, [astc_void_extent(200 * 257, 100 * 257, 50 * 257, 0xFFFF)])
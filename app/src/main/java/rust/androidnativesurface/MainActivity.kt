@@ -1,8 +1,15 @@
 package rust.androidnativesurface
 
 import android.app.Activity
+import android.content.pm.ApplicationInfo
+import android.content.res.AssetManager
+import android.graphics.Bitmap
+import android.graphics.Color
+import android.graphics.Matrix
+import android.graphics.RectF
 import android.graphics.SurfaceTexture
 import android.os.Bundle
+import android.view.MotionEvent
 import android.view.Surface
 import android.view.SurfaceHolder
 import android.view.SurfaceView
@@ -17,46 +24,430 @@ class MainActivity : Activity() {
         }
 
         private external fun init()
+
+        /**
+         * Replaces the native log filter with an env_logger-style spec such as
+         * `android_native_surface=info,jni=warn`. The initial spec is read from the
+         * `android_native_surface.log_filter` system property.
+         */
+        external fun setLogFilter(spec: String)
+
+        /** Restores the original stdout and stderr of the process, which `init()` redirects into logcat */
+        external fun stopStdioCapture()
+
+        /**
+         * Loads shaders and textures from [assetManager], or from the directory in the
+         * `android_native_surface.asset_dir` system property if it is set.  Must be called before
+         * the first frame is rendered, shaders fall back to their built-in sources otherwise.
+         */
+        external fun initAssets(assetManager: AssetManager)
     }
 
-    class NativeGL {
-        private val mNative: Long = 0 // TODO: var?
-        private external fun init(self: NativeGL)
+    /** Must remain in sync with `Backend` in Rust */
+    enum class Backend {
+        /** Render through EGL and OpenGL ES */
+        GL,
 
-        init {
-            init(this)
+        /** Rasterize on the CPU and post through ANativeWindow_lock/unlockAndPost */
+        SOFTWARE,
+    }
+
+    /** Must remain in sync with `Recovery` in Rust */
+    enum class Recovery {
+        /** The EGL context was lost and recreated, together with all GL resources */
+        RECREATE_CONTEXT,
+
+        /** The EGL surface became invalid and was recreated on the same window */
+        RECREATE_SURFACE,
+
+        /** The native window is dead and was dropped, the wrapper no longer has a surface */
+        DROP_WINDOW,
+    }
+
+    /**
+     * Full-screen pass that runs over a surface after it was drawn, see
+     * [NativeWindowWrapper.postPasses].  The order of [kind]s must match `post::PassKind`.
+     */
+    sealed class PostPass(internal val kind: Int) {
+        internal open val value = 0f
+        internal open val texture = 0L
+
+        /** Blurs with taps [radius] pixels apart */
+        data class Blur(val radius: Float) : PostPass(0) {
+            override val value get() = radius
+        }
+
+        /** Darkens towards the corners, by [strength] at the corners themselves */
+        data class Vignette(val strength: Float) : PostPass(1) {
+            override val value get() = strength
+        }
+
+        /** Maps HDR colors into `0..1` after multiplying them by [exposure] */
+        data class ToneMap(val exposure: Float) : PostPass(2) {
+            override val value get() = exposure
         }
 
-        // TODO: Add a destructor
+        /**
+         * Maps colors through a 16x16x16 [lut] from [NativeGL.uploadBitmap], laid out as a 256x16
+         * bitmap of 16 blue slices with red increasing to the right and green downwards
+         */
+        data class ColorLut(val lut: Long) : PostPass(3) {
+            override val texture get() = lut
+        }
     }
 
-    open class NativeSurfaceWrapper(private val gl: NativeGL) {
-        private var mNative: Long = 0
+    /**
+     * @param debug Create debug contexts, on which the driver validates more and reports more
+     * through `KHR_debug` into logcat, at a cost
+     * @throws RuntimeException when the device provides no GLES 3.0 context
+     */
+    class NativeGL(debug: Boolean = false) {
+        private external fun init(debug: Boolean): Long
+        private external fun injectFault(native: Long, recovery: Int)
+        private external fun destroy(native: Long): Boolean
+        private external fun uploadBitmap(native: Long, bitmap: Bitmap): Long
+        private external fun renderToBitmap(native: Long, bitmap: Bitmap)
+        private external fun verifySrgb(native: Long)
+        private external fun getCapabilities(native: Long): Map<String, String>
+        private external fun loadTexture(native: Long, path: String): Long
+
+        /** Releases a texture returned by [uploadBitmap] or [loadTexture] */
+        external fun deleteTexture(texture: Long)
+
+        /** Opaque handle to the native object, `0` after [destroy] */
+        var mNative: Long = init(debug)
+            private set
+
+        /** Make the next buffer swap fail with an error that is handled by [recovery] */
+        fun injectFault(recovery: Recovery) {
+            injectFault(mNative, recovery.ordinal)
+        }
+
+        /**
+         * Uploads an `ARGB_8888`, `RGB_565` or `RGBA_F16` [bitmap] to a texture, returning its
+         * handle.  The texture must be uploaded again after the context was lost.
+         */
+        fun uploadBitmap(bitmap: Bitmap): Long = uploadBitmap(mNative, bitmap)
+
+        /**
+         * Decodes the PNG, JPEG or KTX2 asset at [path] into a texture, returning its handle.  The
+         * texture must be loaded again after the context was lost.
+         */
+        fun loadTexture(path: String): Long = loadTexture(mNative, path)
+
+        /**
+         * GL and EGL versions, limits and extensions of the device, keyed by `gl.*` and `egl.*`
+         * names that stay stable across releases
+         */
+        fun capabilities(): Map<String, String> = getCapabilities(mNative)
+
+        /** Renders the scene into a mutable `ARGB_8888`, `RGB_565` or `RGBA_F16` [bitmap] */
+        fun renderToBitmap(bitmap: Bitmap) {
+            renderToBitmap(mNative, bitmap)
+        }
 
-        private external fun setSurface(gl: NativeGL, self: NativeSurfaceWrapper, surface: Surface)
-        private external fun removeSurface(self: NativeSurfaceWrapper)
-        private external fun renderToSurface(gl: NativeGL, self: NativeSurfaceWrapper)
+        /**
+         * Draws a test scene of gradient ramps into an offscreen sRGB framebuffer, and throws a
+         * [RuntimeException] if the pixels read back are not blended in linear space
+         */
+        fun verifySrgb() {
+            verifySrgb(mNative)
+        }
 
-        fun setSurface(surface: Surface) {
+        /**
+         * Releases all native GL and EGL state.  Every surface wrapper that uses this instance must
+         * have had its surface removed first.
+         */
+        fun destroy() {
+            check(destroy(mNative)) { "NativeGL still has live surfaces" }
+            mNative = 0
+        }
+    }
+
+    /**
+     * Retained 2D scene of colored rects and textured sprites, in pixels from the top-left of the
+     * surface, that is drawn by every GL surface wrapper it is attached to.  Nodes are drawn in
+     * increasing [z] order, ties in the order they were added.
+     */
+    class Scene {
+        private external fun create(): Long
+        private external fun destroy(native: Long)
+        private external fun addRect(
+            native: Long, x: Float, y: Float, width: Float, height: Float, z: Int, color: Int
+        ): Long
+
+        private external fun addSprite(
+            native: Long,
+            texture: Long,
+            x: Float,
+            y: Float,
+            width: Float,
+            height: Float,
+            z: Int,
+            tint: Int
+        ): Long
+
+        private external fun setTransform(
+            native: Long,
+            node: Long,
+            scaleX: Float,
+            skewX: Float,
+            translateX: Float,
+            skewY: Float,
+            scaleY: Float,
+            translateY: Float
+        )
+
+        private external fun setClip(
+            native: Long, node: Long, x: Float, y: Float, width: Float, height: Float
+        )
+
+        private external fun clearClip(native: Long, node: Long)
+        private external fun remove(native: Long, node: Long)
+        private external fun setShowPointers(native: Long, show: Boolean)
+
+        /** Opaque handle to the native scene, `0` after [destroy] */
+        var mNative: Long = create()
+            private set
+
+        /**
+         * Draw a marker for every pointer that was forwarded with `onMotionEvent()`, sized by its
+         * pressure and rotated by its orientation and tilt, with a trail of recent samples
+         */
+        var showPointers = false
+            set(show) {
+                field = show
+                setShowPointers(mNative, show)
+            }
+
+        /** Adds a rect filled with an ARGB [color], returning its node id */
+        fun addRect(rect: RectF, color: Int, z: Int = 0): Long =
+            addRect(mNative, rect.left, rect.top, rect.width(), rect.height(), z, color)
+
+        /**
+         * Adds a rect showing a [texture] from [NativeGL.uploadBitmap] multiplied by an ARGB
+         * [tint], returning its node id.  The sprite is skipped once the texture is deleted.
+         */
+        fun addSprite(texture: Long, rect: RectF, z: Int = 0, tint: Int = Color.WHITE): Long =
+            addSprite(mNative, texture, rect.left, rect.top, rect.width(), rect.height(), z, tint)
+
+        /** Transforms the rect of [node] by an affine [matrix], whose perspective is ignored */
+        fun setTransform(node: Long, matrix: Matrix) {
+            val v = FloatArray(9).also { matrix.getValues(it) }
+            setTransform(
+                mNative,
+                node,
+                v[Matrix.MSCALE_X],
+                v[Matrix.MSKEW_X],
+                v[Matrix.MTRANS_X],
+                v[Matrix.MSKEW_Y],
+                v[Matrix.MSCALE_Y],
+                v[Matrix.MTRANS_Y]
+            )
+        }
+
+        /** Only draws the part of [node] within [clip], which is not transformed */
+        fun setClip(node: Long, clip: RectF?) {
+            if (clip == null) {
+                clearClip(mNative, node)
+            } else {
+                setClip(mNative, node, clip.left, clip.top, clip.width(), clip.height())
+            }
+        }
+
+        fun remove(node: Long) {
+            remove(mNative, node)
+        }
+
+        /** Releases the scene, which is then no longer drawn by any wrapper */
+        fun destroy() {
+            destroy(mNative)
+            mNative = 0
+        }
+    }
+
+    /**
+     * Native window that a surface wrapper renders to, with the settings that apply to any window.
+     * Settings are kept while there is no window, and applied to every window that is [attach]ed.
+     */
+    abstract class NativeWindowWrapper(protected val gl: NativeGL) {
+        /** Opaque handle to the native window, `0` when there is no surface */
+        protected var mNative: Long = 0
+            private set
+
+        private external fun remove(native: Long)
+        private external fun render(gl: Long, native: Long)
+        private external fun setFrameRate(
+            native: Long,
+            frameRate: Float,
+            compatibility: Int,
+            changeFrameRateStrategy: Int
+        )
+
+        private external fun setHudEnabled(native: Long, enabled: Boolean)
+        private external fun setMsaaSamples(native: Long, samples: Int)
+        private external fun setSrgb(gl: Long, native: Long, srgb: Boolean)
+        private external fun setPostPasses(
+            native: Long, kinds: IntArray, values: FloatArray, textures: LongArray
+        )
+
+        private fun setPostPasses(native: Long, passes: List<PostPass>) {
+            setPostPasses(
+                native,
+                passes.map { it.kind }.toIntArray(),
+                passes.map { it.value }.toFloatArray(),
+                passes.map { it.texture }.toLongArray()
+            )
+        }
+        private external fun setScene(native: Long, scene: Long)
+        private external fun onMotionEvent(
+            native: Long, event: MotionEvent, viewWidth: Int, viewHeight: Int
+        ): Boolean
+
+        /** Draw the debug HUD over this surface, only supported with [Backend.GL] */
+        var hudEnabled = false
+            set(enabled) {
+                field = enabled
+                if (mNative != 0L) {
+                    setHudEnabled(mNative, enabled)
+                }
+            }
+
+        /**
+         * Draw with this many samples per pixel, clamped to what the device supports, or without
+         * MSAA for `0` and `1`.  Only supported with [Backend.GL]
+         */
+        var msaaSamples = 0
+            set(samples) {
+                field = samples
+                if (mNative != 0L) {
+                    setMsaaSamples(mNative, samples)
+                }
+            }
+
+        /**
+         * Write to the surface in the sRGB colorspace, so that blending and gradients happen on
+         * linear colors.  Falls back to linear when the device lacks `EGL_KHR_gl_colorspace`.
+         * Only supported with [Backend.GL]
+         */
+        var srgb = false
+            set(srgb) {
+                field = srgb
+                if (mNative != 0L) {
+                    setSrgb(gl.mNative, mNative, srgb)
+                }
+            }
+
+        /**
+         * Run over the surface in order after it was drawn, below the HUD.  Only supported with
+         * [Backend.GL]
+         */
+        var postPasses = emptyList<PostPass>()
+            set(passes) {
+                field = passes
+                if (mNative != 0L) {
+                    setPostPasses(mNative, passes)
+                }
+            }
+
+        /** Drawn on every [redraw], only supported with [Backend.GL] */
+        var scene: Scene? = null
+            set(scene) {
+                field = scene
+                if (mNative != 0L) {
+                    setScene(mNative, scene?.mNative ?: 0)
+                }
+            }
+
+        /** Takes ownership of the window handle [native], and applies the settings to it */
+        protected fun attach(native: Long) {
             assert(mNative == 0L)
-            setSurface(gl, this, surface)
-            assert(mNative != 0L)
+            mNative = native
+            if (hudEnabled) {
+                setHudEnabled(mNative, true)
+            }
+            if (msaaSamples > 1) {
+                setMsaaSamples(mNative, msaaSamples)
+            }
+            if (srgb) {
+                setSrgb(gl.mNative, mNative, true)
+            }
+            if (postPasses.isNotEmpty()) {
+                setPostPasses(mNative, postPasses)
+            }
+            scene?.let { setScene(mNative, it.mNative) }
+        }
+
+        /**
+         * Releases the window, unless it was dropped after an unrecoverable failure or was already
+         * released
+         */
+        fun detach() {
+            if (mNative != 0L) {
+                remove(mNative)
+                mNative = 0
+            }
         }
 
+        /** Throws a [RuntimeException] for EGL errors that no [Recovery] applies to */
         fun redraw() {
+            if (mNative == 0L) {
+                // Dropped after an unrecoverable failure
+                return
+            }
+            render(gl.mNative, mNative)
+        }
+
+        /**
+         * Forwards [event] to the [scene], mapping its coordinates from a view of [viewWidth] x
+         * [viewHeight] to the buffer of the surface.  Returns whether a scene received it.
+         */
+        fun onMotionEvent(event: MotionEvent, viewWidth: Int, viewHeight: Int): Boolean {
+            if (mNative == 0L) {
+                return false
+            }
+            return onMotionEvent(mNative, event, viewWidth, viewHeight)
+        }
+
+        /** Called from native code after recovering from a failed render */
+        @Suppress("unused")
+        protected fun onRecovery(recovery: Int) {
+            println("Recovered $this with ${Recovery.entries[recovery]}")
+            if (Recovery.entries[recovery] == Recovery.DROP_WINDOW) {
+                mNative = 0
+            }
+        }
+
+        /**
+         * Takes [Surface].FRAME_RATE_COMPATIBILITY_* and [Surface].CHANGE_FRAME_RATE_* values,
+         * which are only ignored (not rejected) below API 30 and 31 respectively.  Values that are
+         * unknown to the native side are ignored too, in favor of the defaults.
+         */
+        fun setFrameRate(
+            frameRate: Float,
+            compatibility: Int = 0, // FRAME_RATE_COMPATIBILITY_DEFAULT
+            changeFrameRateStrategy: Int = 0 // CHANGE_FRAME_RATE_ONLY_IF_SEAMLESS
+        ) {
             assert(mNative != 0L)
-            renderToSurface(gl, this)
+            setFrameRate(mNative, frameRate, compatibility, changeFrameRateStrategy)
+        }
+    }
 
+    open class NativeSurfaceWrapper(gl: NativeGL, private val backend: Backend = Backend.GL) :
+        NativeWindowWrapper(gl) {
+        private external fun setSurface(gl: Long, surface: Surface, backend: Int): Long
+
+        fun setSurface(surface: Surface) {
+            attach(setSurface(gl.mNative, surface, backend.ordinal))
         }
 
         fun removeSurface() {
-            assert(mNative != 0L)
-            removeSurface(this)
-            assert(mNative == 0L)
+            detach()
         }
     }
 
-    class SurfaceHolderWrapper(private val gl: NativeGL) : NativeSurfaceWrapper(gl),
+    class SurfaceHolderWrapper(gl: NativeGL, backend: Backend = Backend.GL) :
+        NativeSurfaceWrapper(gl, backend),
         SurfaceHolder.Callback {
         override fun surfaceCreated(holder: SurfaceHolder) {
             println("SurfaceView created: ${holder.surface}")
@@ -74,7 +465,8 @@ class MainActivity : Activity() {
         }
     }
 
-    class SurfaceTextureWrapper(private val gl: NativeGL) : NativeSurfaceWrapper(gl),
+    class SurfaceTextureWrapper(gl: NativeGL, backend: Backend = Backend.GL) :
+        NativeSurfaceWrapper(gl, backend),
         TextureView.SurfaceTextureListener {
 
         override fun onSurfaceTextureAvailable(
@@ -107,41 +499,32 @@ class MainActivity : Activity() {
         }
     }
 
-    class NativeSurfaceTextureWrapper(private val gl: NativeGL) :
+    class NativeSurfaceTextureWrapper(gl: NativeGL, private val backend: Backend = Backend.GL) :
+        NativeWindowWrapper(gl),
         TextureView.SurfaceTextureListener {
-        private var mNative: Long = 0
-
         private external fun setSurfaceTexture(
-            gl: NativeGL, self: NativeSurfaceTextureWrapper, surface: SurfaceTexture
-        )
-
-        private external fun removeSurfaceTexture(self: NativeSurfaceTextureWrapper)
-        private external fun renderToSurfaceTexture(gl: NativeGL, self: NativeSurfaceTextureWrapper)
+            gl: Long, surface: SurfaceTexture, backend: Int
+        ): Long
 
         override fun onSurfaceTextureAvailable(
             surfaceTexture: SurfaceTexture, p1: Int, p2: Int
         ) {
             println("Rust TextureView created: $surfaceTexture")
-            assert(mNative == 0L)
-            setSurfaceTexture(gl, this, surfaceTexture)
-            assert(mNative != 0L)
+            attach(setSurfaceTexture(gl.mNative, surfaceTexture, backend.ordinal))
             // No "changed" callback that always fires, so we have to draw immediately
-            renderToSurfaceTexture(gl, this)
+            redraw()
         }
 
         override fun onSurfaceTextureSizeChanged(
             surfaceTexture: SurfaceTexture, p1: Int, p2: Int
         ) {
             println("Rust TextureView resized: $surfaceTexture")
-            assert(mNative != 0L)
-            renderToSurfaceTexture(gl, this)
+            redraw()
         }
 
         override fun onSurfaceTextureDestroyed(surfaceTexture: SurfaceTexture): Boolean {
             println("Rust TextureView destroyed: $surfaceTexture")
-            assert(mNative != 0L)
-            removeSurfaceTexture(this)
-            assert(mNative == 0L)
+            detach()
             return true
         }
 
@@ -151,23 +534,58 @@ class MainActivity : Activity() {
         }
     }
 
+    private lateinit var gl: NativeGL
+    private lateinit var windowWrappers: List<NativeWindowWrapper>
+
     override fun onCreate(savedInstanceState: Bundle?) {
         super.onCreate(savedInstanceState)
         setContentView(R.layout.activity_main)
 
-        val gl = NativeGL()
+        initAssets(assets)
+
+        val debuggable = applicationInfo.flags and ApplicationInfo.FLAG_DEBUGGABLE != 0
+        gl = NativeGL(debug = debuggable)
+        if (debuggable) {
+            gl.verifySrgb()
+        }
 
         val surfaceView: SurfaceView = findViewById(R.id.surface_view)
         println("SurfaceView: ${surfaceView.holder.surface}")
-        surfaceView.holder.addCallback(SurfaceHolderWrapper(gl))
+        val surfaceHolderWrapper = SurfaceHolderWrapper(gl)
+        surfaceView.holder.addCallback(surfaceHolderWrapper)
+        surfaceView.setOnClickListener {
+            surfaceHolderWrapper.hudEnabled = !surfaceHolderWrapper.hudEnabled
+            surfaceHolderWrapper.redraw()
+        }
 
         val javaTextureView: TextureView = findViewById(R.id.java_texture_view)
         println("Java TextureView: ${javaTextureView.surfaceTexture}")
         // TODO: This breaks because the Surface() (producer-end) changes for the given SurfaceTexture() (consumer-end)
-        javaTextureView.surfaceTextureListener = SurfaceTextureWrapper(gl)
+        val surfaceTextureWrapper = SurfaceTextureWrapper(gl)
+        javaTextureView.surfaceTextureListener = surfaceTextureWrapper
+        javaTextureView.setOnClickListener {
+            surfaceTextureWrapper.hudEnabled = !surfaceTextureWrapper.hudEnabled
+            surfaceTextureWrapper.redraw()
+        }
 
         val rustTextureView: TextureView = findViewById(R.id.rust_texture_view)
         println("Rust TextureView: ${rustTextureView.surfaceTexture}")
-        rustTextureView.surfaceTextureListener = NativeSurfaceTextureWrapper(gl)
+        val nativeSurfaceTextureWrapper = NativeSurfaceTextureWrapper(gl)
+        rustTextureView.surfaceTextureListener = nativeSurfaceTextureWrapper
+        rustTextureView.setOnClickListener {
+            nativeSurfaceTextureWrapper.hudEnabled = !nativeSurfaceTextureWrapper.hudEnabled
+            nativeSurfaceTextureWrapper.redraw()
+        }
+
+        windowWrappers =
+            listOf(surfaceHolderWrapper, surfaceTextureWrapper, nativeSurfaceTextureWrapper)
+    }
+
+    override fun onDestroy() {
+        // TextureViews only destroy their SurfaceTexture when they are detached from the window,
+        // after this
+        windowWrappers.forEach { it.detach() }
+        gl.destroy()
+        super.onDestroy()
     }
 }